@@ -0,0 +1,328 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Debug socket of the shim, to introspect a running shim without attaching a debugger.
+//!
+//! Each connection sends a single command line and gets a single JSON line back:
+//!
+//! - `containers`: the containers of the shim with their init process
+//! - `processes`: every process of every container
+//! - `fds`: the open file descriptors of the shim
+//! - `cleanup <id>`: kill all the processes of a container, delete it and forget it
+//!
+//! e.g. `echo containers | socat - UNIX-CONNECT:<bundle>/debug.sock`
+
+use std::{
+    collections::HashMap,
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use containerd_shim::{
+    asynchronous::{container::Container, ExitSignal},
+    event::Event,
+    io_error,
+    protos::{events::task::TaskDelete, protobuf::MessageDyn},
+    util::convert_to_timestamp,
+    Error, Result,
+};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc::Sender, Mutex},
+};
+
+use crate::asynchronous::runc::RuncContainer;
+
+/// Socket in the bundle of the shim, which is its working directory.
+pub const DEBUG_SOCKET: &str = "debug.sock";
+
+/// How long a cleanup waits for the init process to exit once killed.
+const CLEANUP_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Containers = Arc<Mutex<HashMap<String, RuncContainer>>>;
+type EventSender = Sender<(String, Box<dyn MessageDyn>)>;
+
+/// Serve the debug socket until the shim exits.
+pub fn serve(containers: Containers, tx: EventSender, exit: Arc<ExitSignal>) -> Result<()> {
+    let path = Path::new(DEBUG_SOCKET);
+    // a socket left by a previous shim of the bundle
+    if path.exists() {
+        fs::remove_file(path).map_err(io_error!(e, "remove stale debug socket"))?;
+    }
+    // no connection is accepted before the socket is made 0600, so that only its owner
+    // can connect, the umask is left alone as it is shared with the other runtime threads
+    let listener = UnixListener::bind(path).map_err(io_error!(e, "bind debug socket"))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+        .map_err(io_error!(e, "set permissions of debug socket"))?;
+    info!("debug socket listening on {}", DEBUG_SOCKET);
+
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("failed to accept debug connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = exit.wait() => break,
+            };
+            let (containers, tx) = (containers.clone(), tx.clone());
+            tokio::spawn(async move {
+                if let Err(e) = handle(stream, containers, tx).await {
+                    debug!("debug connection failed: {}", e);
+                }
+            });
+        }
+        fs::remove_file(DEBUG_SOCKET).unwrap_or_else(|e| warn!("remove debug socket: {}", e));
+    });
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Containers,
+    Processes,
+    Fds,
+    Cleanup(String),
+}
+
+fn parse_command(line: &str) -> Result<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["containers"] => Ok(Command::Containers),
+        ["processes"] => Ok(Command::Processes),
+        ["fds"] => Ok(Command::Fds),
+        ["cleanup", id] => Ok(Command::Cleanup(id.to_string())),
+        _ => Err(Error::InvalidArgument(format!(
+            "unknown debug command {:?}",
+            line.trim()
+        ))),
+    }
+}
+
+async fn handle(
+    stream: UnixStream,
+    containers: Containers,
+    tx: EventSender,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    let res = match parse_command(&line) {
+        Ok(Command::Containers) => Ok(list_containers(&containers).await),
+        Ok(Command::Processes) => Ok(list_processes(&containers).await),
+        Ok(Command::Fds) => list_fds(),
+        Ok(Command::Cleanup(id)) => cleanup(&containers, &tx, &id).await,
+        Err(e) => Err(e),
+    };
+    let reply = match res {
+        Ok(value) => value,
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+    writer.write_all(format!("{}\n", reply).as_bytes()).await?;
+    writer.shutdown().await
+}
+
+async fn list_containers(containers: &Containers) -> Value {
+    let containers = containers.lock().await;
+    containers
+        .values()
+        .map(|c| {
+            json!({
+                "id": c.id,
+                "bundle": c.bundle,
+                "pid": c.init.pid,
+                "status": format!("{:?}", c.init.state),
+                "group": c.group,
+                "execs": c.processes.len(),
+            })
+        })
+        .collect()
+}
+
+async fn list_processes(containers: &Containers) -> Value {
+    let containers = containers.lock().await;
+    let mut processes = vec![];
+    for c in containers.values() {
+        processes.push(json!({
+            "container": c.id,
+            "exec_id": null,
+            "pid": c.init.pid,
+            "status": format!("{:?}", c.init.state),
+            "exit_code": c.init.exit_code,
+        }));
+        for (exec_id, p) in c.processes.iter() {
+            processes.push(json!({
+                "container": c.id,
+                "exec_id": exec_id,
+                "pid": p.pid,
+                "status": format!("{:?}", p.state),
+                "exit_code": p.exit_code,
+            }));
+        }
+    }
+    Value::Array(processes)
+}
+
+fn list_fds() -> Result<Value> {
+    let entries = fs::read_dir("/proc/self/fd").map_err(io_error!(e, "read fd table"))?;
+    let mut fds = vec![];
+    for entry in entries.flatten() {
+        let fd: i32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(fd) => fd,
+            Err(_) => continue,
+        };
+        // fds closed meanwhile are skipped
+        if let Ok(target) = fs::read_link(entry.path()) {
+            fds.push((fd, target.display().to_string()));
+        }
+    }
+    fds.sort();
+    Ok(fds
+        .into_iter()
+        .map(|(fd, target)| json!({ "fd": fd, "target": target }))
+        .collect())
+}
+
+/// Kill the processes of a container, wait for its init to exit and delete it.
+///
+/// The exit of the init process is published as TaskExit by the exit monitor of the shim,
+/// which needs the containers, so they are not held while waiting for it.
+async fn cleanup(containers: &Containers, tx: &EventSender, id: &str) -> Result<Value> {
+    warn!(
+        "force cleanup of container {} requested on debug socket",
+        id
+    );
+    let exited = {
+        let mut containers = containers.lock().await;
+        let container = get_container(&mut containers, id)?;
+        if container.try_wait(None).await?.is_some() {
+            None
+        } else {
+            container
+                .kill(None, 9, true)
+                .await
+                .unwrap_or_else(|e| debug!("kill container {} for cleanup: {}", id, e));
+            Some(container.wait_channel(None).await?)
+        }
+    };
+    if let Some(exited) = exited {
+        tokio::time::timeout(CLEANUP_EXIT_TIMEOUT, exited)
+            .await
+            .map_err(|_| Error::Other(format!("container {} did not exit once killed", id)))?
+            .unwrap_or_default();
+    }
+
+    let mut containers = containers.lock().await;
+    let container = get_container(&mut containers, id)?;
+    let (pid, exit_code, exited_at) = container.delete(None).await?;
+    containers.remove(id);
+    let event = TaskDelete {
+        container_id: id.to_string(),
+        pid: pid as u32,
+        exit_status: exit_code as u32,
+        exited_at: Some(convert_to_timestamp(exited_at)).into(),
+        ..Default::default()
+    };
+    let topic = event.topic();
+    tx.send((topic.to_string(), Box::new(event)))
+        .await
+        .unwrap_or_else(|e| warn!("send {} to publisher: {}", topic, e));
+    Ok(json!({ "id": id, "pid": pid, "exit_code": exit_code }))
+}
+
+fn get_container<'a>(
+    containers: &'a mut HashMap<String, RuncContainer>,
+    id: &str,
+) -> Result<&'a mut RuncContainer> {
+    containers
+        .get_mut(id)
+        .ok_or_else(|| Error::NotFoundError(format!("can not find container by id {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::AsRawFd;
+
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("containers\n").unwrap(), Command::Containers);
+        assert_eq!(parse_command(" processes ").unwrap(), Command::Processes);
+        assert_eq!(parse_command("fds").unwrap(), Command::Fds);
+        assert_eq!(
+            parse_command("cleanup abc\n").unwrap(),
+            Command::Cleanup("abc".to_string())
+        );
+        assert!(parse_command("").is_err());
+        assert!(parse_command("cleanup").is_err());
+        assert!(parse_command("cleanup a b").is_err());
+        assert!(parse_command("containers all").is_err());
+        assert!(parse_command("unknown").is_err());
+    }
+
+    #[test]
+    fn test_list_fds() {
+        let file = tempfile::tempfile().unwrap();
+        let fds = list_fds().unwrap();
+        let fds = fds.as_array().unwrap();
+        assert!(fds
+            .iter()
+            .any(|fd| fd["fd"] == file.as_raw_fd() && fd["target"].is_string()));
+    }
+
+    async fn request(containers: &Containers, line: &str) -> Value {
+        let (tx, _rx) = channel(1);
+        let (client, server) = UnixStream::pair().unwrap();
+        let handler = tokio::spawn(handle(server, containers.clone(), tx));
+        let (reader, mut writer) = client.into_split();
+        writer.write_all(line.as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        BufReader::new(reader).read_line(&mut reply).await.unwrap();
+        handler.await.unwrap().unwrap();
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle() {
+        let containers = Containers::default();
+        assert_eq!(request(&containers, "containers\n").await, json!([]));
+        assert_eq!(request(&containers, "processes\n").await, json!([]));
+        assert!(request(&containers, "fds\n").await.is_array());
+
+        let reply = request(&containers, "cleanup abc\n").await;
+        assert!(reply["error"]
+            .as_str()
+            .unwrap()
+            .contains("can not find container by id abc"));
+        let reply = request(&containers, "bogus\n").await;
+        assert!(reply["error"]
+            .as_str()
+            .unwrap()
+            .contains("unknown debug command"));
+    }
+}
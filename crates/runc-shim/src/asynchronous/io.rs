@@ -0,0 +1,212 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::{
+    future::Future,
+    os::unix::io::RawFd,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[cfg(feature = "splice")]
+use containerd_shim::io_error;
+use containerd_shim::{other, Error, ExitSignal, Result};
+use log::{debug, warn};
+use runc::io::Io;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc::UnboundedReceiver,
+    task::JoinHandle,
+};
+
+#[cfg(feature = "splice")]
+use crate::asynchronous::splice::Splice;
+
+/// Read side of a process output.
+pub enum Output {
+    /// A pipe, copied with splice(2).
+    #[cfg(feature = "splice")]
+    Pipe(RawFd),
+    Reader(Box<dyn AsyncRead + Send + Sync + Unpin>),
+}
+
+impl Output {
+    pub fn stdout(io: &dyn Io) -> Option<Self> {
+        Self::new(io.stdout_fd(), || io.stdout())
+    }
+
+    pub fn stderr(io: &dyn Io) -> Option<Self> {
+        Self::new(io.stderr_fd(), || io.stderr())
+    }
+
+    #[cfg_attr(not(feature = "splice"), allow(unused_variables))]
+    fn new<R>(fd: Option<RawFd>, reader: R) -> Option<Self>
+    where
+        R: FnOnce() -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>>,
+    {
+        #[cfg(feature = "splice")]
+        if let Some(fd) = fd {
+            return Some(Output::Pipe(fd));
+        }
+        reader().map(Output::Reader)
+    }
+}
+
+/// Owns the io copy tasks of a process.
+///
+/// Output copies are awaited on shutdown so that nothing written by the process
+/// before it exited is lost, the stdin copy and the outputs still running after the
+/// timeout are stopped by the exit signal.
+#[derive(Default)]
+pub struct IoCopier {
+    exit_signal: Arc<ExitSignal>,
+    outputs: Mutex<Vec<JoinHandle<std::io::Result<()>>>>,
+    held: Mutex<Vec<Arc<dyn Io>>>,
+}
+
+impl IoCopier {
+    /// Copy the stdin fifo to the process, stopped on shutdown.
+    ///
+    /// Each time the fifo reaches EOF, the copy goes on with the next fifo received from
+    /// `reopened`, and ends when it gets none, so writes resume on a reopened stdin
+    /// without what is left in the previous fifo being lost.
+    pub fn copy_stdin<W, F>(
+        &self,
+        from: File,
+        to: W,
+        mut reopened: UnboundedReceiver<File>,
+        on_close: Option<F>,
+    ) where
+        W: AsyncWrite + Send + Unpin + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let mut src = from;
+        let mut dst = to;
+        let copy = async move {
+            loop {
+                tokio::io::copy(&mut src, &mut dst).await?;
+                match reopened.recv().await {
+                    Some(next) => {
+                        debug!("stdin reopened, resume copy");
+                        src = next;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        };
+        self.spawn_task(copy, on_close);
+    }
+
+    /// Copy from the process to the client, awaited on shutdown.
+    pub fn copy_output<R, W, F>(&self, from: R, to: W, on_close: Option<F>)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let task = self.spawn(from, to, on_close);
+        self.outputs.lock().unwrap().push(task);
+    }
+
+    /// Keep a runc io alive until shutdown, e.g. a binary io whose logging binary
+    /// must outlive the process.
+    pub fn hold(&self, io: Arc<dyn Io>) {
+        self.held.lock().unwrap().push(io);
+    }
+
+    /// Copy a process output to the client, awaited on shutdown.
+    ///
+    /// With the `splice` feature, an output pipe is spliced to the client,
+    /// without copying the data through userspace.
+    pub async fn copy_process_output<F>(
+        &self,
+        from: Output,
+        to: File,
+        on_close: Option<F>,
+    ) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match from {
+            #[cfg(feature = "splice")]
+            Output::Pipe(fd) => {
+                let splice = Splice::new(fd, to.into_std().await)
+                    .map_err(io_error!(e, "prepare splice of output"))?;
+                let task = self.spawn_task(splice.copy(), on_close);
+                self.outputs.lock().unwrap().push(task);
+            }
+            Output::Reader(r) => self.copy_output(r, to, on_close),
+        }
+        Ok(())
+    }
+
+    fn spawn<R, W, F>(&self, from: R, to: W, on_close: Option<F>) -> JoinHandle<std::io::Result<()>>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let mut src = from;
+        let mut dst = to;
+        let copy = async move { tokio::io::copy(&mut src, &mut dst).await.map(|_| ()) };
+        self.spawn_task(copy, on_close)
+    }
+
+    fn spawn_task<C, F>(&self, copy: C, on_close: Option<F>) -> JoinHandle<std::io::Result<()>>
+    where
+        C: Future<Output = std::io::Result<()>> + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let exit_signal = self.exit_signal.clone();
+        tokio::spawn(async move {
+            let res = tokio::select! {
+                _ = exit_signal.wait() => {
+                    debug!("container exit, copy task should exit too");
+                    Ok(())
+                },
+                res = copy => res,
+            };
+            if let Some(f) = on_close {
+                f();
+            }
+            res
+        })
+    }
+
+    /// Wait for the output copies to finish for at most `timeout`, then stop all the copies.
+    ///
+    /// Returns the first error met by an output copy.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        let outputs = std::mem::take(&mut *self.outputs.lock().unwrap());
+        let res = tokio::time::timeout(timeout, futures::future::join_all(outputs)).await;
+        self.exit_signal.signal();
+        self.held.lock().unwrap().clear();
+        match res {
+            Ok(results) => {
+                for r in results {
+                    r.map_err(|e| other!("io copy task failed: {}", e))?
+                        .map_err(|e| other!("io copy failed: {}", e))?;
+                }
+                Ok(())
+            }
+            Err(_) => {
+                warn!("io copy did not finish in {:?}, stopping it", timeout);
+                Ok(())
+            }
+        }
+    }
+}
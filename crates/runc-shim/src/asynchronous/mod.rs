@@ -19,8 +19,9 @@ use std::{env::current_dir, sync::Arc};
 use ::runc::options::DeleteOpts;
 use async_trait::async_trait;
 use containerd_shim::{
+    api::Options,
     asynchronous::{
-        container::Container,
+        container::{Container, HookedFactory},
         monitor::{monitor_subscribe, monitor_unsubscribe, Subscription},
         processes::Process,
         publisher::RemotePublisher,
@@ -29,23 +30,28 @@ use containerd_shim::{
         ExitSignal, Shim,
     },
     event::Event,
-    io_error,
     monitor::{Subject, Topic},
     protos::{events::task::TaskExit, protobuf::MessageDyn},
-    util::{
-        convert_to_timestamp, read_options, read_runtime, read_spec, timestamp, write_str_to_file,
-    },
-    Config, Context, DeleteResponse, Error, StartOpts,
+    util::{convert_to_timestamp, read_options, read_runtime, read_spec, write_str_to_file},
+    Config, Context, DeleteResponse, StartOpts,
 };
 use log::{debug, error, warn};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 use crate::{
     asynchronous::runc::{RuncContainer, RuncFactory},
-    common::{create_runc, has_shared_pid_namespace, ShimExecutor, GROUP_LABELS},
+    common::{
+        create_runc, has_shared_pid_namespace, killed_delete_response, unmount_rootfs_async,
+        RuncRoot, ShimConfig, ShimExecutor, GROUP_LABELS,
+    },
 };
 
+#[cfg(feature = "debug-socket")]
+mod debug;
+mod io;
 mod runc;
+#[cfg(feature = "splice")]
+mod splice;
 
 pub(crate) struct Service {
     exit: Arc<ExitSignal>,
@@ -55,9 +61,10 @@ pub(crate) struct Service {
 
 #[async_trait]
 impl Shim for Service {
-    type T = TaskService<RuncFactory, RuncContainer>;
+    type T = TaskService<HookedFactory<RuncFactory>, RuncContainer>;
 
-    async fn new(_runtime_id: &str, id: &str, namespace: &str, _config: &mut Config) -> Self {
+    async fn new(_runtime_id: &str, id: &str, namespace: &str, config: &mut Config) -> Self {
+        config.debug |= ShimConfig::from_env().debug;
         let exit = Arc::new(ExitSignal::default());
         // TODO: add publisher
         Service {
@@ -88,31 +95,48 @@ impl Shim for Service {
     }
 
     async fn delete_shim(&mut self) -> containerd_shim::Result<DeleteResponse> {
-        let namespace = self.namespace.as_str();
-        let bundle = current_dir().map_err(io_error!(e, "get current dir"))?;
-        let opts = read_options(&bundle).await?;
-        let runtime = read_runtime(&bundle).await?;
-
-        let runc = create_runc(
-            &runtime,
-            namespace,
-            &bundle,
-            &opts,
-            Some(Arc::new(ShimExecutor::default())),
-        )?;
-
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
-            .await
-            .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
-        let mut resp = DeleteResponse::new();
-        // sigkill
-        resp.set_exit_status(137);
-        resp.set_exited_at(timestamp()?);
-        Ok(resp)
+        // containerd also deletes the shims of failed creates, whose bundle may hold no state,
+        // so the cleanup is best effort and the container is always reported as killed
+        match current_dir() {
+            Ok(bundle) => {
+                let opts = read_options(&bundle).await.unwrap_or_else(|e| {
+                    debug!("no options in bundle: {}", e);
+                    Options::new()
+                });
+                let runtime = read_runtime(&bundle).await.unwrap_or_default();
+                // a forced delete kills what is left of the container, and only it
+                match create_runc(
+                    &runtime,
+                    &self.namespace,
+                    &bundle,
+                    &opts,
+                    Some(Arc::new(ShimExecutor::default())),
+                ) {
+                    Ok(runc) => runc
+                        .delete(&self.id, Some(&DeleteOpts::new().force(true)))
+                        .await
+                        .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e)),
+                    Err(e) => warn!("failed to create runc client: {}", e),
+                }
+                unmount_rootfs_async(bundle.join("rootfs"))
+                    .await
+                    .unwrap_or_else(|e| warn!("failed to unmount rootfs: {}", e));
+            }
+            Err(e) => warn!("failed to get the bundle of the shim: {}", e),
+        }
+        Ok(killed_delete_response())
     }
 
     async fn wait(&mut self) {
         self.exit.wait().await;
+        // the shim runs in the bundle, where the options of the container are kept
+        if let Ok(bundle) = current_dir() {
+            if let Ok(opts) = read_options(&bundle).await {
+                RuncRoot::new(opts.root.as_str(), &self.namespace)
+                    .remove_if_empty()
+                    .unwrap_or_else(|e| warn!("failed to remove runc root: {}", e));
+            }
+        }
     }
 
     async fn create_task_service(&self, publisher: RemotePublisher) -> Self::T {
@@ -124,13 +148,18 @@ impl Shim for Service {
             .expect("monitor subscribe failed");
         process_exits(s, &task, tx).await;
         forward(publisher, self.namespace.to_string(), rx).await;
+        #[cfg(feature = "debug-socket")]
+        if ShimConfig::from_env().debug_socket {
+            debug::serve(task.containers.clone(), task.tx.clone(), self.exit.clone())
+                .unwrap_or_else(|e| warn!("failed to serve debug socket: {}", e));
+        }
         task
     }
 }
 
 async fn process_exits(
     s: Subscription,
-    task: &TaskService<RuncFactory, RuncContainer>,
+    task: &TaskService<HookedFactory<RuncFactory>, RuncContainer>,
     tx: Sender<(String, Box<dyn MessageDyn>)>,
 ) {
     let containers = task.containers.clone();
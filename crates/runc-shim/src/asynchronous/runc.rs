@@ -16,50 +16,53 @@
 
 use std::{
     convert::TryFrom,
-    os::unix::{
-        io::{AsRawFd, FromRawFd, RawFd},
-        prelude::ExitStatusExt,
-    },
+    os::unix::{io::FromRawFd, prelude::ExitStatusExt},
     path::{Path, PathBuf},
     process::ExitStatus,
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use containerd_shim::{
-    api::{CreateTaskRequest, ExecProcessRequest, Options, Status},
+    api::{CheckpointOptions, CreateTaskRequest, ExecProcessRequest, Options, Status},
     asynchronous::{
         console::ConsoleSocket,
         container::{ContainerFactory, ContainerTemplate, ProcessFactory},
         monitor::{monitor_subscribe, monitor_unsubscribe, Subscription},
-        processes::{ProcessLifecycle, ProcessTemplate},
+        processes::{open_stdin_fifo, ProcessLifecycle, ProcessTemplate},
     },
     io::Stdio,
     io_error,
     monitor::{ExitEvent, Subject, Topic},
     other, other_error,
-    protos::{
-        api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
-    },
-    util::{asyncify, mkdir, mount_rootfs, read_file_to_str, write_options, write_runtime},
-    Console, Error, ExitSignal, Result,
+    protos::{api::ProcessInfo, cgroups::metrics::Metrics, protobuf::Message},
+    pty::MasterReader,
+    util::{mkdir, mount_rootfs, read_file_to_str, read_spec, write_options, write_runtime},
+    Console, Error, Result,
 };
 use log::{debug, error};
-use nix::{sys::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process};
 use runc::{Command, Runc, Spawner};
 use tokio::{
     fs::{File, OpenOptions},
-    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
 };
 
-use crate::common::{
-    check_kill_error, create_io, create_runc, get_spec_from_request, receive_socket, CreateConfig,
-    ProcessIO, ShimExecutor, INIT_PID_FILE,
+use crate::{
+    asynchronous::io::{IoCopier, Output},
+    common::{
+        check_container_id, check_kill_error, checkpoint_opts, create_io_async, create_runc,
+        detect_systemd_cgroup, get_spec_from_request, kill_pid, parse_create_options,
+        receive_socket_async, unmount_rootfs_async, CreateConfig, ProcessIO, RuncRoot, ShimConfig,
+        ShimExecutor, GROUP_LABELS, INIT_PID_FILE,
+    },
 };
 
+/// How long to wait for the output of a process to be copied when it is deleted
+const IO_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type ExecProcess = ProcessTemplate<RuncExecLifecycle>;
 pub type InitProcess = ProcessTemplate<RuncInitLifecycle>;
 
@@ -75,15 +78,23 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         ns: &str,
         req: &CreateTaskRequest,
     ) -> containerd_shim::Result<RuncContainer> {
+        check_container_id(req.id())?;
         let bundle = req.bundle();
         let mut opts = Options::new();
         if let Some(any) = req.options.as_ref() {
-            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
-            opts.merge_from(&mut input)?;
+            opts = parse_create_options(any)?;
         }
+        ShimConfig::from_env().apply(&mut opts);
+        detect_systemd_cgroup(&mut opts, bundle);
         if opts.compute_size() > 0 {
             debug!("create options: {:?}", &opts);
         }
+        // read before the create, as a failure afterwards would leave the runc container behind
+        let annotations = read_spec(bundle)
+            .await?
+            .annotations()
+            .clone()
+            .unwrap_or_default();
         let runtime = opts.binary_name.as_str();
         write_options(bundle, &opts).await?;
         write_runtime(bundle, runtime).await?;
@@ -101,6 +112,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
             mount_rootfs(&m, rootfs.as_path()).await?
         }
 
+        RuncRoot::new(opts.root.as_str(), ns).create()?;
         let runc = create_runc(
             runtime,
             ns,
@@ -115,11 +127,15 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         let mut init = InitProcess::new(
             id,
             stdio,
-            RuncInitLifecycle::new(runc.clone(), opts.clone(), bundle),
+            RuncInitLifecycle::new(runc.clone(), opts.clone(), ns, bundle, rootfs),
         );
 
         let config = CreateConfig::default();
         self.do_create(&mut init, config).await?;
+        let group = GROUP_LABELS
+            .iter()
+            .find_map(|label| annotations.get(*label))
+            .cloned();
         let container = RuncContainer {
             id: id.to_string(),
             bundle: bundle.to_string(),
@@ -127,10 +143,14 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
             process_factory: RuncExecFactory {
                 runtime: runc,
                 bundle: bundle.to_string(),
+                namespace: ns.to_string(),
                 io_uid: opts.io_uid,
                 io_gid: opts.io_gid,
             },
             processes: Default::default(),
+            deleted_processes: Default::default(),
+            annotations,
+            group,
         };
         Ok(container)
     }
@@ -157,7 +177,14 @@ impl RuncFactory {
             create_opts.console_socket = Some(s.path.to_owned());
             (Some(s), None)
         } else {
-            let pio = create_io(&id, opts.io_uid, opts.io_gid, stdio)?;
+            let pio = create_io_async(
+                &id,
+                &init.lifecycle.namespace,
+                opts.io_uid,
+                opts.io_gid,
+                stdio,
+            )
+            .await?;
             create_opts.io = pio.io.as_ref().cloned();
             (None, Some(pio))
         };
@@ -173,7 +200,7 @@ impl RuncFactory {
             }
             return Err(other!("failed to create runc container: {}", e));
         }
-        copy_io_or_console(init, socket, pio, init.lifecycle.exit_signal.clone()).await?;
+        copy_io_or_console(init, socket, pio, init.lifecycle.io.clone()).await?;
         let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
         init.pid = pid;
         Ok(())
@@ -183,6 +210,7 @@ impl RuncFactory {
 pub struct RuncExecFactory {
     runtime: Runc,
     bundle: String,
+    namespace: String,
     io_uid: u32,
     io_gid: u32,
 }
@@ -209,10 +237,11 @@ impl ProcessFactory<ExecProcess> for RuncExecFactory {
                 runtime: self.runtime.clone(),
                 bundle: self.bundle.to_string(),
                 container_id: req.id.to_string(),
+                namespace: self.namespace.to_string(),
                 io_uid: self.io_uid,
                 io_gid: self.io_gid,
                 spec: p,
-                exit_signal: Default::default(),
+                io: Default::default(),
             }),
         })
     }
@@ -221,8 +250,11 @@ impl ProcessFactory<ExecProcess> for RuncExecFactory {
 pub struct RuncInitLifecycle {
     runtime: Runc,
     opts: Options,
+    namespace: String,
     bundle: String,
-    exit_signal: Arc<ExitSignal>,
+    /// rootfs mounted by the shim, empty if the rootfs was not mounted by us
+    rootfs: PathBuf,
+    io: Arc<IoCopier>,
 }
 
 #[async_trait]
@@ -256,18 +288,18 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
         self.runtime
             .delete(
                 p.id.as_str(),
-                Some(&runc::options::DeleteOpts { force: true }),
+                Some(&runc::options::DeleteOpts::new().force(true)),
             )
             .await
-            .or_else(|e| {
-                if !e.to_string().to_lowercase().contains("does not exist") {
-                    Err(e)
-                } else {
-                    Ok(())
-                }
+            .or_else(|e| match e {
+                runc::error::Error::ContainerNotFound { .. } => Ok(()),
+                e => Err(e),
             })
             .map_err(other_error!(e, "failed delete"))?;
-        self.exit_signal.signal();
+        unmount_rootfs_async(&self.rootfs).await?;
+        if let Err(e) = self.io.shutdown(IO_WAIT_TIMEOUT).await {
+            error!("failed to copy io of {}: {}", p.id, e);
+        }
         Ok(())
     }
 
@@ -317,20 +349,70 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             })
             .collect())
     }
+
+    async fn pause(&self, p: &mut InitProcess) -> Result<()> {
+        match p.state {
+            Status::RUNNING => {
+                self.runtime
+                    .pause(p.id.as_str())
+                    .await
+                    .map_err(other_error!(e, "failed to pause"))?;
+                p.state = Status::PAUSED;
+                Ok(())
+            }
+            s => Err(Error::FailedPreconditionError(format!(
+                "cannot pause container in {:?} state",
+                s
+            ))),
+        }
+    }
+
+    async fn resume(&self, p: &mut InitProcess) -> Result<()> {
+        match p.state {
+            Status::PAUSED => {
+                self.runtime
+                    .resume(p.id.as_str())
+                    .await
+                    .map_err(other_error!(e, "failed to resume"))?;
+                p.state = Status::RUNNING;
+                Ok(())
+            }
+            s => Err(Error::FailedPreconditionError(format!(
+                "cannot resume container in {:?} state",
+                s
+            ))),
+        }
+    }
+
+    async fn checkpoint(
+        &self,
+        p: &mut InitProcess,
+        path: &str,
+        opts: &CheckpointOptions,
+    ) -> Result<()> {
+        let copts = checkpoint_opts(path, self.opts.criu_work_path(), opts);
+        self.runtime
+            .checkpoint(p.id.as_str(), Some(&copts))
+            .await
+            .map_err(other_error!(e, "failed to checkpoint"))?;
+        Ok(())
+    }
 }
 
 impl RuncInitLifecycle {
-    pub fn new(runtime: Runc, opts: Options, bundle: &str) -> Self {
+    pub fn new(runtime: Runc, opts: Options, ns: &str, bundle: &str, rootfs: PathBuf) -> Self {
         let work_dir = Path::new(bundle).join("work");
         let mut opts = opts;
-        if opts.criu_path().is_empty() {
-            opts.criu_path = work_dir.to_string_lossy().to_string();
+        if opts.criu_work_path().is_empty() {
+            opts.criu_work_path = work_dir.to_string_lossy().to_string();
         }
         Self {
             runtime,
             opts,
+            namespace: ns.to_string(),
             bundle: bundle.to_string(),
-            exit_signal: Default::default(),
+            rootfs,
+            io: Default::default(),
         }
     }
 }
@@ -339,10 +421,11 @@ pub struct RuncExecLifecycle {
     runtime: Runc,
     bundle: String,
     container_id: String,
+    namespace: String,
     io_uid: u32,
     io_gid: u32,
     spec: Process,
-    exit_signal: Arc<ExitSignal>,
+    io: Arc<IoCopier>,
 }
 
 #[async_trait]
@@ -354,13 +437,15 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             pid_file: Some(pid_path.to_owned()),
             console_socket: None,
             detach: true,
+            ..Default::default()
         };
         let (socket, pio) = if p.stdio.terminal {
             let s = ConsoleSocket::new().await?;
             exec_opts.console_socket = Some(s.path.to_owned());
             (Some(s), None)
         } else {
-            let pio = create_io(&p.id, self.io_uid, self.io_gid, &p.stdio)?;
+            let pio =
+                create_io_async(&p.id, &self.namespace, self.io_uid, self.io_gid, &p.stdio).await?;
             exec_opts.io = pio.io.as_ref().cloned();
             (None, Some(pio))
         };
@@ -375,7 +460,7 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             }
             return Err(other!("failed to start runc exec: {}", e));
         }
-        copy_io_or_console(p, socket, pio, p.lifecycle.exit_signal.clone()).await?;
+        copy_io_or_console(p, socket, pio, p.lifecycle.io.clone()).await?;
         let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
         p.pid = pid;
         p.state = Status::RUNNING;
@@ -395,17 +480,14 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
         } else if p.exited_at.is_some() {
             Err(Error::NotFoundError("process already finished".to_string()))
         } else {
-            // TODO this is kill from nix crate, it is os specific, maybe have annotated with target os
-            kill(
-                Pid::from_raw(p.pid as i32),
-                nix::sys::signal::Signal::try_from(signal as i32).unwrap(),
-            )
-            .map_err(Into::into)
+            kill_pid(p.pid as i32, signal)
         }
     }
 
-    async fn delete(&self, _p: &mut ExecProcess) -> containerd_shim::Result<()> {
-        self.exit_signal.signal();
+    async fn delete(&self, p: &mut ExecProcess) -> containerd_shim::Result<()> {
+        if let Err(e) = self.io.shutdown(IO_WAIT_TIMEOUT).await {
+            error!("failed to copy io of {}: {}", p.id, e);
+        }
         Ok(())
     }
 
@@ -420,16 +502,35 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
     async fn ps(&self, _p: &ExecProcess) -> Result<Vec<ProcessInfo>> {
         Err(Error::Unimplemented("exec ps".to_string()))
     }
+
+    async fn pause(&self, _p: &mut ExecProcess) -> Result<()> {
+        Err(Error::Unimplemented("exec pause".to_string()))
+    }
+
+    async fn resume(&self, _p: &mut ExecProcess) -> Result<()> {
+        Err(Error::Unimplemented("exec resume".to_string()))
+    }
+
+    async fn checkpoint(
+        &self,
+        _p: &mut ExecProcess,
+        _path: &str,
+        _opts: &CheckpointOptions,
+    ) -> Result<()> {
+        Err(Error::Unimplemented("exec checkpoint".to_string()))
+    }
 }
 
 async fn copy_console(
     console_socket: &ConsoleSocket,
     stdio: &Stdio,
-    exit_signal: Arc<ExitSignal>,
+    stdin_holder: Arc<Mutex<Option<File>>>,
+    stdin_reopened: UnboundedReceiver<File>,
+    io: Arc<IoCopier>,
 ) -> Result<Console> {
     debug!("copy_console: waiting for runtime to send console fd");
     let stream = console_socket.accept().await?;
-    let fd = asyncify(move || -> Result<RawFd> { receive_socket(stream.as_raw_fd()) }).await?;
+    let fd = receive_socket_async(stream).await?;
     let f = unsafe { File::from_raw_fd(fd) };
     if !stdio.stdin.is_empty() {
         debug!("copy_console: pipe stdin to console");
@@ -437,26 +538,16 @@ async fn copy_console(
             .try_clone()
             .await
             .map_err(io_error!(e, "failed to clone console file"))?;
-        let stdin_fut = async {
-            OpenOptions::new()
-                .read(true)
-                .open(stdio.stdin.as_str())
-                .await
-        };
-        let stdin_w_fut = async {
-            OpenOptions::new()
-                .write(true)
-                .open(stdio.stdin.as_str())
-                .await
-        };
-        let (stdin, stdin_w) =
-            tokio::try_join!(stdin_fut, stdin_w_fut).map_err(io_error!(e, "open stdin"))?;
-        spawn_copy(
+        let (stdin, stdin_w) = open_stdin_fifo(stdio.stdin.as_str()).await?;
+        // keep the write end open until close_io is requested,
+        // so the copy does not stop when the client side of the fifo is closed
+        *stdin_holder.lock().unwrap() = Some(stdin_w);
+        io.copy_stdin(
             stdin,
             console_stdin,
-            exit_signal.clone(),
+            stdin_reopened,
             Some(move || {
-                drop(stdin_w);
+                stdin_holder.lock().unwrap().take();
             }),
         );
     }
@@ -479,10 +570,11 @@ async fn copy_console(
             .open(stdio.stdout.as_str())
             .await
             .map_err(io_error!(e, "open stdout for read"))?;
-        spawn_copy(
+        let console_stdout =
+            MasterReader::new(console_stdout).on_hangup(|| debug!("copy_console: console hung up"));
+        io.copy_output(
             console_stdout,
             stdout,
-            exit_signal,
             Some(move || {
                 drop(stdout_r);
             }),
@@ -494,7 +586,13 @@ async fn copy_console(
     Ok(console)
 }
 
-pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal>) -> Result<()> {
+pub async fn copy_io(
+    pio: &ProcessIO,
+    stdio: &Stdio,
+    stdin_holder: Arc<Mutex<Option<File>>>,
+    stdin_reopened: UnboundedReceiver<File>,
+    io_copier: Arc<IoCopier>,
+) -> Result<()> {
     if !pio.copy {
         return Ok(());
     };
@@ -502,16 +600,28 @@ pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal
         if let Some(w) = io.stdin() {
             debug!("copy_io: pipe stdin from {}", stdio.stdin.as_str());
             if !stdio.stdin.is_empty() {
-                let stdin = OpenOptions::new()
-                    .read(true)
-                    .open(stdio.stdin.as_str())
-                    .await
-                    .map_err(io_error!(e, "open stdin"))?;
-                spawn_copy(stdin, w, exit_signal.clone(), None::<fn()>);
+                let (stdin, stdin_w) = open_stdin_fifo(stdio.stdin.as_str()).await?;
+                // like the console, the process keeps its stdin while clients come and go
+                *stdin_holder.lock().unwrap() = Some(stdin_w);
+                io_copier.copy_stdin(
+                    stdin,
+                    w,
+                    stdin_reopened,
+                    Some(move || {
+                        stdin_holder.lock().unwrap().take();
+                    }),
+                );
+            }
+        }
+
+        if let Some(log_file) = &pio.log_file {
+            for r in IntoIterator::into_iter([io.stdout(), io.stderr()]).flatten() {
+                io_copier.copy_output(r, log_file.clone(), None::<fn()>);
             }
+            return Ok(());
         }
 
-        if let Some(r) = io.stdout() {
+        if let Some(r) = Output::stdout(io.as_ref()) {
             debug!("copy_io: pipe stdout from to {}", stdio.stdout.as_str());
             if !stdio.stdout.is_empty() {
                 let stdout = OpenOptions::new()
@@ -526,18 +636,19 @@ pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal
                     .open(stdio.stdout.as_str())
                     .await
                     .map_err(io_error!(e, "open stdout for read"))?;
-                spawn_copy(
-                    r,
-                    stdout,
-                    exit_signal.clone(),
-                    Some(move || {
-                        drop(stdout_r);
-                    }),
-                );
+                io_copier
+                    .copy_process_output(
+                        r,
+                        stdout,
+                        Some(move || {
+                            drop(stdout_r);
+                        }),
+                    )
+                    .await?;
             }
         }
 
-        if let Some(r) = io.stderr() {
+        if let Some(r) = Output::stderr(io.as_ref()) {
             if !stdio.stderr.is_empty() {
                 debug!("copy_io: pipe stderr from to {}", stdio.stderr.as_str());
                 let stderr = OpenOptions::new()
@@ -552,14 +663,15 @@ pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal
                     .open(stdio.stderr.as_str())
                     .await
                     .map_err(io_error!(e, "open stderr for read"))?;
-                spawn_copy(
-                    r,
-                    stderr,
-                    exit_signal,
-                    Some(move || {
-                        drop(stderr_r);
-                    }),
-                );
+                io_copier
+                    .copy_process_output(
+                        r,
+                        stderr,
+                        Some(move || {
+                            drop(stderr_r);
+                        }),
+                    )
+                    .await?;
             }
         }
     }
@@ -567,40 +679,24 @@ pub async fn copy_io(pio: &ProcessIO, stdio: &Stdio, exit_signal: Arc<ExitSignal
     Ok(())
 }
 
-fn spawn_copy<R, W, F>(from: R, to: W, exit_signal: Arc<ExitSignal>, on_close: Option<F>)
-where
-    R: AsyncRead + Send + Unpin + 'static,
-    W: AsyncWrite + Send + Unpin + 'static,
-    F: FnOnce() + Send + 'static,
-{
-    let mut src = from;
-    let mut dst = to;
-    tokio::spawn(async move {
-        tokio::select! {
-            _ = exit_signal.wait() => {
-                debug!("container exit, copy task should exit too");
-            },
-            res = tokio::io::copy(&mut src, &mut dst) => {
-               if let Err(e) = res {
-                    error!("copy io failed {}", e);
-                }
-            }
-        }
-        if let Some(f) = on_close {
-            f();
-        }
-    });
-}
-
 async fn copy_io_or_console<P>(
     p: &mut ProcessTemplate<P>,
     socket: Option<ConsoleSocket>,
     pio: Option<ProcessIO>,
-    exit_signal: Arc<ExitSignal>,
+    io: Arc<IoCopier>,
 ) -> Result<()> {
+    let (reopen_tx, stdin_reopened) = unbounded_channel();
+    p.stdin_reopen = Some(reopen_tx);
     if p.stdio.terminal {
         if let Some(console_socket) = socket {
-            let console_result = copy_console(&console_socket, &p.stdio, exit_signal).await;
+            let console_result = copy_console(
+                &console_socket,
+                &p.stdio,
+                p.stdin.clone(),
+                stdin_reopened,
+                io,
+            )
+            .await;
             console_socket.clean().await;
             match console_result {
                 Ok(c) => {
@@ -612,7 +708,10 @@ async fn copy_io_or_console<P>(
             }
         }
     } else if let Some(pio) = pio {
-        copy_io(&pio, &p.stdio, exit_signal).await?;
+        copy_io(&pio, &p.stdio, p.stdin.clone(), stdin_reopened, io.clone()).await?;
+        if let Some(runc_io) = pio.io {
+            io.hold(runc_io);
+        }
     }
     Ok(())
 }
@@ -0,0 +1,102 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Copy of the process outputs with splice(2), the data moves from the pipes of runc
+//! to the fifos of containerd in the kernel instead of through a userspace buffer.
+
+use std::{
+    fs::File,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, splice, FcntlArg, OFlag, SpliceFFlags},
+    poll::{poll, PollFd, PollFlags},
+    unistd::dup,
+};
+use tokio::io::unix::AsyncFd;
+
+/// Bytes moved by a single splice, the default capacity of a pipe.
+const SPLICE_LEN: usize = 64 * 1024;
+
+pub struct Splice {
+    from: AsyncFd<File>,
+    to: AsyncFd<File>,
+}
+
+impl Splice {
+    /// Prepare to splice the pipe `from` to `to`, `from` is duplicated so that the copy
+    /// does not depend on the lifetime of the original fd.
+    pub fn new(from: RawFd, to: File) -> io::Result<Self> {
+        // Safe as the fd is just returned by dup and owned by nothing else
+        let from = unsafe { File::from_raw_fd(dup(from)?) };
+        set_nonblocking(&from)?;
+        set_nonblocking(&to)?;
+        Ok(Self {
+            from: AsyncFd::new(from)?,
+            to: AsyncFd::new(to)?,
+        })
+    }
+
+    /// Move everything from the pipe to the destination until the pipe reaches EOF.
+    pub async fn copy(self) -> io::Result<()> {
+        loop {
+            let mut readable = self.from.readable().await?;
+            let mut writable = self.to.writable().await?;
+            match splice(
+                self.from.as_raw_fd(),
+                None,
+                self.to.as_raw_fd(),
+                None,
+                SPLICE_LEN,
+                SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK,
+            ) {
+                Ok(0) => return Ok(()),
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(Errno::EAGAIN) => {
+                    // EAGAIN does not tell which side would block, only clear the readiness
+                    // of the sides which are really not ready, or their wait may never end
+                    let mut fds = [
+                        PollFd::new(self.from.as_raw_fd(), PollFlags::POLLIN),
+                        PollFd::new(self.to.as_raw_fd(), PollFlags::POLLOUT),
+                    ];
+                    poll(&mut fds, 0)?;
+                    let ready = |fd: &PollFd, flags: PollFlags| {
+                        fd.revents().map_or(false, |r| r.intersects(flags))
+                    };
+                    if !ready(&fds[0], PollFlags::POLLIN | PollFlags::POLLHUP) {
+                        readable.clear_ready();
+                    }
+                    if !ready(&fds[1], PollFlags::POLLOUT | PollFlags::POLLERR) {
+                        writable.clear_ready();
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn set_nonblocking(file: &File) -> io::Result<()> {
+    let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?);
+    fcntl(
+        file.as_raw_fd(),
+        FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+    )?;
+    Ok(())
+}
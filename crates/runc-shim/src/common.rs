@@ -14,29 +14,59 @@
    limitations under the License.
 */
 
-use std::{io::IoSliceMut, ops::Deref, os::unix::io::RawFd, path::Path, sync::Arc};
+#[cfg(feature = "async")]
+use std::os::unix::io::AsRawFd;
+use std::{
+    convert::TryFrom,
+    fs::{self, DirBuilder},
+    io::IoSliceMut,
+    ops::Deref,
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt},
+        io::RawFd,
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use containerd_shim::{
-    api::{ExecProcessRequest, Options},
+    api::{CheckpointOptions, DeleteResponse, ExecProcessRequest, Options},
+    cgroup::is_systemd_cgroups_path,
     io::Stdio,
-    io_error, other, other_error,
-    util::IntoOption,
+    io_error,
+    mount::unmount_all,
+    other, other_error,
+    protos::protobuf::{
+        rt::read_unknown_or_skip_group, well_known_types::any::Any, CodedInputStream, Message,
+        UnknownFields,
+    },
+    util::{convert_to_timestamp, IntoOption},
     Error,
 };
 use log::{debug, warn};
 use nix::{
     cmsg_space,
+    errno::Errno,
+    mount::MntFlags,
     sys::{
+        signal::{kill, Signal},
         socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr},
         termios::tcgetattr,
     },
+    unistd::Pid,
 };
 use oci_spec::runtime::{LinuxNamespaceType, Spec};
 use runc::{
-    io::{Io, NullIo, FIFO},
-    options::GlobalOpts,
+    io::{BinaryIO, IOOption, Io, NullIo, PipedIo, FIFO},
+    options::{CheckpointOpts, GlobalOpts, RetryPolicy},
     Runc, Spawner,
 };
+use time::OffsetDateTime;
+#[cfg(feature = "async")]
+use tokio::io::unix::AsyncFd;
+
+use crate::logfile::{LogFile, LogFileOptions};
 
 pub const GROUP_LABELS: [&str; 2] = [
     "io.containerd.runc.v2.group",
@@ -48,12 +78,28 @@ pub struct ProcessIO {
     pub uri: Option<String>,
     pub io: Option<Arc<dyn Io>>,
     pub copy: bool,
+    /// Destination of the outputs of the `file` scheme, instead of the stdio paths.
+    pub log_file: Option<LogFile>,
+}
+
+/// [create_io] on a blocking thread, as a logging binary is waited for until it is ready.
+#[cfg(feature = "async")]
+pub async fn create_io_async(
+    id: &str,
+    ns: &str,
+    io_uid: u32,
+    io_gid: u32,
+    stdio: &Stdio,
+) -> containerd_shim::Result<ProcessIO> {
+    let (id, ns, stdio) = (id.to_string(), ns.to_string(), stdio.clone());
+    containerd_shim::util::asyncify(move || create_io(&id, &ns, io_uid, io_gid, &stdio)).await
 }
 
 pub fn create_io(
     id: &str,
-    _io_uid: u32,
-    _io_gid: u32,
+    ns: &str,
+    io_uid: u32,
+    io_gid: u32,
     stdio: &Stdio,
 ) -> containerd_shim::Result<ProcessIO> {
     if stdio.is_null() {
@@ -62,6 +108,7 @@ pub fn create_io(
             uri: None,
             io: Some(Arc::new(nio)),
             copy: false,
+            log_file: None,
         };
         return Ok(pio);
     }
@@ -83,6 +130,7 @@ pub fn create_io(
         uri: Some(uri),
         io: None,
         copy: false,
+        log_file: None,
     };
 
     if scheme == "fifo" {
@@ -100,10 +148,102 @@ pub fn create_io(
         };
         pio.io = Some(Arc::new(io));
         pio.copy = false;
+    } else if scheme == "binary" {
+        let (path, args) = parse_binary_uri(stdout)?;
+        debug!(
+            "create binary io for container {}, binary: {}, args: {:?}",
+            id, path, args
+        );
+        let io = BinaryIO::new(&path, &args, id, ns, io_uid, io_gid).map_err(io_error!(
+            e,
+            "start logging binary {}",
+            path
+        ))?;
+        pio.io = Some(Arc::new(io));
+        pio.copy = false;
+    } else if scheme == "file" {
+        let opts = LogFileOptions::parse(stdout)?;
+        debug!(
+            "create file io for container {}, file: {:?}",
+            id,
+            opts.path.as_path()
+        );
+        let log_file = LogFile::open(opts).map_err(io_error!(e, "open log file"))?;
+        let io_opts = IOOption {
+            open_stdin: !stdio.stdin.is_empty(),
+            open_stdout: true,
+            open_stderr: true,
+        };
+        let io = PipedIo::new(io_uid, io_gid, &io_opts).map_err(io_error!(e, "new pipe io"))?;
+        pio.io = Some(Arc::new(io));
+        pio.copy = true;
+        pio.log_file = Some(log_file);
     }
     Ok(pio)
 }
 
+/// Decode the `%XX` escapes of a uri component, `None` if one of them is malformed.
+pub(crate) fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Split a `binary:///path/to/binary?key=value` uri into the binary path and its args,
+/// every query key is passed as an arg, followed by its value if not empty.
+fn parse_binary_uri(uri: &str) -> containerd_shim::Result<(String, Vec<String>)> {
+    let rest = uri
+        .trim()
+        .strip_prefix("binary://")
+        .ok_or_else(|| Error::InvalidArgument(format!("invalid binary uri {}", uri)))?;
+    let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (rest, ""),
+    };
+    if path.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "no binary path in uri {}",
+            uri
+        )));
+    }
+    // a '+' is a space in a query only, as for url.ParseQuery of Go
+    let decode = |s: &str, query: bool| {
+        let s = if query {
+            s.replace('+', " ")
+        } else {
+            s.to_string()
+        };
+        percent_decode(&s)
+            .and_then(|s| String::from_utf8(s).ok())
+            .ok_or_else(|| Error::InvalidArgument(format!("invalid escape in uri {}", uri)))
+    };
+    let mut args = vec![];
+    for pair in query.split('&').filter(|x| !x.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        args.push(decode(key, true)?);
+        let value = decode(value, true)?;
+        if !value.is_empty() {
+            args.push(value);
+        }
+    }
+    Ok((decode(path, false)?, args))
+}
+
 #[derive(Default, Debug)]
 pub struct ShimExecutor {}
 
@@ -119,6 +259,32 @@ pub fn get_spec_from_request(
     }
 }
 
+/// Reject an id runc would not accept before anything is written for the container.
+pub fn check_container_id(id: &str) -> containerd_shim::Result<()> {
+    runc::ids::validate(id).map_err(|e| Error::InvalidArgument(e.to_string()))
+}
+
+/// Send a signal to a process started by the shim, a process which is already gone
+/// is reported as not found.
+pub fn kill_pid(pid: i32, signal: u32) -> containerd_shim::Result<()> {
+    let signal = Signal::try_from(signal as i32)
+        .map_err(|_| Error::InvalidArgument(format!("invalid signal {}", signal)))?;
+    match kill(Pid::from_raw(pid), signal) {
+        Ok(_) => Ok(()),
+        Err(Errno::ESRCH) => Err(Error::NotFoundError("process already finished".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Response of `shim delete`, reporting the container as killed now.
+pub fn killed_delete_response() -> DeleteResponse {
+    let mut resp = DeleteResponse::new();
+    // sigkill
+    resp.set_exit_status(137);
+    resp.set_exited_at(convert_to_timestamp(Some(OffsetDateTime::now_utc())));
+    resp
+}
+
 pub fn check_kill_error(emsg: String) -> Error {
     let emsg = emsg.to_lowercase();
     if emsg.contains("process already finished")
@@ -133,8 +299,116 @@ pub fn check_kill_error(emsg: String) -> Error {
     }
 }
 
+/// Shim wide settings taken from the environment of the shim, so that a deployment
+/// can tune the shim without setting the per-container Options.
+#[derive(Debug, Default, Clone)]
+pub struct ShimConfig {
+    /// `RUNC_V2_SYSTEMD_CGROUP`: use the systemd cgroup driver by default.
+    pub systemd_cgroup: bool,
+    /// `RUNC_V2_BINARY`: runc binary used when the Options do not name one.
+    pub runc_binary: Option<String>,
+    /// `RUNC_V2_DEBUG`: log at debug level.
+    pub debug: bool,
+    /// `RUNC_V2_WORKER_THREADS`: worker threads of the tokio runtime of the async shim,
+    /// one per CPU if unset.
+    pub worker_threads: Option<usize>,
+    /// `RUNC_V2_DEBUG_SOCKET`: serve the debug socket in the bundle,
+    /// when built with the `debug-socket` feature.
+    pub debug_socket: bool,
+}
+
+impl ShimConfig {
+    pub fn from_env() -> Self {
+        let flag = |key: &str| {
+            std::env::var(key)
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or_default()
+        };
+        Self {
+            systemd_cgroup: flag("RUNC_V2_SYSTEMD_CGROUP"),
+            runc_binary: std::env::var("RUNC_V2_BINARY")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            debug: flag("RUNC_V2_DEBUG"),
+            worker_threads: std::env::var("RUNC_V2_WORKER_THREADS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0),
+            debug_socket: flag("RUNC_V2_DEBUG_SOCKET"),
+        }
+    }
+
+    /// Fill the settings left unset by the Options of a container with the shim defaults.
+    pub fn apply(&self, opts: &mut Options) {
+        if opts.binary_name.is_empty() {
+            if let Some(binary) = &self.runc_binary {
+                opts.binary_name = binary.to_string();
+            }
+        }
+        if self.systemd_cgroup {
+            opts.systemd_cgroup = true;
+        }
+    }
+}
+
+/// Containers given a cgroups path in the systemd form need the systemd cgroup driver,
+/// even if their Options do not ask for it.
+pub fn detect_systemd_cgroup(opts: &mut Options, bundle: &str) {
+    if opts.systemd_cgroup {
+        return;
+    }
+    let cgroups_path = Spec::load(Path::new(bundle).join("config.json"))
+        .ok()
+        .and_then(|spec| spec.linux().as_ref()?.cgroups_path().clone());
+    if let Some(path) = cgroups_path {
+        if is_systemd_cgroups_path(&path.to_string_lossy()) {
+            debug!("use systemd cgroup driver for cgroups path {:?}", path);
+            opts.systemd_cgroup = true;
+        }
+    }
+}
+
+const RUNC_OPTIONS_TYPE: &str = "containerd.runc.v1.Options";
+const LEGACY_RUNC_OPTIONS_TYPE: &str = "containerd.linux.runc.RuncOptions";
+
+/// Decode the runtime options of a create request, sent either as the `Options` of
+/// the runc v2 shim, or as the legacy `RuncOptions` of the runc v1 shim.
+pub fn parse_create_options(any: &Any) -> containerd_shim::Result<Options> {
+    // the type url may be prefixed, as in "types.containerd.io/containerd.runc.v1.Options"
+    let type_name = any.type_url.rsplit('/').next().unwrap_or_default();
+    match type_name {
+        RUNC_OPTIONS_TYPE | "" => Ok(Options::parse_from_bytes(&any.value)?),
+        LEGACY_RUNC_OPTIONS_TYPE => parse_legacy_options(&any.value),
+        t => Err(Error::InvalidArgument(format!(
+            "unsupported runtime options type {}",
+            t
+        ))),
+    }
+}
+
+/// The legacy `RuncOptions` are `runtime = 1`, `runtime_root = 2`,
+/// `criu_path = 3` and `systemd_cgroup = 4`.
+fn parse_legacy_options(data: &[u8]) -> containerd_shim::Result<Options> {
+    let mut opts = Options::new();
+    let mut is = CodedInputStream::from_bytes(data);
+    let mut unknown = UnknownFields::new();
+    while let Some(tag) = is.read_raw_tag_or_eof()? {
+        match tag {
+            10 => opts.binary_name = is.read_string()?,
+            18 => opts.root = is.read_string()?,
+            26 => opts.criu_path = is.read_string()?,
+            32 => opts.systemd_cgroup = is.read_bool()?,
+            tag => read_unknown_or_skip_group(tag, &mut is, &mut unknown)?,
+        }
+    }
+    Ok(opts)
+}
+
 const DEFAULT_RUNC_ROOT: &str = "/run/containerd/runc";
 const DEFAULT_COMMAND: &str = "runc";
+/// runc delete and the unmount of the rootfs may fail with EBUSY right after a kill
+const DELETE_RETRY_ATTEMPTS: u32 = 5;
+const DELETE_RETRY_BACKOFF: Duration = Duration::from_millis(50);
 
 pub fn create_runc(
     runtime: &str,
@@ -148,21 +422,22 @@ pub fn create_runc(
     } else {
         runtime
     };
-    let root = opts.root.as_str();
-    let root = Path::new(if root.is_empty() {
-        DEFAULT_RUNC_ROOT
-    } else {
-        root
-    })
-    .join(namespace);
+    let root = RuncRoot::new(opts.root.as_str(), namespace);
 
     let log = bundle.as_ref().join("log.json");
     let mut gopts = GlobalOpts::default()
         .command(runtime)
-        .root(root)
+        .root(root.path())
         .log(log)
         .log_json()
-        .systemd_cgroup(opts.systemd_cgroup);
+        .systemd_cgroup(opts.systemd_cgroup)
+        .retry(RetryPolicy::new(
+            DELETE_RETRY_ATTEMPTS,
+            DELETE_RETRY_BACKOFF,
+        ));
+    if !opts.criu_path.is_empty() {
+        gopts = gopts.criu(opts.criu_path.as_str());
+    }
     if let Some(s) = spawner {
         gopts.custom_spawner(s);
     }
@@ -171,31 +446,188 @@ pub fn create_runc(
         .map_err(other_error!(e, "unable to create runc instance"))
 }
 
-#[derive(Default)]
-pub(crate) struct CreateConfig {}
+/// The namespace scoped root directory where runc keeps the container states,
+/// `<root>/<namespace>` with `/run/containerd/runc` as the default root.
+pub struct RuncRoot {
+    path: PathBuf,
+}
 
-pub fn receive_socket(stream_fd: RawFd) -> containerd_shim::Result<RawFd> {
-    let mut buf = [0u8; 4096];
-    let mut iovec = [IoSliceMut::new(&mut buf)];
-    let mut space = cmsg_space!([RawFd; 2]);
-    let (path, fds) =
-        match recvmsg::<UnixAddr>(stream_fd, &mut iovec, Some(&mut space), MsgFlags::empty()) {
-            Ok(msg) => {
-                let mut iter = msg.cmsgs();
-                if let Some(ControlMessageOwned::ScmRights(fds)) = iter.next() {
-                    (iovec[0].deref(), fds)
-                } else {
-                    return Err(other!("received message is empty"));
-                }
+impl RuncRoot {
+    pub fn new(root: &str, namespace: &str) -> Self {
+        let root = if root.is_empty() {
+            DEFAULT_RUNC_ROOT
+        } else {
+            root
+        };
+        Self {
+            path: Path::new(root).join(namespace),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create the root directory with 0700 if it does not exist,
+    /// and make sure it is owned by the current user.
+    ///
+    /// Missing parents are created with the default permissions, as other
+    /// users of them, such as the runc root of another namespace, need to reach them.
+    pub fn create(&self) -> containerd_shim::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(io_error!(
+                e,
+                "create runc root {}",
+                parent.display()
+            ))?;
+        }
+        match DirBuilder::new().mode(0o700).create(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+                return Err(Error::IoError {
+                    context: format!("create runc root {}", self.path.display()),
+                    err: e,
+                })
             }
+            _ => {}
+        }
+        let md = fs::metadata(&self.path).map_err(io_error!(
+            e,
+            "stat runc root {}",
+            self.path.display()
+        ))?;
+        if !md.is_dir() {
+            return Err(other!(
+                "runc root {} is not a directory",
+                self.path.display()
+            ));
+        }
+        let euid = nix::unistd::geteuid().as_raw();
+        if md.uid() != euid {
+            return Err(other!(
+                "runc root {} is owned by uid {}, expected {}",
+                self.path.display(),
+                md.uid(),
+                euid
+            ));
+        }
+        Ok(())
+    }
+
+    /// List the ids of the containers which have states in the root.
+    pub fn containers(&self) -> containerd_shim::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
             Err(e) => {
-                return Err(other!("failed to receive message: {}", e));
+                return Err(Error::IoError {
+                    context: format!("read runc root {}", self.path.display()),
+                    err: e,
+                })
             }
         };
-    if fds.is_empty() {
-        return Err(other!("received message is empty"));
+        let mut ids = vec![];
+        for entry in entries {
+            let entry = entry.map_err(io_error!(e, "read runc root entry"))?;
+            if entry.path().join("state.json").exists() {
+                ids.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Remove the root directory, nothing is done if it does not exist
+    /// or still holds other containers.
+    pub fn remove_if_empty(&self) -> containerd_shim::Result<()> {
+        let containers = self.containers()?;
+        if !containers.is_empty() {
+            debug!(
+                "runc root {} still has containers {:?}",
+                self.path.display(),
+                containers
+            );
+            return Ok(());
+        }
+        match fs::remove_dir(&self.path) {
+            Ok(_) => Ok(()),
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound
+                    || e.raw_os_error() == Some(libc::ENOTEMPTY)
+                    || e.raw_os_error() == Some(libc::EEXIST) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(Error::IoError {
+                context: format!("remove runc root {}", self.path.display()),
+                err: e,
+            }),
+        }
     }
-    let path = String::from_utf8(Vec::from(path)).unwrap_or_else(|e| {
+}
+
+#[derive(Default)]
+pub(crate) struct CreateConfig {}
+
+/// Unmount all the mounts stacked on the rootfs mounted by the shim,
+/// nothing is done if `rootfs` is empty or not a mount point.
+pub fn unmount_rootfs(rootfs: impl AsRef<Path>) -> containerd_shim::Result<()> {
+    let rootfs = rootfs.as_ref();
+    if rootfs.as_os_str().is_empty() {
+        return Ok(());
+    }
+    unmount_all(rootfs, MntFlags::MNT_DETACH)
+}
+
+/// [unmount_rootfs] on a blocking thread, as a busy rootfs is retried for a while.
+#[cfg(feature = "async")]
+pub async fn unmount_rootfs_async(rootfs: impl AsRef<Path>) -> containerd_shim::Result<()> {
+    let rootfs = rootfs.as_ref().to_path_buf();
+    containerd_shim::util::asyncify(move || unmount_rootfs(rootfs)).await
+}
+
+/// Translate the checkpoint options sent by containerd into runc checkpoint options,
+/// `work_path` is used when the request does not carry its own criu work path.
+pub fn checkpoint_opts(
+    image_path: &str,
+    work_path: &str,
+    opts: &CheckpointOptions,
+) -> CheckpointOpts {
+    let work_path = if opts.work_path.is_empty() {
+        work_path
+    } else {
+        opts.work_path.as_str()
+    };
+    let mut copts = CheckpointOpts::new()
+        .image_path(image_path)
+        .work_path(work_path)
+        .leave_running(!opts.exit)
+        .tcp_established(opts.open_tcp)
+        .ext_unix_sk(opts.external_unix_sockets)
+        .shell_job(opts.terminal)
+        .file_locks(opts.file_locks);
+    if !opts.cgroups_mode.is_empty() {
+        copts = copts.manage_cgroups_mode(opts.cgroups_mode.as_str());
+    }
+    for ns in opts.empty_namespaces.iter() {
+        copts = copts.empty_namespace(ns.as_str());
+    }
+    copts
+}
+
+fn recv_console_fd(stream_fd: RawFd) -> std::io::Result<RawFd> {
+    let mut buf = [0u8; 4096];
+    let mut iovec = [IoSliceMut::new(&mut buf)];
+    let mut space = cmsg_space!([RawFd; 2]);
+    let msg = recvmsg::<UnixAddr>(stream_fd, &mut iovec, Some(&mut space), MsgFlags::empty())?;
+    let fds = match msg.cmsgs().next() {
+        Some(ControlMessageOwned::ScmRights(fds)) if !fds.is_empty() => fds,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "received message is empty",
+            ))
+        }
+    };
+    let path = String::from_utf8(Vec::from(iovec[0].deref())).unwrap_or_else(|e| {
         warn!("failed to get path from array {}", e);
         "".to_string()
     });
@@ -204,10 +636,38 @@ pub fn receive_socket(stream_fd: RawFd) -> containerd_shim::Result<RawFd> {
         "copy_console: console socket get path: {}, fd: {}",
         path, &fds[0]
     );
-    tcgetattr(fds[0])?;
     Ok(fds[0])
 }
 
+#[cfg(not(feature = "async"))]
+pub fn receive_socket(stream_fd: RawFd) -> containerd_shim::Result<RawFd> {
+    let fd = recv_console_fd(stream_fd).map_err(other_error!(e, "failed to receive message"))?;
+    tcgetattr(fd)?;
+    Ok(fd)
+}
+
+/// Receive the pty master sent by runc over the console socket without blocking the runtime
+#[cfg(feature = "async")]
+pub async fn receive_socket_async(
+    stream: tokio::net::UnixStream,
+) -> containerd_shim::Result<RawFd> {
+    let stream = stream
+        .into_std()
+        .map_err(io_error!(e, "failed to deregister console stream"))?;
+    let stream = AsyncFd::new(stream).map_err(io_error!(e, "failed to register console stream"))?;
+    loop {
+        let mut guard = stream
+            .readable()
+            .await
+            .map_err(io_error!(e, "failed to poll console stream"))?;
+        if let Ok(res) = guard.try_io(|inner| recv_console_fd(inner.as_raw_fd())) {
+            let fd = res.map_err(other_error!(e, "failed to receive message"))?;
+            tcgetattr(fd)?;
+            return Ok(fd);
+        }
+    }
+}
+
 pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
     match spec.linux() {
         None => true,
@@ -224,3 +684,96 @@ pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use containerd_shim::protos::protobuf::CodedOutputStream;
+
+    use super::*;
+
+    #[test]
+    fn test_runc_root_create() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = tmp.path().join("reference");
+        fs::create_dir(&reference).unwrap();
+        let default_mode = fs::metadata(&reference).unwrap().permissions().mode();
+
+        let base = tmp.path().join("run/runc");
+        let root = RuncRoot::new(base.to_str().unwrap(), "default");
+        root.create().unwrap();
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(root.path()), 0o700);
+        assert_eq!(mode(&base), default_mode & 0o7777);
+        assert_eq!(mode(base.parent().unwrap()), default_mode & 0o7777);
+
+        // an existing root is kept
+        root.create().unwrap();
+        RuncRoot::new(base.to_str().unwrap(), "k8s.io")
+            .create()
+            .unwrap();
+        assert_eq!(mode(&base.join("k8s.io")), 0o700);
+    }
+
+    #[test]
+    fn test_parse_legacy_options() {
+        let mut data = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut data);
+            os.write_string(1, "/usr/bin/runc").unwrap();
+            // fields unknown to the shim, of each wire type, are skipped
+            os.write_uint64(7, 42).unwrap();
+            os.write_string(2, "/run/runc-legacy").unwrap();
+            os.write_bytes(9, b"unknown").unwrap();
+            os.write_fixed32(12, 1).unwrap();
+            os.write_string(3, "/usr/sbin/criu").unwrap();
+            os.write_fixed64(13, 2).unwrap();
+            os.write_bool(4, true).unwrap();
+            os.flush().unwrap();
+        }
+        let mut any = Any::new();
+        any.type_url = format!("types.containerd.io/{}", LEGACY_RUNC_OPTIONS_TYPE);
+        any.value = data;
+
+        let opts = parse_create_options(&any).unwrap();
+        assert_eq!(opts.binary_name, "/usr/bin/runc");
+        assert_eq!(opts.root, "/run/runc-legacy");
+        assert_eq!(opts.criu_path, "/usr/sbin/criu");
+        assert!(opts.systemd_cgroup);
+
+        // a truncated message is an error
+        any.value.truncate(any.value.len() - 1);
+        assert!(parse_create_options(&any).is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), b"a b/c");
+        assert_eq!(percent_decode("plain+text").unwrap(), b"plain+text");
+        assert!(percent_decode("bad%2").is_none());
+        assert!(percent_decode("bad%zz").is_none());
+        assert!(percent_decode("bad%+1").is_none());
+    }
+
+    #[test]
+    fn test_parse_binary_uri() {
+        let (path, args) = parse_binary_uri("binary:///usr/bin/logger").unwrap();
+        assert_eq!(path, "/usr/bin/logger");
+        assert!(args.is_empty());
+
+        let (path, args) = parse_binary_uri(
+            "binary:///opt/my%20logger?--tag=web+app&--verbose&--dir=%2Fvar%2Flog",
+        )
+        .unwrap();
+        assert_eq!(path, "/opt/my logger");
+        assert_eq!(
+            args,
+            vec!["--tag", "web app", "--verbose", "--dir", "/var/log"]
+        );
+
+        assert!(parse_binary_uri("file:///usr/bin/logger").is_err());
+        assert!(parse_binary_uri("binary://?a=b").is_err());
+        assert!(parse_binary_uri("binary:///logger?a=%zz").is_err());
+    }
+}
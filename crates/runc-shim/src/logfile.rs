@@ -0,0 +1,311 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Log file of the `file://` stdio scheme, where the outputs of a process are copied
+//! by the shim into a file, rotated by size and reopened on SIGUSR1.
+
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::ffi::OsStringExt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+};
+
+use containerd_shim::{Error, Result};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+#[cfg(feature = "async")]
+use tokio::io::AsyncWrite;
+
+use crate::common::percent_decode;
+
+/// Rotated files kept when the uri does not say.
+const DEFAULT_MAX_FILES: usize = 5;
+
+/// Bumped by SIGUSR1, the log files are reopened on their next write when it changed.
+static REOPEN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_reopen_signal(_: libc::c_int) {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Reopen the log files on SIGUSR1, e.g. after logrotate moved them away.
+fn handle_reopen_signal() -> io::Result<()> {
+    static INSTALL: Once = Once::new();
+    let mut res = Ok(());
+    INSTALL.call_once(|| {
+        let action = SigAction::new(
+            SigHandler::Handler(on_reopen_signal),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        // Safe as the handler only touches an atomic
+        if let Err(e) = unsafe { sigaction(Signal::SIGUSR1, &action) } {
+            res = Err(e.into());
+        }
+    });
+    res
+}
+
+/// Settings of a `file:///path/to/log?max-size=<bytes>&max-files=<count>` uri.
+///
+/// The file is rotated to `<path>.1` once it would grow over `max-size` bytes,
+/// pushing the older files up to `<path>.<max-files>`. No rotation without `max-size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFileOptions {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl LogFileOptions {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let rest = uri
+            .trim()
+            .strip_prefix("file://")
+            .ok_or_else(|| Error::InvalidArgument(format!("invalid file uri {}", uri)))?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if path.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "no file path in uri {}",
+                uri
+            )));
+        }
+        let path = percent_decode(path)
+            .ok_or_else(|| Error::InvalidArgument(format!("invalid escape in uri {}", uri)))?;
+        let mut opts = Self {
+            path: PathBuf::from(OsString::from_vec(path)),
+            max_size: 0,
+            max_files: DEFAULT_MAX_FILES,
+        };
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let invalid = |_| Error::InvalidArgument(format!("invalid {} in uri {}", key, uri));
+            match key {
+                "max-size" => opts.max_size = value.parse().map_err(invalid)?,
+                "max-files" => opts.max_files = value.parse().map_err(invalid)?,
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "unknown option {} in uri {}",
+                        key, uri
+                    )))
+                }
+            }
+        }
+        Ok(opts)
+    }
+}
+
+struct Inner {
+    opts: LogFileOptions,
+    file: File,
+    size: u64,
+    generation: usize,
+}
+
+impl Inner {
+    fn open(opts: &LogFileOptions) -> io::Result<(File, u64)> {
+        if let Some(parent) = opts.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&opts.path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let (file, size) = Self::open(&self.opts)?;
+        self.file = file;
+        self.size = size;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |i: usize| {
+            let mut name = self.opts.path.clone().into_os_string();
+            name.push(format!(".{}", i));
+            PathBuf::from(name)
+        };
+        if self.opts.max_files == 0 {
+            fs::remove_file(&self.opts.path)?;
+        } else {
+            for i in (1..self.opts.max_files).rev() {
+                match fs::rename(rotated(i), rotated(i + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.opts.path, rotated(1))?;
+        }
+        self.reopen()
+    }
+}
+
+impl Write for Inner {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        if generation != self.generation {
+            self.generation = generation;
+            self.reopen()?;
+        }
+        if self.opts.max_size > 0
+            && self.size > 0
+            && self.size + buf.len() as u64 > self.opts.max_size
+        {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Handle of a log file shared by the stdout and the stderr copies of a process.
+#[derive(Clone)]
+pub struct LogFile {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl LogFile {
+    pub fn open(opts: LogFileOptions) -> io::Result<Self> {
+        handle_reopen_signal()?;
+        let (file, size) = Inner::open(&opts)?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                opts,
+                file,
+                size,
+                generation: REOPEN_GENERATION.load(Ordering::SeqCst),
+            })),
+        })
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap().flush()
+    }
+}
+
+// Writes to a local file are short enough to be done in place
+#[cfg(feature = "async")]
+impl AsyncWrite for LogFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let opts = LogFileOptions::parse("file:///var/log/ctr%20a.log").unwrap();
+        assert_eq!(opts.path, PathBuf::from("/var/log/ctr a.log"));
+        assert_eq!(opts.max_size, 0);
+        assert_eq!(opts.max_files, DEFAULT_MAX_FILES);
+
+        let opts = LogFileOptions::parse("file:///log?max-size=1024&max-files=3").unwrap();
+        assert_eq!(opts.path, PathBuf::from("/log"));
+        assert_eq!(opts.max_size, 1024);
+        assert_eq!(opts.max_files, 3);
+
+        assert!(LogFileOptions::parse("binary:///log").is_err());
+        assert!(LogFileOptions::parse("file://").is_err());
+        assert!(LogFileOptions::parse("file:///log%2").is_err());
+        assert!(LogFileOptions::parse("file:///log?max-size=big").is_err());
+        assert!(LogFileOptions::parse("file:///log?color=red").is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ctr.log");
+        let rotated = |i: usize| dir.path().join(format!("ctr.log.{}", i));
+        let mut log = LogFile::open(LogFileOptions {
+            path: path.clone(),
+            max_size: 10,
+            max_files: 2,
+        })
+        .unwrap();
+
+        log.write_all(b"first\n").unwrap();
+        log.write_all(b"second\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "first\n");
+
+        log.write_all(b"third\n").unwrap();
+        log.write_all(b"fourth\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "second\n");
+        assert!(!rotated(3).exists());
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ctr.log");
+        let mut log = LogFile::open(LogFileOptions {
+            path: path.clone(),
+            max_size: 0,
+            max_files: DEFAULT_MAX_FILES,
+        })
+        .unwrap();
+        log.write_all(b"before\n").unwrap();
+
+        // as logrotate does, before sending SIGUSR1
+        let moved = dir.path().join("moved.log");
+        fs::rename(&path, &moved).unwrap();
+        nix::sys::signal::raise(Signal::SIGUSR1).unwrap();
+        log.write_all(b"after\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "before\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    }
+}
@@ -17,6 +17,7 @@
 #[cfg(feature = "async")]
 mod asynchronous;
 mod common;
+mod logfile;
 #[cfg(not(feature = "async"))]
 mod synchronous;
 
@@ -26,11 +27,17 @@ fn main() {
 }
 
 #[cfg(feature = "async")]
-#[tokio::main]
-async fn main() {
-    containerd_shim::asynchronous::run::<crate::asynchronous::Service>(
-        "io.containerd.runc.v2-rs",
-        None,
-    )
-    .await;
+fn main() {
+    // all the containers of the shim share this runtime
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = common::ShimConfig::from_env().worker_threads {
+        builder.worker_threads(threads);
+    }
+    let runtime = builder
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime");
+    runtime.block_on(containerd_shim::asynchronous::run::<
+        crate::asynchronous::Service,
+    >("io.containerd.runc.v2-rs", None));
 }
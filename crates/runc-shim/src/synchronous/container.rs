@@ -18,13 +18,23 @@ use std::{
     collections::HashMap,
     convert::TryFrom,
     fs::{File, OpenOptions},
-    os::unix::io::{AsRawFd, FromRawFd},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
     path::Path,
-    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
 };
 
 use containerd_shim as shim;
 use log::debug;
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    unistd::dup,
+};
 use oci_spec::runtime::LinuxResources;
 use shim::{
     api::*,
@@ -33,6 +43,7 @@ use shim::{
     io::Stdio,
     io_error, ioctl_set_winsz, other, other_error,
     protos::cgroups::metrics::Metrics,
+    pty::MasterReader,
     util::{convert_to_timestamp, read_pid_from_file},
     Console,
 };
@@ -65,6 +76,7 @@ pub trait Process {
     fn copy_io(&self) -> Result<()>;
     fn set_pid_from_file(&mut self, pid_path: &Path) -> Result<()>;
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()>;
+    fn close_io(&mut self) -> Result<()>;
 }
 
 pub trait Container {
@@ -81,13 +93,23 @@ pub trait Container {
     fn update(&mut self, resources: &LinuxResources) -> Result<()>;
     fn pids(&self) -> Result<PidsResponse>;
     fn id(&self) -> String;
+    fn pause(&mut self) -> Result<()>;
+    fn resume(&mut self) -> Result<()>;
+    fn close_io(&mut self, exec_id: Option<&str>) -> Result<()>;
+    fn checkpoint(&mut self, path: &str, opts: &CheckpointOptions) -> Result<()>;
 }
 
+/// How many exits of deleted exec processes a container keeps.
+const MAX_DELETED_PROCESSES: usize = 32;
+
 pub struct CommonContainer<T, E> {
     pub id: String,
     pub bundle: String,
     pub init: T,
     pub processes: HashMap<String, E>,
+    /// pid, exit code and exit time of the last deleted exec processes,
+    /// so that a repeated delete of an exec still reports how it exited
+    pub deleted_processes: HashMap<String, (i32, i32, Option<OffsetDateTime>)>,
 }
 
 impl<T, E> CommonContainer<T, E>
@@ -97,6 +119,22 @@ where
     E: TryFrom<ExecProcessRequest>,
     E::Error: ToString,
 {
+    /// Keep the exit of a deleted exec, forgetting the oldest exits beyond
+    /// MAX_DELETED_PROCESSES, as execs with unique ids may run for the whole life of a container.
+    pub fn record_deleted(&mut self, exec_id: &str, exit: (i32, i32, Option<OffsetDateTime>)) {
+        self.deleted_processes.insert(exec_id.to_string(), exit);
+        if self.deleted_processes.len() > MAX_DELETED_PROCESSES {
+            if let Some(oldest) = self
+                .deleted_processes
+                .iter()
+                .min_by_key(|(_, (_, _, exited_at))| *exited_at)
+                .map(|(id, _)| id.clone())
+            {
+                self.deleted_processes.remove(&oldest);
+            }
+        }
+    }
+
     pub fn get_process(&self, exec_id: Option<&str>) -> Result<&dyn Process> {
         match exec_id {
             Some(exec_id) => {
@@ -133,6 +171,8 @@ where
     pub fn exec(&mut self, req: ExecProcessRequest) -> Result<()> {
         let exec_id = req.exec_id.to_string();
         let exec_process = E::try_from(req).map_err(other_error!(e, "convert ExecProcess"))?;
+        // the id of a deleted exec can be taken again by a new one
+        self.deleted_processes.remove(&exec_id);
         self.processes.insert(exec_id, exec_process);
         Ok(())
     }
@@ -178,6 +218,8 @@ pub struct CommonProcess {
     pub exited_at: Option<OffsetDateTime>,
     pub wait_chan_tx: Vec<SyncSender<i8>>,
     pub console: Option<Console>,
+    /// Write end of the stdin fifo held by the shim, dropped when stdin is closed
+    pub stdin_w: Arc<Mutex<Option<File>>>,
 }
 
 impl Process for CommonProcess {
@@ -257,13 +299,29 @@ impl Process for CommonProcess {
 
         if !self.stdio.stdin.is_empty() {
             debug!("copy_console: pipe stdin to console");
-            let f = unsafe { File::from_raw_fd(fd) };
+            let f = unsafe { File::from_raw_fd(dup(fd)?) };
+            // open the read end without blocking, then hold a write end until close_io
+            // is requested, so the copy does not stop when the client side is closed
             let stdin = OpenOptions::new()
                 .read(true)
-                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
                 .open(self.stdio.stdin.as_str())
                 .map_err(io_error!(e, "open stdin"))?;
-            spawn_copy_for_tty(stdin, f, None, None);
+            let stdin_w = OpenOptions::new()
+                .write(true)
+                .open(self.stdio.stdin.as_str())
+                .map_err(io_error!(e, "open stdin for write"))?;
+            fcntl(stdin.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+            *self.stdin_w.lock().unwrap() = Some(stdin_w);
+            let stdin_w = self.stdin_w.clone();
+            spawn_copy_for_tty(
+                stdin,
+                f,
+                None,
+                Some(Box::new(move || {
+                    stdin_w.lock().unwrap().take();
+                })),
+            );
         }
 
         if !self.stdio.stdout.is_empty() {
@@ -279,6 +337,7 @@ impl Process for CommonProcess {
                 .read(true)
                 .open(self.stdio.stdout.as_str())
                 .map_err(io_error!(e, "open stdout for read"))?;
+            let f = MasterReader::new(f).on_hangup(|| debug!("copy_console: console hung up"));
             spawn_copy_for_tty(
                 f,
                 stdout,
@@ -323,4 +382,9 @@ impl Process for CommonProcess {
             None => Err(other!("there is no console")),
         }
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        self.stdin_w.lock().unwrap().take();
+        Ok(())
+    }
 }
@@ -112,6 +112,13 @@ impl ProcessIO {
                 }
             }
 
+            if let Some(log_file) = &self.log_file {
+                for r in IntoIterator::into_iter([pio.stdout(), pio.stderr()]).flatten() {
+                    spawn_copy(r, log_file.clone(), Some(&wg), None);
+                }
+                return Ok(wg);
+            }
+
             if let Some(r) = pio.stdout() {
                 debug!("copy_io: pipe stdout from to {}", stdio.stdout.as_str());
                 if !stdio.stdout.is_empty() {
@@ -29,10 +29,7 @@ use std::{
 
 use containerd_shim as shim;
 use log::{debug, error};
-use nix::{
-    sys::{signal::kill, stat::Mode},
-    unistd::{mkdir, Pid},
-};
+use nix::{sys::stat::Mode, unistd::mkdir};
 use oci_spec::runtime::{LinuxNamespaceType, LinuxResources};
 use runc::{Command, Spawner};
 use shim::{
@@ -44,10 +41,7 @@ use shim::{
     mount::mount_rootfs,
     other, other_error,
     protos::{
-        api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
-        shim::oci::ProcessDetails,
+        api::ProcessInfo, cgroups::metrics::Metrics, protobuf::Message, shim::oci::ProcessDetails,
     },
     util::{convert_to_any, read_spec_from_file, write_options, write_runtime, IntoOption},
     Console,
@@ -56,7 +50,10 @@ use time::OffsetDateTime;
 
 use crate::{
     common,
-    common::{create_io, has_shared_pid_namespace, CreateConfig, ShimExecutor, INIT_PID_FILE},
+    common::{
+        checkpoint_opts, create_io, has_shared_pid_namespace, unmount_rootfs, CreateConfig,
+        ShimExecutor, INIT_PID_FILE,
+    },
     synchronous::container::{
         CommonContainer, CommonProcess, Container, ContainerFactory, Process,
     },
@@ -67,12 +64,14 @@ pub(crate) struct RuncFactory {}
 
 impl ContainerFactory<RuncContainer> for RuncFactory {
     fn create(&self, ns: &str, req: &CreateTaskRequest) -> Result<RuncContainer> {
+        common::check_container_id(&req.id)?;
         let bundle = req.bundle.as_str();
         let mut opts = Options::new();
         if let Some(any) = req.options.as_ref() {
-            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
-            opts.merge_from(&mut input)?;
+            opts = common::parse_create_options(any)?;
         }
+        common::ShimConfig::from_env().apply(&mut opts);
+        common::detect_systemd_cgroup(&mut opts, bundle);
         if opts.compute_size() > 0 {
             debug!("create options: {:?}", &opts);
         }
@@ -100,6 +99,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
             mount_rootfs(mount_type, source, &m.options.to_vec(), rootfs)?;
         }
 
+        common::RuncRoot::new(opts.root.as_str(), ns).create()?;
         let runc = common::create_runc(
             runtime,
             ns,
@@ -118,6 +118,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
 
         let mut init = InitProcess::new(id, bundle, runc, stdio);
         init.rootfs = rootfs.to_string();
+        init.namespace = ns.to_string();
         let work_dir = Path::new(bundle).join("work");
         let work_dir = work_dir
             .as_path()
@@ -128,10 +129,10 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         init.io_gid = opts.io_gid();
         init.no_pivot_root = opts.no_pivot_root();
         init.no_new_key_ring = opts.no_new_keyring();
-        init.criu_work_path = if opts.criu_path().is_empty() {
+        init.criu_work_path = if opts.criu_work_path().is_empty() {
             work_dir.to_string()
         } else {
-            opts.criu_path().to_string()
+            opts.criu_work_path().to_string()
         };
 
         let config = CreateConfig::default();
@@ -142,6 +143,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
                 bundle: bundle.to_string(),
                 init,
                 processes: Default::default(),
+                deleted_processes: Default::default(),
             },
         };
         Ok(container)
@@ -170,6 +172,7 @@ impl Container for RuncContainer {
                     pid_file: Some(pid_path.to_owned()),
                     console_socket: None,
                     detach: true,
+                    ..Default::default()
                 };
                 let terminal = process.common.stdio.terminal;
                 let socket = if terminal {
@@ -179,6 +182,7 @@ impl Container for RuncContainer {
                 } else {
                     let io = create_io(
                         &process.common.id,
+                        &self.common.init.namespace,
                         self.common.init.io_uid,
                         self.common.init.io_gid,
                         &process.common.stdio,
@@ -252,17 +256,71 @@ impl Container for RuncContainer {
         self.common.wait_channel(exec_id)
     }
 
+    fn close_io(&mut self, exec_id: Option<&str>) -> Result<()> {
+        self.common.get_mut_process(exec_id)?.close_io()
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        match self.common.init.status() {
+            Status::RUNNING => {
+                self.common
+                    .init
+                    .runtime
+                    .pause(self.id().as_str())
+                    .map_err(other_error!(e, "failed to pause"))?;
+                self.common.init.set_status(Status::PAUSED);
+                Ok(())
+            }
+            s => Err(Error::FailedPreconditionError(format!(
+                "cannot pause container in {:?} state",
+                s
+            ))),
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        match self.common.init.status() {
+            Status::PAUSED => {
+                self.common
+                    .init
+                    .runtime
+                    .resume(self.id().as_str())
+                    .map_err(other_error!(e, "failed to resume"))?;
+                self.common.init.set_status(Status::RUNNING);
+                Ok(())
+            }
+            s => Err(Error::FailedPreconditionError(format!(
+                "cannot resume container in {:?} state",
+                s
+            ))),
+        }
+    }
+
+    fn checkpoint(&mut self, path: &str, opts: &CheckpointOptions) -> Result<()> {
+        let copts = checkpoint_opts(path, &self.common.init.criu_work_path, opts);
+        self.common
+            .init
+            .runtime
+            .checkpoint(self.id().as_str(), Some(&copts))
+            .map_err(other_error!(e, "failed to checkpoint"))?;
+        Ok(())
+    }
+
     fn get_exit_info(&self, exec_id: Option<&str>) -> Result<(i32, i32, Option<OffsetDateTime>)> {
         self.common.get_exit_info(exec_id)
     }
 
     fn delete(&mut self, exec_id_opt: Option<&str>) -> Result<(i32, i32, Option<OffsetDateTime>)> {
+        if let Some(exit_info) = exec_id_opt.and_then(|id| self.common.deleted_processes.get(id)) {
+            return Ok(*exit_info);
+        }
         let (pid, code, exited_at) = self
             .get_exit_info(exec_id_opt)
             .map_err(other_error!(e, "failed to get exit info"))?;
         match exec_id_opt {
             Some(exec_id) => {
                 self.common.processes.remove(exec_id);
+                self.common.record_deleted(exec_id, (pid, code, exited_at));
             }
             None => {
                 self.common
@@ -270,16 +328,14 @@ impl Container for RuncContainer {
                     .runtime
                     .delete(
                         self.id().as_str(),
-                        Some(&runc::options::DeleteOpts { force: true }),
+                        Some(&runc::options::DeleteOpts::new().force(true)),
                     )
-                    .or_else(|e| {
-                        if !e.to_string().to_lowercase().contains("does not exist") {
-                            Err(e)
-                        } else {
-                            Ok(())
-                        }
+                    .or_else(|e| match e {
+                        runc::error::Error::ContainerNotFound { .. } => Ok(()),
+                        e => Err(e),
                     })
                     .map_err(other_error!(e, "failed delete"))?;
+                unmount_rootfs(&self.common.init.rootfs)?;
             }
         };
         Ok((pid, code, exited_at))
@@ -336,10 +392,10 @@ impl Container for RuncContainer {
                 pid: pid as u32,
                 ..Default::default()
             };
-            for process in self.common.processes.values() {
+            for (exec_id, process) in self.common.processes.iter() {
                 if process.common.pid as usize == pid {
                     let details = ProcessDetails {
-                        exec_id: "".to_string(),
+                        exec_id: exec_id.to_string(),
                         ..Default::default()
                     };
                     p_info.set_info(convert_to_any(Box::new(details))?);
@@ -380,11 +436,7 @@ fn kill_process(pid: u32, exit_at: Option<OffsetDateTime>, sig: u32) -> Result<(
     } else if exit_at.is_some() {
         Err(Error::NotFoundError("process already finished".to_string()))
     } else {
-        kill(
-            Pid::from_raw(pid as i32),
-            nix::sys::signal::Signal::try_from(sig as i32).unwrap(),
-        )
-        .map_err(Into::into)
+        common::kill_pid(pid as i32, sig)
     }
 }
 
@@ -399,6 +451,7 @@ pub(crate) struct InitProcess {
     pub(crate) no_pivot_root: bool,
     pub(crate) no_new_key_ring: bool,
     pub(crate) criu_work_path: String,
+    pub(crate) namespace: String,
 }
 
 impl InitProcess {
@@ -414,6 +467,7 @@ impl InitProcess {
                 exited_at: None,
                 wait_chan_tx: vec![],
                 console: None,
+                stdin_w: Default::default(),
             },
             bundle: bundle.to_string(),
             runtime,
@@ -424,6 +478,7 @@ impl InitProcess {
             no_pivot_root: false,
             no_new_key_ring: false,
             criu_work_path: "".to_string(),
+            namespace: "".to_string(),
         }
     }
 
@@ -443,7 +498,13 @@ impl InitProcess {
             create_opts.console_socket = Some(s.path.to_owned());
             Some(s)
         } else {
-            let io = create_io(&id, self.io_uid, self.io_gid, &self.common.stdio)?;
+            let io = create_io(
+                &id,
+                &self.namespace,
+                self.io_uid,
+                self.io_gid,
+                &self.common.stdio,
+            )?;
             self.common.io = Some(io);
             create_opts.io = self
                 .common
@@ -538,6 +599,10 @@ impl Process for InitProcess {
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()> {
         self.common.resize_pty(height, width)
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        self.common.close_io()
+    }
 }
 
 pub(crate) struct ExecProcess {
@@ -613,6 +678,10 @@ impl Process for ExecProcess {
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()> {
         self.common.resize_pty(height, width)
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        self.common.close_io()
+    }
 }
 
 impl TryFrom<ExecProcessRequest> for ExecProcess {
@@ -635,6 +704,7 @@ impl TryFrom<ExecProcessRequest> for ExecProcess {
                 exited_at: None,
                 wait_chan_tx: vec![],
                 console: None,
+                stdin_w: Default::default(),
             },
             spec: p,
         };
@@ -49,7 +49,10 @@ use shim::{
 };
 
 use crate::{
-    common::{create_runc, ShimExecutor, GROUP_LABELS},
+    common::{
+        create_runc, killed_delete_response, unmount_rootfs, RuncRoot, ShimConfig, ShimExecutor,
+        GROUP_LABELS,
+    },
     synchronous::{
         container::{Container, Process},
         runc::{RuncContainer, RuncFactory},
@@ -61,7 +64,8 @@ use crate::{
 impl Shim for Service {
     type T = ShimTask<RuncFactory, RuncContainer>;
 
-    fn new(_runtime_id: &str, id: &str, namespace: &str, _config: &mut Config) -> Self {
+    fn new(_runtime_id: &str, id: &str, namespace: &str, config: &mut Config) -> Self {
+        config.debug |= ShimConfig::from_env().debug;
         Service {
             exit: Arc::new(ExitSignal::default()),
             id: id.to_string(),
@@ -95,25 +99,34 @@ impl Shim for Service {
 
     #[cfg(not(feature = "async"))]
     fn delete_shim(&mut self) -> containerd_shim::Result<DeleteResponse> {
-        let namespace = self.namespace.as_str();
-        let bundle = current_dir().map_err(io_error!(e, "get current dir"))?;
-        let opts = read_options(&bundle)?;
-        let runtime = read_runtime(&bundle)?;
-
-        let runc = create_runc(
-            &runtime,
-            namespace,
-            &bundle,
-            &opts,
-            Some(Arc::new(ShimExecutor::default())),
-        )?;
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
-            .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
-        let mut resp = DeleteResponse::new();
-        // sigkill
-        resp.set_exit_status(137);
-        resp.set_exited_at(timestamp()?);
-        Ok(resp)
+        // containerd also deletes the shims of failed creates, whose bundle may hold no state,
+        // so the cleanup is best effort and the container is always reported as killed
+        match current_dir() {
+            Ok(bundle) => {
+                let opts = read_options(&bundle).unwrap_or_else(|e| {
+                    debug!("no options in bundle: {}", e);
+                    Options::new()
+                });
+                let runtime = read_runtime(&bundle).unwrap_or_default();
+                // a forced delete kills what is left of the container, and only it
+                match create_runc(
+                    &runtime,
+                    &self.namespace,
+                    &bundle,
+                    &opts,
+                    Some(Arc::new(ShimExecutor::default())),
+                ) {
+                    Ok(runc) => runc
+                        .delete(&self.id, Some(&DeleteOpts::new().force(true)))
+                        .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e)),
+                    Err(e) => warn!("failed to create runc client: {}", e),
+                }
+                unmount_rootfs(bundle.join("rootfs"))
+                    .unwrap_or_else(|e| warn!("failed to unmount rootfs: {}", e));
+            }
+            Err(e) => warn!("failed to get the bundle of the shim: {}", e),
+        }
+        Ok(killed_delete_response())
     }
 
     #[cfg(feature = "async")]
@@ -123,6 +136,15 @@ impl Shim for Service {
 
     fn wait(&mut self) {
         self.exit.wait();
+        // the shim runs in the bundle, where the options of the container are kept
+        if let Ok(opts) = current_dir()
+            .map_err(io_error!(e, "get current dir"))
+            .and_then(read_options)
+        {
+            RuncRoot::new(opts.root.as_str(), &self.namespace)
+                .remove_if_empty()
+                .unwrap_or_else(|e| warn!("failed to remove runc root: {}", e));
+        }
     }
 
     fn create_task_service(&self, publisher: RemotePublisher) -> Self::T {
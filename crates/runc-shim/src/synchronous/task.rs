@@ -17,7 +17,11 @@
 use std::{
     collections::HashMap,
     process,
-    sync::{mpsc::Sender, Arc, Mutex, Once},
+    sync::{
+        mpsc::{RecvTimeoutError, Sender},
+        Arc, Mutex, Once,
+    },
+    time::{Duration, Instant},
 };
 
 use containerd_shim as shim;
@@ -28,8 +32,11 @@ use shim::{
     event::Event,
     other_error,
     protos::{
-        events::task::{TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskStart},
-        protobuf::MessageDyn,
+        events::task::{
+            TaskCheckpointed, TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO,
+            TaskOOM, TaskPaused, TaskResumed, TaskStart,
+        },
+        protobuf::{CodedInputStream, Message, MessageDyn},
     },
     util::{convert_to_any, convert_to_timestamp, IntoOption},
     Error, ExitSignal, Task, TtrpcContext, TtrpcResult,
@@ -39,6 +46,9 @@ use crate::synchronous::container::{Container, ContainerFactory};
 
 type EventSender = Sender<(String, Box<dyn MessageDyn>)>;
 
+/// How long a forced shutdown waits for the killed containers to exit.
+const SHUTDOWN_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct ShimTask<F, C> {
     pub containers: Arc<Mutex<HashMap<String, C>>>,
     factory: F,
@@ -74,6 +84,33 @@ impl<F, C> ShimTask<F, C> {
             .send((topic.to_string(), Box::new(event)))
             .unwrap_or_else(|e| warn!("send {} to publisher: {}", topic, e));
     }
+
+    /// Publish a TaskOOM event every time the container's memory cgroup hits an OOM
+    #[cfg(target_os = "linux")]
+    fn monitor_oom(&self, id: &str, pid: u32) {
+        let rx = match shim::cgroup::register_oom_event(id, pid) {
+            Ok(rx) => rx,
+            Err(e) => {
+                warn!("failed to register oom event for {}: {}", id, e);
+                return;
+            }
+        };
+        let tx = self.tx.lock().unwrap().clone();
+        std::thread::spawn(move || {
+            while let Ok(container_id) = rx.recv() {
+                debug!("oom event for container {}", container_id);
+                let event = TaskOOM {
+                    container_id,
+                    ..Default::default()
+                };
+                let topic = event.topic();
+                if let Err(e) = tx.send((topic.to_string(), Box::new(event))) {
+                    warn!("send {} to publisher: {}", topic, e);
+                    break;
+                }
+            }
+        });
+    }
 }
 
 impl<F, C> Task for ShimTask<F, C>
@@ -91,6 +128,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, pid = tracing::field::Empty))
+    )]
     fn create(
         &self,
         _ctx: &TtrpcContext,
@@ -108,6 +149,8 @@ where
         let mut resp = CreateTaskResponse::new();
         let pid = container.pid() as u32;
         resp.pid = pid;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pid", pid);
 
         containers.insert(id.to_string(), container);
 
@@ -132,6 +175,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id, pid = tracing::field::Empty))
+    )]
     fn start(&self, _ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
         info!("Start request for {:?}", &req);
         let mut containers = self.containers.lock().unwrap();
@@ -139,6 +186,8 @@ where
             Error::NotFoundError(format!("can not find container by id {}", req.id()))
         })?;
         let pid = container.start(req.exec_id.as_str().none_if(|&x| x.is_empty()))?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pid", pid);
 
         let mut resp = StartResponse::new();
         resp.pid = pid as u32;
@@ -149,6 +198,8 @@ where
                 pid: pid as u32,
                 ..Default::default()
             });
+            #[cfg(target_os = "linux")]
+            self.monitor_oom(req.id(), pid as u32);
         } else {
             self.send_event(TaskExecStarted {
                 container_id: req.id.to_string(),
@@ -162,6 +213,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id))
+    )]
     fn delete(&self, _ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
         info!("Delete request for {:?}", &req);
         let mut containers = self.containers.lock().unwrap();
@@ -171,19 +226,19 @@ where
         let id = container.id();
         let exec_id_opt = req.exec_id().none_if(|x| x.is_empty());
         let (pid, exit_status, exited_at) = container.delete(exec_id_opt)?;
-        if req.exec_id().is_empty() {
+        let ts = convert_to_timestamp(exited_at);
+        // deleting an exec only removes the process, the container is kept
+        if exec_id_opt.is_none() {
             containers.remove(req.id.as_str());
+            self.send_event(TaskDelete {
+                container_id: id,
+                pid: pid as u32,
+                exit_status: exit_status as u32,
+                exited_at: Some(ts.clone()).into(),
+                ..Default::default()
+            });
         }
 
-        let ts = convert_to_timestamp(exited_at);
-        self.send_event(TaskDelete {
-            container_id: id,
-            pid: pid as u32,
-            exit_status: exit_status as u32,
-            exited_at: Some(ts.clone()).into(),
-            ..Default::default()
-        });
-
         let mut resp = DeleteResponse::new();
         resp.set_exited_at(ts);
         resp.set_pid(pid as u32);
@@ -208,6 +263,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id, signal = req.signal))
+    )]
     fn kill(&self, _ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
         info!("Kill request for {:?}", req);
         let mut containers = self.containers.lock().unwrap();
@@ -223,6 +282,62 @@ where
         Ok(Empty::new())
     }
 
+    fn pause(&self, _ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        info!("Pause request for {:?}", req);
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(req.id()).ok_or_else(|| {
+            Error::NotFoundError(format!("can not find container by id {}", req.id()))
+        })?;
+        container.pause()?;
+        self.send_event(TaskPaused {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        });
+        info!("Pause request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    fn resume(&self, _ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        info!("Resume request for {:?}", req);
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(req.id()).ok_or_else(|| {
+            Error::NotFoundError(format!("can not find container by id {}", req.id()))
+        })?;
+        container.resume()?;
+        self.send_event(TaskResumed {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        });
+        info!("Resume request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    fn checkpoint(&self, _ctx: &TtrpcContext, req: CheckpointTaskRequest) -> TtrpcResult<Empty> {
+        info!("Checkpoint request for {:?}", req);
+        let mut opts = CheckpointOptions::new();
+        if let Some(any) = req.options.as_ref() {
+            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
+            opts.merge_from(&mut input)
+                .map_err(other_error!(e, "failed to parse checkpoint options"))?;
+        }
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(req.id()).ok_or_else(|| {
+            Error::NotFoundError(format!("can not find container by id {}", req.id()))
+        })?;
+        container.checkpoint(req.path(), &opts)?;
+        self.send_event(TaskCheckpointed {
+            container_id: req.id.to_string(),
+            checkpoint: req.path.to_string(),
+            ..Default::default()
+        });
+        info!("Checkpoint request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id))
+    )]
     fn exec(&self, _ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
         let exec_id = req.exec_id().to_string();
         info!(
@@ -262,8 +377,15 @@ where
         Ok(Empty::new())
     }
 
-    fn close_io(&self, _ctx: &TtrpcContext, _req: CloseIORequest) -> TtrpcResult<Empty> {
-        // unnecessary close io here since fd was closed automatically after object was destroyed.
+    fn close_io(&self, _ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!("Close io request for {:?}", req);
+        if req.stdin {
+            let mut containers = self.containers.lock().unwrap();
+            let container = containers.get_mut(req.id()).ok_or_else(|| {
+                Error::NotFoundError(format!("can not find container by id {}", req.id()))
+            })?;
+            container.close_io(req.exec_id.as_str().none_if(|&x| x.is_empty()))?;
+        }
         Ok(Empty::new())
     }
 
@@ -337,11 +459,39 @@ where
         Ok(resp)
     }
 
-    fn shutdown(&self, _ctx: &TtrpcContext, _req: ShutdownRequest) -> TtrpcResult<Empty> {
+    fn shutdown(&self, _ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
         debug!("Shutdown request");
-        let containers = self.containers.lock().unwrap();
-        if containers.len() > 0 {
-            return Ok(Empty::new());
+        let mut containers = self.containers.lock().unwrap();
+        if !containers.is_empty() {
+            if !req.now {
+                return Ok(Empty::new());
+            }
+            // kill all the containers and wait for their init processes to exit,
+            // the lock is released while waiting so that exits can be processed.
+            // A container which could not be killed is not waited for, nor the others
+            // for longer than SHUTDOWN_WAIT_TIMEOUT.
+            let mut waits = vec![];
+            for (id, container) in containers.iter_mut() {
+                match container.kill(None, libc::SIGKILL as u32, true) {
+                    Ok(_) => waits.push((id.clone(), container.wait_channel(None)?)),
+                    Err(e) => warn!("failed to kill container {} on shutdown: {}", id, e),
+                }
+            }
+            drop(containers);
+            let deadline = Instant::now() + SHUTDOWN_WAIT_TIMEOUT;
+            for (id, rx) in waits {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(timeout) {
+                    warn!("container {} did not exit on shutdown", id);
+                }
+            }
+            containers = self.containers.lock().unwrap();
+            for (id, container) in containers.iter_mut() {
+                if let Err(e) = container.delete(None) {
+                    warn!("failed to delete container {} on shutdown: {}", id, e);
+                }
+            }
+            containers.clear();
         }
 
         self.shutdown.call_once(|| {
@@ -0,0 +1,79 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to scaffold OCI bundles that can be run by runc.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use oci_spec::runtime::Spec;
+
+use crate::error::Error;
+
+/// Name of the runtime spec file in a bundle.
+pub const CONFIG_FILE: &str = "config.json";
+/// Default name of the rootfs directory in a bundle.
+pub const ROOTFS_DIR: &str = "rootfs";
+
+/// Create the bundle directory with the rootfs directory referenced by `spec`,
+/// and write `spec` as its `config.json`. Returns the path of the rootfs directory.
+pub fn create_bundle<P: AsRef<Path>>(bundle: P, spec: &Spec) -> Result<PathBuf, Error> {
+    let bundle = bundle.as_ref();
+    let rootfs = spec
+        .root()
+        .as_ref()
+        .map(|root| root.path().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(ROOTFS_DIR));
+    // an absolute rootfs path is kept as is by join
+    let rootfs = bundle.join(rootfs);
+    fs::create_dir_all(&rootfs).map_err(Error::FileSystemError)?;
+    write_spec(bundle, spec)?;
+    Ok(rootfs)
+}
+
+/// Write `spec` as the `config.json` of the bundle.
+pub fn write_spec<P: AsRef<Path>>(bundle: P, spec: &Spec) -> Result<(), Error> {
+    let data = serde_json::to_vec_pretty(spec)?;
+    fs::write(bundle.as_ref().join(CONFIG_FILE), data).map_err(Error::SpecFileCreationFailed)
+}
+
+/// Read the `config.json` of the bundle.
+pub fn read_spec<P: AsRef<Path>>(bundle: P) -> Result<Spec, Error> {
+    let data = fs::read(bundle.as_ref().join(CONFIG_FILE)).map_err(Error::FileSystemError)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle");
+        let spec = Spec::default();
+
+        let rootfs = create_bundle(&bundle, &spec).expect("failed to create bundle");
+        assert!(rootfs.is_dir());
+        assert!(rootfs.starts_with(&bundle));
+        assert!(bundle.join(CONFIG_FILE).is_file());
+
+        let loaded = read_spec(&bundle).expect("failed to read spec");
+        assert_eq!(loaded.version(), spec.version());
+    }
+}
@@ -33,17 +33,73 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use serde::{Deserialize, Serialize};
 use time::{serde::timestamp, OffsetDateTime};
 
+use crate::error::Error;
+
+/// Status of a runc container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ContainerStatus {
+    Created,
+    Running,
+    Paused,
+    Stopped,
+    /// Any other status, e.g. `pausing` while the container is being frozen.
+    Unknown,
+}
+
+impl ContainerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerStatus::Created => "created",
+            ContainerStatus::Running => "running",
+            ContainerStatus::Paused => "paused",
+            ContainerStatus::Stopped => "stopped",
+            ContainerStatus::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<&str> for ContainerStatus {
+    fn from(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "created" => ContainerStatus::Created,
+            "running" => ContainerStatus::Running,
+            "paused" => ContainerStatus::Paused,
+            "stopped" => ContainerStatus::Stopped,
+            _ => ContainerStatus::Unknown,
+        }
+    }
+}
+
+impl From<String> for ContainerStatus {
+    fn from(s: String) -> Self {
+        Self::from(s.as_str())
+    }
+}
+
+impl From<ContainerStatus> for String {
+    fn from(status: ContainerStatus) -> Self {
+        status.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ContainerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Information for runc container
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Container {
     pub id: String,
     pub pid: usize,
-    pub status: String,
+    pub status: ContainerStatus,
     pub bundle: String,
     pub rootfs: String,
     #[serde(with = "timestamp")]
@@ -51,10 +107,91 @@ pub struct Container {
     pub annotations: HashMap<String, String>,
 }
 
+/// Processes of a container as listed by `runc ps` in the table format.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TopResults {
+    /// Column names of the table, e.g. `UID`, `PID` and `CMD`.
+    pub headers: Vec<String>,
+    /// A row of fields for each process, in the order of the headers.
+    pub processes: Vec<Vec<String>>,
+}
+
+impl TopResults {
+    /// Parse the table output of `runc ps`.
+    ///
+    /// The last column holds the command line, which may contain spaces.
+    /// Rows without a pid, i.e. the threads listed by `ps -L`, are skipped.
+    pub fn parse(output: &str) -> Result<Self, Error> {
+        let mut lines = output.lines();
+        let headers: Vec<String> = lines
+            .next()
+            .ok_or(Error::TopShortResponseError)?
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        let pid_index = headers
+            .iter()
+            .position(|h| h == "PID")
+            .ok_or(Error::TopMissingPidHeader)?;
+
+        let mut processes = vec![];
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < headers.len() {
+                return Err(Error::TopShortResponseError);
+            }
+            if fields[pid_index] == "-" {
+                continue;
+            }
+            let last = headers.len() - 1;
+            let mut process: Vec<String> = fields[..last].iter().map(|f| f.to_string()).collect();
+            process.push(fields[last..].join(" "));
+            processes.push(process);
+        }
+        Ok(Self { headers, processes })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn top_results_test() {
+        let output = "UID   PID  PPID  C STIME TTY  TIME     CMD
+root  1234 1200  0 10:00 ?    00:00:00 sleep infinity
+root  -    -     0 10:00 -    00:00:00 -
+";
+        let top = TopResults::parse(output).unwrap();
+        assert_eq!(top.headers.len(), 8);
+        assert_eq!(top.processes.len(), 1);
+        assert_eq!(top.processes[0][1], "1234");
+        assert_eq!(top.processes[0][7], "sleep infinity");
+
+        assert!(matches!(
+            TopResults::parse("UID CMD\nroot sleep\n"),
+            Err(Error::TopMissingPidHeader)
+        ));
+        assert!(matches!(
+            TopResults::parse("UID PID CMD\nroot 1234\n"),
+            Err(Error::TopShortResponseError)
+        ));
+        assert!(matches!(
+            TopResults::parse(""),
+            Err(Error::TopShortResponseError)
+        ));
+    }
+
+    #[test]
+    fn container_status_test() {
+        assert_eq!(ContainerStatus::from("paused"), ContainerStatus::Paused);
+        assert_eq!(ContainerStatus::from("pausing"), ContainerStatus::Unknown);
+        assert_eq!(
+            serde_json::to_string(&ContainerStatus::Stopped).unwrap(),
+            "\"stopped\""
+        );
+    }
+
     #[test]
     fn serde_test() {
         let j = r#"
@@ -73,7 +210,7 @@ mod tests {
         let c: Container = serde_json::from_str(j).unwrap();
         assert_eq!(c.id, "fake");
         assert_eq!(c.pid, 1000);
-        assert_eq!(c.status, "RUNNING");
+        assert_eq!(c.status, ContainerStatus::Running);
         assert_eq!(c.bundle, "/path/to/bundle");
         assert_eq!(c.rootfs, "/path/to/rootfs");
         assert_eq!(
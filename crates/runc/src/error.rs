@@ -57,11 +57,16 @@ pub enum Error {
     #[error("Error occured in runc: {0}")]
     InvalidCommand(io::Error),
 
-    #[error("Runc command failed: status={status}, stdout=\"{stdout}\", stderr=\"{stderr}\"")]
+    #[error(
+        "Runc command failed: status={status}, stdout=\"{stdout}\", stderr=\"{stderr}\", log=\"{}\"",
+        .log.as_deref().unwrap_or_default()
+    )]
     CommandFailed {
         status: ExitStatus,
         stdout: String,
         stderr: String,
+        /// Last error message found in the runc log file, if any.
+        log: Option<String>,
     },
 
     #[error("Runc IO unavailable: {0}")]
@@ -77,6 +82,9 @@ pub enum Error {
     #[error("Unable to locate the runc")]
     NotFound,
 
+    #[error("Runc version {found} is older than the required {min}")]
+    IncompatibleVersion { found: String, min: String },
+
     #[error("Error occurs with fs: {0}")]
     FileSystemError(io::Error),
 
@@ -121,4 +129,235 @@ pub enum Error {
 
     #[error("Failed to create dir: {0}")]
     CreateDir(nix::Error),
+
+    #[error("Failed to kill process group: {0}")]
+    KillProcessGroup(nix::Error),
+
+    #[error("Invalid process spec: {0}")]
+    InvalidProcessSpec(String),
+
+    #[error("Failed to set child subreaper: {0}")]
+    SetSubreaper(nix::Error),
+
+    #[error("Invalid container id {id:?}: {reason}")]
+    InvalidId { id: String, reason: String },
+
+    /// The status and stderr are those of the runc command the error comes from, if any.
+    #[error("Container not found: {msg}")]
+    ContainerNotFound {
+        msg: String,
+        status: Option<ExitStatus>,
+        stderr: String,
+    },
+
+    #[error("Container already exists: {msg}")]
+    ContainerExists {
+        msg: String,
+        status: Option<ExitStatus>,
+        stderr: String,
+    },
+
+    #[error("Permission denied in runc: {msg}")]
+    PermissionDenied {
+        msg: String,
+        status: Option<ExitStatus>,
+        stderr: String,
+    },
+
+    #[error("Runc panicked: {msg}")]
+    RuntimePanic {
+        msg: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("The {phase} hook {path} failed: {reason}")]
+    HookFailed {
+        phase: String,
+        path: String,
+        reason: String,
+    },
+
+    #[error("The {phase} hook {path} timed out after {timeout:?}")]
+    HookTimeout {
+        phase: String,
+        path: String,
+        timeout: std::time::Duration,
+    },
+}
+
+/// Exit status of a Go program on an unrecovered panic.
+const GO_PANIC_STATUS: i32 = 2;
+
+/// Errors of runc for a missing container, of runc 1.1 and of the runc versions before it,
+/// which quote the id as in `container "abc" does not exist`.
+fn is_not_exist_message(msg: &str) -> bool {
+    msg.contains("container does not exist")
+        || (msg.contains("container \"") && msg.contains("\" does not exist"))
+}
+
+/// Errors of runc for an existing container, of runc 1.1 and of the runc versions before it.
+fn is_exist_message(msg: &str) -> bool {
+    msg.contains("container with given ID already exists")
+        || msg.contains("container with id exists")
+}
+
+/// Text of the `EACCES` and `EPERM` errors of the syscalls made by runc.
+fn is_permission_message(msg: &str) -> bool {
+    msg.contains("permission denied") || msg.contains("operation not permitted")
+}
+
+/// Failures worth retrying, `EBUSY` and `EAGAIN` of the syscalls made by runc.
+fn is_transient_message(msg: &str) -> bool {
+    msg.contains("device or resource busy") || msg.contains("resource temporarily unavailable")
+}
+
+impl Error {
+    /// Return the error of a runc command which exited with `status`.
+    ///
+    /// The failures runc reports with well known messages are returned as their own variants
+    /// holding the message of runc, taken from its log when there is one, along with its
+    /// status and stderr, and all the others as [`Error::CommandFailed`].
+    pub fn from_command_failure(
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+        log: Option<String>,
+    ) -> Self {
+        if status.code() == Some(GO_PANIC_STATUS) {
+            if let Some(panic) = stderr.lines().find(|l| l.starts_with("panic: ")) {
+                return Error::RuntimePanic {
+                    msg: panic.trim_start_matches("panic: ").to_string(),
+                    status,
+                    stderr,
+                };
+            }
+        }
+        let msg = log
+            .as_deref()
+            .filter(|log| !log.is_empty())
+            .unwrap_or_else(|| stderr.trim())
+            .to_string();
+        if is_not_exist_message(&msg) {
+            Error::ContainerNotFound {
+                msg,
+                status: Some(status),
+                stderr,
+            }
+        } else if is_exist_message(&msg) {
+            Error::ContainerExists {
+                msg,
+                status: Some(status),
+                stderr,
+            }
+        } else if is_permission_message(&msg) {
+            Error::PermissionDenied {
+                msg,
+                status: Some(status),
+                stderr,
+            }
+        } else {
+            Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+                log,
+            }
+        }
+    }
+
+    /// Whether the error is a transient failure worth retrying, such as `EBUSY` when
+    /// deleting a container or unmounting its rootfs right after it has been killed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::CommandFailed { stderr, log, .. } => {
+                is_transient_message(stderr) || log.as_deref().map_or(false, is_transient_message)
+            }
+            Error::ContainerNotFound { msg, stderr, .. }
+            | Error::ContainerExists { msg, stderr, .. }
+            | Error::PermissionDenied { msg, stderr, .. } => {
+                is_transient_message(msg) || is_transient_message(stderr)
+            }
+            Error::FileSystemError(e) => {
+                matches!(e.raw_os_error(), Some(libc::EBUSY) | Some(libc::EAGAIN))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn failure(code: i32, stderr: &str, log: Option<&str>) -> Error {
+        Error::from_command_failure(
+            ExitStatus::from_raw(code << 8),
+            String::new(),
+            stderr.to_string(),
+            log.map(String::from),
+        )
+    }
+
+    #[test]
+    fn test_from_command_failure() {
+        assert!(matches!(
+            failure(1, "container does not exist", None),
+            Error::ContainerNotFound { msg, status: Some(_), .. } if msg == "container does not exist"
+        ));
+        assert!(matches!(
+            failure(1, "container \"abc\" does not exist", None),
+            Error::ContainerNotFound { .. }
+        ));
+        assert!(matches!(
+            failure(1, "", Some("container with given ID already exists")),
+            Error::ContainerExists { .. }
+        ));
+        assert!(matches!(
+            failure(1, "container with id exists: abc", None),
+            Error::ContainerExists { .. }
+        ));
+        assert!(matches!(
+            failure(1, "open /run/runc/id: permission denied", None),
+            Error::PermissionDenied { .. }
+        ));
+        assert!(matches!(
+            failure(2, "panic: runtime error: nil pointer\n\ngoroutine 1 [running]:", None),
+            Error::RuntimePanic { msg, .. } if msg == "runtime error: nil pointer"
+        ));
+        assert!(matches!(
+            failure(1, "container not running", None),
+            Error::CommandFailed { .. }
+        ));
+        // only the messages of runc are matched, not any file which does not exist
+        assert!(matches!(
+            failure(
+                1,
+                "open /run/runc/abc/state.json: file does not exist",
+                None
+            ),
+            Error::CommandFailed { .. }
+        ));
+        assert!(matches!(
+            failure(1, "volume already exists", None),
+            Error::CommandFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(failure(1, "unlinkat /run/runc/abc: device or resource busy", None).is_transient());
+        assert!(!failure(1, "container not running", None).is_transient());
+        // the stderr is kept in the typed variants
+        let err = failure(
+            1,
+            "rmdir /sys/fs/cgroup/abc: device or resource busy",
+            Some("remove /run/runc/abc: permission denied"),
+        );
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+        assert!(err.is_transient());
+        assert!(!failure(1, "container does not exist", None).is_transient());
+    }
 }
@@ -34,8 +34,24 @@
  */
 
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+#[cfg(feature = "async")]
+use futures::{ready, Stream};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    process::{Child, ChildStdout},
+};
+
+#[cfg(feature = "async")]
+use crate::{error::Error, Result};
 
 /// Event type generated by runc
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,15 +72,78 @@ pub struct Event {
     pub stats: Option<Stats>,
 }
 
+/// Stream of events emitted by `runc events`
+///
+/// Each line printed by runc is parsed into an [`Event`]. The underlying runc process
+/// is killed when the stream is dropped.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct EventStream {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+#[cfg(feature = "async")]
+impl EventStream {
+    pub(crate) fn new(mut child: Child) -> Result<Self> {
+        let stdout = child.stdout.take().ok_or_else(|| {
+            Error::UnavailableIO(io::Error::new(
+                io::ErrorKind::NotFound,
+                "stdout of runc events is not piped",
+            ))
+        })?;
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Return the pid of the underlying runc process, if it is still running
+    pub fn pid(&self) -> Option<u32> {
+        self.child.id()
+    }
+}
+
+#[cfg(feature = "async")]
+impl Stream for EventStream {
+    type Item = Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let line = match ready!(Pin::new(&mut self.lines).poll_next_line(cx)) {
+                Ok(Some(line)) => line,
+                Ok(None) => return Poll::Ready(None),
+                Err(e) => return Poll::Ready(Some(Err(Error::InvalidCommand(e)))),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Poll::Ready(Some(
+                serde_json::from_str(&line).map_err(Error::JsonDeserializationFailed),
+            ));
+        }
+    }
+}
+
+/// Statistics of the cgroups of a container, as reported by `runc events --stats`.
+///
+/// The sections introduced by later runc versions default to empty when missing.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
     pub cpu: Cpu,
+    #[serde(default, rename = "cpuset")]
+    pub cpu_set: CpuSet,
     pub memory: Memory,
     pub pids: Pids,
     #[serde(rename = "blkio")]
     pub block_io: BlkIO,
+    /// Usage of the huge pages, by page size
     #[serde(rename = "hugetlb")]
-    pub huge_tlb: HugeTLB,
+    pub huge_tlb: Option<HashMap<String, HugeTLB>>,
+    #[serde(default)]
+    pub intel_rdt: IntelRdt,
+    #[serde(default)]
+    pub network_interfaces: Option<Vec<NetworkInterface>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -137,7 +216,14 @@ pub struct CpuUsage {
     /// Total CPU time consumed
     pub total: Option<u64>,
     /// Total CPU time consumed per core
+    #[serde(rename = "percpu")]
     pub per_cpu: Option<Vec<u64>>,
+    /// CPU time consumed per core in kernel mode
+    #[serde(rename = "percpu_kernel")]
+    pub per_cpu_kernel: Option<Vec<u64>>,
+    /// CPU time consumed per core in user mode
+    #[serde(rename = "percpu_user")]
+    pub per_cpu_user: Option<Vec<u64>>,
     /// Total CPU time consumed in kernel mode
     pub kernel: u64,
     /// Total CPU time consumed in user mode
@@ -146,10 +232,27 @@ pub struct CpuUsage {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cpu {
-    pub usage: Option<u64>,
+    pub usage: Option<CpuUsage>,
     pub throttling: Option<Throttling>,
 }
 
+/// Settings of the cpuset cgroup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CpuSet {
+    pub cpus: Option<Vec<u16>>,
+    pub cpu_exclusive: u64,
+    pub mems: Option<Vec<u16>>,
+    pub mem_hardwall: u64,
+    pub mem_exclusive: u64,
+    pub memory_migrate: u64,
+    pub memory_spread_page: u64,
+    pub memory_spread_slab: u64,
+    pub memory_pressure: u64,
+    pub sched_load_balance: u64,
+    pub sched_relax_domain_level: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryEntry {
     /// Memory limit in bytes
@@ -171,6 +274,9 @@ pub struct Memory {
     pub usage: Option<MemoryEntry>,
     /// Overall memory usage, including swap
     pub swap: Option<MemoryEntry>,
+    /// Swap usage only, on cgroup v2
+    #[serde(rename = "swapOnlyUsage")]
+    pub swap_only_usage: Option<MemoryEntry>,
     /// Kernel usage of memory
     pub kernel: Option<MemoryEntry>,
     /// Kernel TCP of memory
@@ -179,3 +285,332 @@ pub struct Memory {
     /// Raw stats of memory
     pub raw: Option<HashMap<String, u64>>,
 }
+
+/// Statistics of Intel RDT, set when the container is in a resctrl group
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntelRdt {
+    pub l3_cache_info: Option<L3CacheInfo>,
+    pub l3_cache_schema_root: Option<String>,
+    pub l3_cache_schema: Option<String>,
+    pub mem_bw_info: Option<MemBwInfo>,
+    pub mem_bw_schema_root: Option<String>,
+    pub mem_bw_schema: Option<String>,
+    /// Memory bandwidth monitoring, per NUMA node
+    pub mbm_stats: Option<Vec<MbmNumaNodeStats>>,
+    /// Cache monitoring technology, per NUMA node
+    pub cmt_stats: Option<Vec<CmtNumaNodeStats>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct L3CacheInfo {
+    pub cbm_mask: Option<String>,
+    pub min_cbm_bits: Option<u64>,
+    pub num_closids: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemBwInfo {
+    pub bandwidth_gran: Option<u64>,
+    pub delay_linear: Option<u64>,
+    pub min_bandwidth: Option<u64>,
+    pub num_closids: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MbmNumaNodeStats {
+    /// Total bytes of memory traffic
+    pub mbm_total_bytes: u64,
+    /// Bytes of memory traffic to the local NUMA node
+    pub mbm_local_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CmtNumaNodeStats {
+    /// Bytes of the last level cache in use
+    pub llc_occupancy: u64,
+}
+
+/// Counters of a network interface of the container
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// Conversions of the stats of runc into the cgroup metrics of containerd.
+///
+/// The memory stats are taken from the raw cgroup v1 `memory.stat` keys, and the settings
+/// of cpuset and Intel RDT are left out as the metrics have no counterpart for them.
+#[cfg(feature = "protos")]
+mod metrics {
+    use containerd_shim_protos::cgroups::metrics as pb;
+
+    use super::*;
+
+    impl From<&Stats> for pb::Metrics {
+        fn from(stats: &Stats) -> Self {
+            let mut huge_tlb: Vec<_> = stats.huge_tlb.iter().flatten().collect();
+            huge_tlb.sort_by(|a, b| a.0.cmp(b.0));
+
+            let mut metrics = pb::Metrics::new();
+            metrics.set_cpu(cpu_stat(&stats.cpu));
+            metrics.set_memory(memory_stat(&stats.memory));
+            metrics.set_pids(pids_stat(&stats.pids));
+            metrics.set_blkio(blkio_stat(&stats.block_io));
+            metrics.set_hugetlb(
+                huge_tlb
+                    .into_iter()
+                    .map(|(pagesize, h)| hugetlb_stat(pagesize, h))
+                    .collect(),
+            );
+            metrics.set_network(
+                stats
+                    .network_interfaces
+                    .iter()
+                    .flatten()
+                    .map(network_stat)
+                    .collect(),
+            );
+            metrics
+        }
+    }
+
+    impl From<Stats> for pb::Metrics {
+        fn from(stats: Stats) -> Self {
+            Self::from(&stats)
+        }
+    }
+
+    fn cpu_stat(cpu: &Cpu) -> pb::CPUStat {
+        let mut stat = pb::CPUStat::new();
+        if let Some(usage) = &cpu.usage {
+            let mut cpu_usage = pb::CPUUsage::new();
+            cpu_usage.set_total(usage.total.unwrap_or_default());
+            cpu_usage.set_kernel(usage.kernel);
+            cpu_usage.set_user(usage.user);
+            cpu_usage.set_per_cpu(usage.per_cpu.clone().unwrap_or_default());
+            stat.set_usage(cpu_usage);
+        }
+        if let Some(throttling) = &cpu.throttling {
+            let mut throttle = pb::Throttle::new();
+            throttle.set_periods(throttling.periods.unwrap_or_default());
+            throttle.set_throttled_periods(throttling.throtted_periods.unwrap_or_default());
+            throttle.set_throttled_time(throttling.throtted_time.unwrap_or_default());
+            stat.set_throttling(throttle);
+        }
+        stat
+    }
+
+    fn memory_entry(entry: &MemoryEntry) -> pb::MemoryEntry {
+        let mut e = pb::MemoryEntry::new();
+        e.set_limit(entry.limit);
+        e.set_usage(entry.usage.unwrap_or_default());
+        e.set_max(entry.max.unwrap_or_default());
+        e.set_failcnt(entry.fail_count);
+        e
+    }
+
+    fn memory_stat(memory: &Memory) -> pb::MemoryStat {
+        let raw = |key: &str| {
+            memory
+                .raw
+                .as_ref()
+                .and_then(|raw| raw.get(key))
+                .copied()
+                .unwrap_or_default()
+        };
+        let mut stat = pb::MemoryStat::new();
+        stat.set_cache(memory.cache.unwrap_or_else(|| raw("cache")));
+        stat.set_rss(raw("rss"));
+        stat.set_rss_huge(raw("rss_huge"));
+        stat.set_mapped_file(raw("mapped_file"));
+        stat.set_dirty(raw("dirty"));
+        stat.set_writeback(raw("writeback"));
+        stat.set_pg_pg_in(raw("pgpgin"));
+        stat.set_pg_pg_out(raw("pgpgout"));
+        stat.set_pg_fault(raw("pgfault"));
+        stat.set_pg_maj_fault(raw("pgmajfault"));
+        stat.set_inactive_anon(raw("inactive_anon"));
+        stat.set_active_anon(raw("active_anon"));
+        stat.set_inactive_file(raw("inactive_file"));
+        stat.set_active_file(raw("active_file"));
+        stat.set_unevictable(raw("unevictable"));
+        stat.set_hierarchical_memory_limit(raw("hierarchical_memory_limit"));
+        stat.set_hierarchical_swap_limit(raw("hierarchical_memsw_limit"));
+        stat.set_total_cache(raw("total_cache"));
+        stat.set_total_rss(raw("total_rss"));
+        stat.set_total_rss_huge(raw("total_rss_huge"));
+        stat.set_total_mapped_file(raw("total_mapped_file"));
+        stat.set_total_dirty(raw("total_dirty"));
+        stat.set_total_writeback(raw("total_writeback"));
+        stat.set_total_pg_pg_in(raw("total_pgpgin"));
+        stat.set_total_pg_pg_out(raw("total_pgpgout"));
+        stat.set_total_pg_fault(raw("total_pgfault"));
+        stat.set_total_pg_maj_fault(raw("total_pgmajfault"));
+        stat.set_total_inactive_anon(raw("total_inactive_anon"));
+        stat.set_total_active_anon(raw("total_active_anon"));
+        stat.set_total_inactive_file(raw("total_inactive_file"));
+        stat.set_total_active_file(raw("total_active_file"));
+        stat.set_total_unevictable(raw("total_unevictable"));
+        if let Some(usage) = &memory.usage {
+            stat.set_usage(memory_entry(usage));
+        }
+        if let Some(swap) = &memory.swap {
+            stat.set_swap(memory_entry(swap));
+        }
+        if let Some(kernel) = &memory.kernel {
+            stat.set_kernel(memory_entry(kernel));
+        }
+        if let Some(kernel_tcp) = &memory.kernel_tcp {
+            stat.set_kernel_tcp(memory_entry(kernel_tcp));
+        }
+        stat
+    }
+
+    fn pids_stat(pids: &Pids) -> pb::PidsStat {
+        let mut stat = pb::PidsStat::new();
+        stat.set_current(pids.current.unwrap_or_default());
+        stat.set_limit(pids.limit.unwrap_or_default());
+        stat
+    }
+
+    fn blkio_entries(entries: &Option<Vec<BlkIOEntry>>) -> Vec<pb::BlkIOEntry> {
+        entries
+            .iter()
+            .flatten()
+            .map(|entry| {
+                let mut e = pb::BlkIOEntry::new();
+                e.set_major(entry.major.unwrap_or_default());
+                e.set_minor(entry.minor.unwrap_or_default());
+                e.set_op(entry.op.clone().unwrap_or_default());
+                e.set_value(entry.value.unwrap_or_default());
+                e
+            })
+            .collect()
+    }
+
+    fn blkio_stat(blkio: &BlkIO) -> pb::BlkIOStat {
+        let mut stat = pb::BlkIOStat::new();
+        stat.set_io_service_bytes_recursive(blkio_entries(&blkio.io_service_bytes_recursive));
+        stat.set_io_serviced_recursive(blkio_entries(&blkio.io_serviced_recursive));
+        stat.set_io_queued_recursive(blkio_entries(&blkio.io_queued_recursive));
+        stat.set_io_service_time_recursive(blkio_entries(&blkio.io_service_time_recursive));
+        stat.set_io_wait_time_recursive(blkio_entries(&blkio.io_wait_time_recursive));
+        stat.set_io_merged_recursive(blkio_entries(&blkio.io_merged_recursive));
+        stat.set_io_time_recursive(blkio_entries(&blkio.io_time_recursive));
+        stat.set_sectors_recursive(blkio_entries(&blkio.sectors_recursive));
+        stat
+    }
+
+    fn hugetlb_stat(pagesize: &str, huge_tlb: &HugeTLB) -> pb::HugetlbStat {
+        let mut stat = pb::HugetlbStat::new();
+        stat.set_usage(huge_tlb.usage.unwrap_or_default());
+        stat.set_max(huge_tlb.max.unwrap_or_default());
+        stat.set_failcnt(huge_tlb.fail_count);
+        stat.set_pagesize(pagesize.to_string());
+        stat
+    }
+
+    fn network_stat(interface: &NetworkInterface) -> pb::NetworkStat {
+        let mut stat = pb::NetworkStat::new();
+        stat.set_name(interface.name.clone());
+        stat.set_rx_bytes(interface.rx_bytes);
+        stat.set_rx_packets(interface.rx_packets);
+        stat.set_rx_errors(interface.rx_errors);
+        stat.set_rx_dropped(interface.rx_dropped);
+        stat.set_tx_bytes(interface.tx_bytes);
+        stat.set_tx_packets(interface.tx_packets);
+        stat.set_tx_errors(interface.tx_errors);
+        stat.set_tx_dropped(interface.tx_dropped);
+        stat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS_EVENT: &str = r#"{"type":"stats","id":"fake-id","data":{
+        "cpu":{"usage":{"total":3000,"percpu":[1000,2000],"kernel":1200,"user":1800},
+               "throttling":{"periods":10,"throttledPeriods":2,"throttledTime":500}},
+        "cpuset":{"cpus":[0,1],"mems":[0],"cpu_exclusive":0,"mem_hardwall":0,
+                  "mem_exclusive":0,"memory_migrate":0,"memory_spread_page":0,
+                  "memory_spread_slab":0,"memory_pressure":0,"sched_load_balance":1,
+                  "sched_relax_domain_level":-1},
+        "memory":{"cache":4096,"usage":{"limit":1048576,"usage":8192,"max":16384,"failcnt":0},
+                  "swap":{"limit":0,"failcnt":0},"kernel":{"limit":0,"failcnt":0},
+                  "kernelTCP":{"limit":0,"failcnt":0},"raw":{"rss":2048,"pgfault":7}},
+        "pids":{"current":3,"limit":100},
+        "blkio":{"ioServiceBytesRecursive":[{"major":8,"minor":0,"op":"Read","value":512}]},
+        "hugetlb":{"2MB":{"usage":0,"failcnt":1},"1GB":{"failcnt":0}},
+        "intel_rdt":{"l3_cache_schema":"L3:0=ff","mbm_stats":[{"mbm_total_bytes":10,"mbm_local_bytes":5}]},
+        "network_interfaces":[{"name":"eth0","rx_bytes":100,"rx_packets":1,"rx_errors":0,
+            "rx_dropped":0,"tx_bytes":200,"tx_packets":2,"tx_errors":0,"tx_dropped":0}]}}"#;
+
+    #[test]
+    fn stats_event_test() {
+        let event: Event = serde_json::from_str(STATS_EVENT).unwrap();
+        assert_eq!(event.id, "fake-id");
+        let stats = event.stats.unwrap();
+        let usage = stats.cpu.usage.as_ref().unwrap();
+        assert_eq!(usage.total, Some(3000));
+        assert_eq!(usage.per_cpu, Some(vec![1000, 2000]));
+        assert_eq!(stats.cpu_set.cpus, Some(vec![0, 1]));
+        assert_eq!(stats.cpu_set.sched_relax_domain_level, -1);
+        assert_eq!(stats.memory.usage.as_ref().unwrap().usage, Some(8192));
+        assert_eq!(stats.huge_tlb.as_ref().unwrap()["2MB"].fail_count, 1);
+        assert_eq!(stats.intel_rdt.l3_cache_schema.as_deref(), Some("L3:0=ff"));
+        assert_eq!(
+            stats.intel_rdt.mbm_stats.as_ref().unwrap()[0].mbm_total_bytes,
+            10
+        );
+        assert_eq!(stats.network_interfaces.as_ref().unwrap()[0].name, "eth0");
+
+        let event: Event = serde_json::from_str(r#"{"type":"oom","id":"fake-id"}"#).unwrap();
+        assert!(matches!(event.event_type, EventType::Oom));
+        assert!(event.stats.is_none());
+
+        // the sections of newer runc versions are optional
+        let event: Event = serde_json::from_str(
+            r#"{"type":"stats","id":"fake-id","data":{"cpu":{},"memory":{},"pids":{},"blkio":{},"hugetlb":null}}"#,
+        )
+        .unwrap();
+        assert!(event.stats.unwrap().intel_rdt.mbm_stats.is_none());
+    }
+
+    #[cfg(feature = "protos")]
+    #[test]
+    fn stats_to_metrics_test() {
+        use containerd_shim_protos::cgroups::metrics::Metrics;
+
+        let event: Event = serde_json::from_str(STATS_EVENT).unwrap();
+        let metrics = Metrics::from(event.stats.unwrap());
+        assert_eq!(metrics.cpu.usage.total, 3000);
+        assert_eq!(metrics.cpu.usage.per_cpu, vec![1000, 2000]);
+        assert_eq!(metrics.cpu.throttling.throttled_periods, 2);
+        assert_eq!(metrics.memory.cache, 4096);
+        assert_eq!(metrics.memory.rss, 2048);
+        assert_eq!(metrics.memory.pg_fault, 7);
+        assert_eq!(metrics.memory.usage.limit, 1048576);
+        assert_eq!(metrics.pids.current, 3);
+        assert_eq!(metrics.blkio.io_service_bytes_recursive[0].value, 512);
+        let pagesizes: Vec<_> = metrics
+            .hugetlb
+            .iter()
+            .map(|h| h.pagesize.as_str())
+            .collect();
+        assert_eq!(pagesizes, vec!["1GB", "2MB"]);
+        assert_eq!(metrics.network[0].tx_bytes, 200);
+    }
+}
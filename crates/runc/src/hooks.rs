@@ -0,0 +1,282 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Runs the OCI hooks of a bundle, for containers which are not run by runc,
+//! as runc runs them itself.
+//!
+//! See <https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-platform-hooks>
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use oci_spec::runtime::{Hook, Spec};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Interval between the checks of a running hook.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Bytes of the stderr of a hook kept for its error, the rest is drained and dropped.
+const HOOK_STDERR_LIMIT: usize = 4096;
+
+/// Point of the container lifecycle a hook runs at.
+///
+/// The `createContainer` and `startContainer` hooks are left out,
+/// as they run in the namespaces of the container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+    Prestart,
+    CreateRuntime,
+    Poststart,
+    Poststop,
+}
+
+impl HookPhase {
+    /// Whether a failed hook fails the lifecycle operation, the failures of the hooks
+    /// run after the container started or stopped are only logged.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, HookPhase::Prestart | HookPhase::CreateRuntime)
+    }
+}
+
+impl fmt::Display for HookPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HookPhase::Prestart => "prestart",
+            HookPhase::CreateRuntime => "createRuntime",
+            HookPhase::Poststart => "poststart",
+            HookPhase::Poststop => "poststop",
+        };
+        f.write_str(name)
+    }
+}
+
+/// State of the container passed to the hooks on their stdin.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookState {
+    pub oci_version: String,
+    pub id: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    pub bundle: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// Run the hooks of `spec` for `phase` in order.
+///
+/// The first failure of a [fatal](HookPhase::is_fatal) phase stops the run and is returned,
+/// the failures of the other phases are logged and the next hooks still run.
+pub fn run_hooks(spec: &Spec, phase: HookPhase, state: &HookState) -> Result<(), Error> {
+    let hooks = match spec.hooks() {
+        Some(hooks) => match phase {
+            HookPhase::Prestart => hooks.prestart(),
+            HookPhase::CreateRuntime => hooks.create_runtime(),
+            HookPhase::Poststart => hooks.poststart(),
+            HookPhase::Poststop => hooks.poststop(),
+        },
+        None => return Ok(()),
+    };
+    let state = serde_json::to_vec(state)?;
+    for hook in hooks.iter().flatten() {
+        match run_hook(hook, phase, &state) {
+            Err(e) if !phase.is_fatal() => warn!("{}", e),
+            res => res?,
+        }
+    }
+    Ok(())
+}
+
+/// Run a single hook with `state` on its stdin, killing it when it exceeds its timeout.
+pub fn run_hook(hook: &Hook, phase: HookPhase, state: &[u8]) -> Result<(), Error> {
+    let path = hook.path().display().to_string();
+    let failed = |reason: String| Error::HookFailed {
+        phase: phase.to_string(),
+        path: path.clone(),
+        reason,
+    };
+
+    let mut cmd = Command::new(hook.path());
+    // args holds argv[0] first, as in execve(2)
+    if let Some(args) = hook.args() {
+        if let Some((arg0, args)) = args.split_first() {
+            cmd.arg0(arg0).args(args);
+        }
+    }
+    cmd.env_clear();
+    for kv in hook.env().iter().flatten() {
+        if let Some((key, value)) = kv.split_once('=') {
+            cmd.env(key, value);
+        }
+    }
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().map_err(|e| failed(e.to_string()))?;
+    // the state is written and stderr is drained on their own threads, so that neither a hook
+    // not reading its state nor one writing more than a pipe buffer escapes the timeout
+    if let Some(mut stdin) = child.stdin.take() {
+        let state = state.to_vec();
+        let path = path.clone();
+        thread::spawn(move || {
+            // a hook is free not to read its state
+            if let Err(e) = stdin.write_all(&state) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    warn!("failed to write state to hook {}: {}", path, e);
+                }
+            }
+        });
+    }
+    let stderr = child.stderr.take().map(|mut err| {
+        thread::spawn(move || {
+            let mut kept = Vec::new();
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = err.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let n = n.min(HOOK_STDERR_LIMIT - kept.len());
+                kept.extend_from_slice(&buf[..n]);
+            }
+            String::from_utf8_lossy(&kept).to_string()
+        })
+    });
+
+    let timeout = hook
+        .timeout()
+        .filter(|t| *t > 0)
+        .map(|t| Duration::from_secs(t as u64));
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| failed(e.to_string()))? {
+            break status;
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::HookTimeout {
+                    phase: phase.to_string(),
+                    path,
+                    timeout,
+                });
+            }
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    };
+    if status.success() {
+        return Ok(());
+    }
+    let stderr = stderr
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    Err(failed(format!("{}, stderr: {}", status, stderr.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{HookBuilder, HooksBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn sh_hook(script: &str, timeout: Option<i64>) -> Hook {
+        let mut builder = HookBuilder::default()
+            .path("/bin/sh")
+            .args(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+            .env(vec!["PATH=/usr/bin:/bin".to_string()]);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_run_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("state.json");
+        let spec = SpecBuilder::default()
+            .hooks(
+                HooksBuilder::default()
+                    .prestart(vec![sh_hook(&format!("cat > {}", out.display()), None)])
+                    .poststop(vec![sh_hook("exit 1", None)])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let state = HookState {
+            id: "fake-id".to_string(),
+            status: "created".to_string(),
+            pid: Some(42),
+            ..Default::default()
+        };
+
+        run_hooks(&spec, HookPhase::Prestart, &state).expect("prestart failed");
+        let written: HookState =
+            serde_json::from_slice(&std::fs::read(&out).unwrap()).expect("invalid state");
+        assert_eq!(written.id, "fake-id");
+        assert_eq!(written.pid, Some(42));
+
+        // poststop failures are only logged
+        run_hooks(&spec, HookPhase::Poststop, &state).expect("poststop failed");
+        run_hooks(&spec, HookPhase::Poststart, &state).expect("poststart failed");
+    }
+
+    #[test]
+    fn test_run_hook_failure() {
+        let hook = sh_hook("echo denied >&2; exit 3", None);
+        match run_hook(&hook, HookPhase::CreateRuntime, b"{}") {
+            Err(Error::HookFailed { phase, reason, .. }) => {
+                assert_eq!(phase, "createRuntime");
+                assert!(reason.contains("denied"));
+            }
+            res => panic!("unexpected hook result: {:?}", res),
+        }
+
+        // more stderr than a pipe buffer holds
+        let hook = sh_hook("head -c 100000 /dev/zero | tr '\\0' x >&2; exit 1", Some(5));
+        match run_hook(&hook, HookPhase::CreateRuntime, b"{}") {
+            Err(Error::HookFailed { reason, .. }) => assert!(reason.contains("xxx")),
+            res => panic!("unexpected hook result: {:?}", res),
+        }
+
+        // neither reading its state nor exiting
+        let state = vec![b' '; 1 << 20];
+        let hook = sh_hook("sleep 10", Some(1));
+        match run_hook(&hook, HookPhase::Prestart, &state) {
+            Err(Error::HookTimeout { .. }) => {}
+            res => panic!("unexpected hook result: {:?}", res),
+        }
+
+        let hook = sh_hook("sleep 10", Some(1));
+        match run_hook(&hook, HookPhase::Prestart, b"{}") {
+            Err(Error::HookTimeout { timeout, .. }) => assert_eq!(timeout, Duration::from_secs(1)),
+            res => panic!("unexpected hook result: {:?}", res),
+        }
+    }
+}
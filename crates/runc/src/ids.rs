@@ -0,0 +1,150 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Validation and generation of container ids.
+//!
+//! runc accepts ids made of ASCII letters, digits and `_+,-.`, other than `.` and `..`,
+//! and names the state directory of the container after it under its root. The `,` comes
+//! from the `+-\.` of the pattern of runc, `[\w+-\.]+`, being a range from `+` to `.`.
+
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// Longest id accepted, as the state directory named after it is limited to NAME_MAX.
+pub const MAX_ID_LEN: usize = 255;
+
+/// Check that `id` is accepted by runc as a container id.
+pub fn validate(id: &str) -> Result<(), Error> {
+    let invalid = |reason: &str| Error::InvalidId {
+        id: id.to_string(),
+        reason: reason.to_string(),
+    };
+    if id.is_empty() {
+        return Err(invalid("id is empty"));
+    }
+    if id.len() > MAX_ID_LEN {
+        return Err(invalid(&format!("id is longer than {} bytes", MAX_ID_LEN)));
+    }
+    if id == "." || id == ".." {
+        return Err(invalid("id is a relative path"));
+    }
+    if let Some(c) = id
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | ',' | '-' | '.')))
+    {
+        return Err(invalid(&format!("invalid character {:?}", c)));
+    }
+    Ok(())
+}
+
+/// Return whether a container with `id` already has a state directory under `root`.
+///
+/// The check is only a hint, another process can still create the container before
+/// it is used, in which case the create fails with the error of runc for an existing
+/// container, which [`Error::from_command_failure`] returns as [`Error::ContainerExists`].
+pub fn in_use<P: AsRef<Path>>(root: P, id: &str) -> bool {
+    root.as_ref().join(id).exists()
+}
+
+/// Check that `id` is valid and not used by a container under `root`.
+pub fn check_available<P: AsRef<Path>>(root: P, id: &str) -> Result<(), Error> {
+    validate(id)?;
+    if in_use(root, id) {
+        return Err(Error::ContainerExists {
+            msg: format!("container {} has a state under its root", id),
+            status: None,
+            stderr: String::new(),
+        });
+    }
+    Ok(())
+}
+
+/// Generate a random id, unique without any coordination between the callers.
+pub fn generate() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Generate a random id starting with `prefix`, which must be valid itself.
+pub fn generate_with_prefix(prefix: &str) -> Result<String, Error> {
+    let id = format!("{}-{}", prefix, generate());
+    validate(&id)?;
+    Ok(id)
+}
+
+/// Generate a random id which is not used by a container under `root`.
+pub fn generate_unused<P: AsRef<Path>>(root: P) -> String {
+    loop {
+        let id = generate();
+        if !in_use(root.as_ref(), &id) {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        for id in [
+            "abc",
+            "a.b-c_d+e",
+            "a,b",
+            "0",
+            "x".repeat(MAX_ID_LEN).as_str(),
+        ] {
+            assert!(validate(id).is_ok(), "{} should be valid", id);
+        }
+        for id in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "a b",
+            "a*b",
+            "caf\u{e9}",
+            "x".repeat(MAX_ID_LEN + 1).as_str(),
+        ] {
+            match validate(id) {
+                Err(Error::InvalidId { .. }) => {}
+                res => panic!("{:?} should be invalid: {:?}", id, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let root = tempfile::tempdir().unwrap();
+        let id = generate();
+        assert!(validate(&id).is_ok());
+        assert_ne!(id, generate());
+
+        std::fs::create_dir(root.path().join(&id)).unwrap();
+        assert!(in_use(root.path(), &id));
+        assert!(matches!(
+            check_available(root.path(), &id),
+            Err(Error::ContainerExists { .. })
+        ));
+        assert_ne!(generate_unused(root.path()), id);
+
+        assert!(generate_with_prefix("test").unwrap().starts_with("test-"));
+        assert!(generate_with_prefix("bad/prefix").is_err());
+    }
+}
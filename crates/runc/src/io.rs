@@ -14,18 +14,30 @@
    limitations under the License.
 */
 #[cfg(not(feature = "async"))]
-use std::io::{Read, Write};
+use std::io::Write;
 use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
-    io::Result,
-    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
-    process::Stdio,
+    io::{Read, Result},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        process::CommandExt,
+    },
+    path::Path,
+    process::{Child, Stdio},
     sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
 
 use log::debug;
-use nix::unistd::{Gid, Uid};
+use nix::{
+    fcntl::{fcntl, FcntlArg},
+    poll::{poll, PollFd, PollFlags},
+    sys::signal::{kill, Signal},
+    unistd::{dup2, Gid, Pid, Uid},
+};
 use os_pipe::{PipeReader, PipeWriter};
 #[cfg(feature = "async")]
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -69,6 +81,17 @@ pub trait Io: Debug + Send + Sync {
         None
     }
 
+    /// Return the raw fd of the read side of stdout, if it is a pipe,
+    /// e.g. to splice it to the client without copying it through userspace
+    fn stdout_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Return the raw fd of the read side of stderr, if it is a pipe
+    fn stderr_fd(&self) -> Option<RawFd> {
+        None
+    }
+
     /// Set IO for passed command.
     /// Read side of stdin, write side of stdout and write side of stderr should be provided to command.
     fn set(&self, cmd: &mut Command) -> Result<()>;
@@ -101,7 +124,7 @@ impl Default for IOOption {
 #[derive(Debug)]
 pub struct Pipe {
     rd: PipeReader,
-    wr: PipeWriter,
+    wr: Mutex<Option<PipeWriter>>,
 }
 
 #[derive(Debug)]
@@ -114,7 +137,10 @@ pub struct PipedIo {
 impl Pipe {
     fn new() -> std::io::Result<Self> {
         let (rd, wr) = os_pipe::pipe()?;
-        Ok(Self { rd, wr })
+        Ok(Self {
+            rd,
+            wr: Mutex::new(Some(wr)),
+        })
     }
 }
 
@@ -143,8 +169,7 @@ impl PipedIo {
         if stdin {
             let rd = pipe.rd.try_clone()?;
             nix::unistd::fchown(rd.as_raw_fd(), uid, gid)?;
-        } else {
-            let wr = pipe.wr.try_clone()?;
+        } else if let Some(wr) = pipe.wr.lock().unwrap().as_ref() {
             nix::unistd::fchown(wr.as_raw_fd(), uid, gid)?;
         }
         Ok(Some(pipe))
@@ -156,6 +181,9 @@ impl Io for PipedIo {
     fn stdin(&self) -> Option<Box<dyn Write + Send + Sync>> {
         self.stdin.as_ref().and_then(|pipe| {
             pipe.wr
+                .lock()
+                .unwrap()
+                .as_ref()?
                 .try_clone()
                 .map(|x| Box::new(x) as Box<dyn Write + Send + Sync>)
                 .ok()
@@ -165,7 +193,7 @@ impl Io for PipedIo {
     #[cfg(feature = "async")]
     fn stdin(&self) -> Option<Box<dyn AsyncWrite + Send + Sync + Unpin>> {
         self.stdin.as_ref().and_then(|pipe| {
-            let fd = pipe.wr.as_raw_fd();
+            let fd = pipe.wr.lock().unwrap().as_ref()?.as_raw_fd();
             tokio_pipe::PipeWrite::from_raw_fd_checked(fd)
                 .map(|x| Box::new(x) as Box<dyn AsyncWrite + Send + Sync + Unpin>)
                 .ok()
@@ -212,6 +240,14 @@ impl Io for PipedIo {
         })
     }
 
+    fn stdout_fd(&self) -> Option<RawFd> {
+        self.stdout.as_ref().map(|pipe| pipe.rd.as_raw_fd())
+    }
+
+    fn stderr_fd(&self) -> Option<RawFd> {
+        self.stderr.as_ref().map(|pipe| pipe.rd.as_raw_fd())
+    }
+
     // Note that this internally use [`std::fs::File`]'s `try_clone()`.
     // Thus, the files passed to commands will be not closed after command exit.
     fn set(&self, cmd: &mut Command) -> std::io::Result<()> {
@@ -221,25 +257,28 @@ impl Io for PipedIo {
         }
 
         if let Some(p) = self.stdout.as_ref() {
-            let pw = p.wr.try_clone()?;
-            cmd.stdout(pw);
+            if let Some(pw) = p.wr.lock().unwrap().as_ref() {
+                cmd.stdout(pw.try_clone()?);
+            }
         }
 
         if let Some(p) = self.stderr.as_ref() {
-            let pw = p.wr.try_clone()?;
-            cmd.stdout(pw);
+            if let Some(pw) = p.wr.lock().unwrap().as_ref() {
+                cmd.stdout(pw.try_clone()?);
+            }
         }
 
         Ok(())
     }
 
+    /// The write sides are dropped rather than closed by fd, as the io may outlive the command.
     fn close_after_start(&self) {
         if let Some(p) = self.stdout.as_ref() {
-            nix::unistd::close(p.wr.as_raw_fd()).unwrap_or_else(|e| debug!("close stdout: {}", e));
+            p.wr.lock().unwrap().take();
         }
 
         if let Some(p) = self.stderr.as_ref() {
-            nix::unistd::close(p.wr.as_raw_fd()).unwrap_or_else(|e| debug!("close stderr: {}", e));
+            p.wr.lock().unwrap().take();
         }
     }
 }
@@ -355,8 +394,236 @@ impl Io for FIFO {
     fn close_after_start(&self) {}
 }
 
+/// Io driver handing already open fds to the command, without creating any pipe.
+///
+/// The fds are owned by [FdIo] from [FdIo::new] on: they are duplicated for every command
+/// they are set for, and closed after start or when the [FdIo] is dropped.
+/// A negative fd leaves the corresponding stdio of the command untouched.
+#[derive(Debug)]
+pub struct FdIo {
+    stdin: Mutex<Option<File>>,
+    stdout: Mutex<Option<File>>,
+    stderr: Mutex<Option<File>>,
+}
+
+impl FdIo {
+    /// The same fd may be given more than once, e.g. for stdout and stderr,
+    /// it is then duplicated so that each stdio closes its own copy.
+    ///
+    /// # Safety
+    ///
+    /// Each non-negative fd must be open and not owned by anything else,
+    /// as it will be closed by [FdIo].
+    pub unsafe fn new(stdin: RawFd, stdout: RawFd, stderr: RawFd) -> Result<Self> {
+        let own = |fd: RawFd| {
+            if fd < 0 {
+                None
+            } else {
+                Some(File::from_raw_fd(fd))
+            }
+        };
+        let stdin_f = own(stdin);
+        let stdout_f = match stdin_f.as_ref() {
+            Some(f) if stdout == stdin => Some(f.try_clone()?),
+            _ => own(stdout),
+        };
+        let stderr_f = match (stdin_f.as_ref(), stdout_f.as_ref()) {
+            (Some(f), _) if stderr == stdin => Some(f.try_clone()?),
+            (_, Some(f)) if stderr == stdout => Some(f.try_clone()?),
+            _ => own(stderr),
+        };
+        Ok(Self {
+            stdin: Mutex::new(stdin_f),
+            stdout: Mutex::new(stdout_f),
+            stderr: Mutex::new(stderr_f),
+        })
+    }
+}
+
+impl Io for FdIo {
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        if let Some(f) = self.stdin.lock().unwrap().as_ref() {
+            cmd.stdin(f.try_clone()?);
+        }
+        if let Some(f) = self.stdout.lock().unwrap().as_ref() {
+            cmd.stdout(f.try_clone()?);
+        }
+        if let Some(f) = self.stderr.lock().unwrap().as_ref() {
+            cmd.stderr(f.try_clone()?);
+        }
+        Ok(())
+    }
+
+    /// All the fds were given to the command, none of them is needed any more.
+    fn close_after_start(&self) {
+        self.stdin.lock().unwrap().take();
+        self.stdout.lock().unwrap().take();
+        self.stderr.lock().unwrap().take();
+    }
+}
+
+/// Time given to a logging binary to exit after SIGTERM, before it is killed.
+const BINARY_IO_TERM_TIMEOUT: Duration = Duration::from_secs(12);
+
+/// Time given to a logging binary to get ready, before it is killed.
+const BINARY_IO_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Io driver forwarding stdout/stderr to a logging binary, following containerd's
+/// binary logging protocol.
+///
+/// The binary is started with `CONTAINER_ID` and `CONTAINER_NAMESPACE` set, the read sides
+/// of the stdout/stderr pipes as fd 3 and 4, and the write side of a sync pipe as fd 5,
+/// which it closes once ready. It is terminated when the io is dropped.
+#[derive(Debug)]
+pub struct BinaryIO {
+    stdout: Mutex<Option<PipeWriter>>,
+    stderr: Mutex<Option<PipeWriter>>,
+    child: Mutex<Option<Child>>,
+}
+
+impl BinaryIO {
+    /// Start the logging binary at `path` and wait until it is ready.
+    pub fn new(
+        path: impl AsRef<Path>,
+        args: &[String],
+        id: &str,
+        ns: &str,
+        uid: u32,
+        gid: u32,
+    ) -> std::io::Result<Self> {
+        let (stdout_r, stdout_w) = os_pipe::pipe()?;
+        let (stderr_r, stderr_w) = os_pipe::pipe()?;
+        for wr in [&stdout_w, &stderr_w] {
+            nix::unistd::fchown(
+                wr.as_raw_fd(),
+                Some(Uid::from_raw(uid)),
+                Some(Gid::from_raw(gid)),
+            )?;
+        }
+        let (mut ready_r, ready_w) = os_pipe::pipe()?;
+
+        let fds = [
+            stdout_r.as_raw_fd(),
+            stderr_r.as_raw_fd(),
+            ready_w.as_raw_fd(),
+        ];
+        let mut cmd = std::process::Command::new(path.as_ref());
+        cmd.args(args)
+            .env("CONTAINER_ID", id)
+            .env("CONTAINER_NAMESPACE", ns)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        unsafe {
+            cmd.pre_exec(move || {
+                // move the fds out of the way first, they may already sit on 3, 4 or 5
+                let mut moved = [0; 3];
+                for (i, fd) in fds.iter().enumerate() {
+                    moved[i] = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(10))?;
+                }
+                for (i, fd) in moved.iter().enumerate() {
+                    dup2(*fd, 3 + i as RawFd)?;
+                }
+                Ok(())
+            });
+        }
+        let mut child = cmd.spawn()?;
+        // the logging binary holds its own copies from now on
+        drop(ready_w);
+        drop(stdout_r);
+        drop(stderr_r);
+
+        // the binary closes the sync pipe once it is ready, EOF is expected here
+        let mut buf = [0u8; 1];
+        if let Err(e) =
+            wait_readable(&ready_r, BINARY_IO_READY_TIMEOUT).and_then(|_| ready_r.read(&mut buf))
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+
+        Ok(Self {
+            stdout: Mutex::new(Some(stdout_w)),
+            stderr: Mutex::new(Some(stderr_w)),
+            child: Mutex::new(Some(child)),
+        })
+    }
+}
+
+/// Wait up to `timeout` for `fd` to be readable, or to be closed on the other end.
+fn wait_readable(fd: &impl AsRawFd, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(fd.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, left.as_millis() as libc::c_int) {
+            Ok(0) => return Err(std::io::ErrorKind::TimedOut.into()),
+            Ok(_) => return Ok(()),
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl Io for BinaryIO {
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        cmd.stdin(Stdio::null());
+        if let Some(w) = self.stdout.lock().unwrap().as_ref() {
+            cmd.stdout(w.try_clone()?);
+        }
+        if let Some(w) = self.stderr.lock().unwrap().as_ref() {
+            cmd.stderr(w.try_clone()?);
+        }
+        Ok(())
+    }
+
+    fn close_after_start(&self) {
+        self.stdout.lock().unwrap().take();
+        self.stderr.lock().unwrap().take();
+    }
+}
+
+impl Drop for BinaryIO {
+    fn drop(&mut self) {
+        self.close_after_start();
+        let mut child = match self.child.get_mut().unwrap().take() {
+            Some(c) => c,
+            None => return,
+        };
+        // send SIGTERM first, so the logging binary has a chance to flush and exit properly
+        if let Err(e) = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM) {
+            debug!("failed to terminate logging binary {}: {}", child.id(), e);
+        }
+        thread::spawn(move || {
+            let deadline = Instant::now() + BINARY_IO_TERM_TIMEOUT;
+            loop {
+                match child.try_wait() {
+                    Ok(None) if Instant::now() < deadline => {
+                        thread::sleep(Duration::from_millis(100))
+                    }
+                    Ok(None) => {
+                        debug!(
+                            "logging binary {} did not exit in time, killing it",
+                            child.id()
+                        );
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return;
+                    }
+                    // an error means the binary was reaped by someone else, e.g. a subreaper
+                    Ok(Some(_)) | Err(_) => return,
+                }
+            }
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    #[cfg(not(feature = "async"))]
+    use std::os::unix::io::IntoRawFd;
+
     use super::*;
 
     #[cfg(not(target_os = "macos"))]
@@ -395,18 +662,30 @@ mod tests {
 
         let mut stdout = io.stdout().unwrap();
         buf[0] = 0xce;
-        io.stdout
-            .as_ref()
-            .map(|v| v.wr.try_clone().unwrap().write(&buf).unwrap());
+        io.stdout.as_ref().map(|v| {
+            let wr = v.wr.lock().unwrap();
+            wr.as_ref()
+                .unwrap()
+                .try_clone()
+                .unwrap()
+                .write(&buf)
+                .unwrap()
+        });
         buf[0] = 0x0;
         stdout.read_exact(&mut buf).unwrap();
         assert_eq!(&buf, &[0xceu8]);
 
         let mut stderr = io.stderr().unwrap();
         buf[0] = 0xa5;
-        io.stderr
-            .as_ref()
-            .map(|v| v.wr.try_clone().unwrap().write(&buf).unwrap());
+        io.stderr.as_ref().map(|v| {
+            let wr = v.wr.lock().unwrap();
+            wr.as_ref()
+                .unwrap()
+                .try_clone()
+                .unwrap()
+                .write(&buf)
+                .unwrap()
+        });
         buf[0] = 0x0;
         stderr.read_exact(&mut buf).unwrap();
         assert_eq!(&buf, &[0xa5u8]);
@@ -416,6 +695,91 @@ mod tests {
         stderr.read_exact(&mut buf).unwrap_err();
     }
 
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_fd_io() {
+        let (in_r, mut in_w) = os_pipe::pipe().unwrap();
+        let (mut out_r, out_w) = os_pipe::pipe().unwrap();
+        let io = unsafe { FdIo::new(in_r.into_raw_fd(), out_w.into_raw_fd(), -1) }.unwrap();
+
+        let mut cmd = Command::new("cat");
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        drop(cmd);
+        io.close_after_start();
+
+        in_w.write_all(b"hello").unwrap();
+        drop(in_w);
+        let mut out = String::new();
+        out_r.read_to_string(&mut out).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(out, "hello");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_fd_io_shared_fd() {
+        let (mut out_r, out_w) = os_pipe::pipe().unwrap();
+        let out_w = out_w.into_raw_fd();
+        let io = unsafe { FdIo::new(-1, out_w, out_w) }.unwrap();
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("echo out; echo err >&2");
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        drop(cmd);
+        // each stdio closes its own copy of the fd
+        io.close_after_start();
+
+        let mut out = String::new();
+        out_r.read_to_string(&mut out).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(out, "out\nerr\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_binary_io() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script = format!(
+            "exec 5>&-; echo $CONTAINER_NAMESPACE/$CONTAINER_ID > {0}; cat <&3 >> {0}",
+            out.display()
+        );
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = BinaryIO::new(
+            "/bin/sh",
+            &["-c".to_string(), script],
+            "id1",
+            "ns1",
+            uid.as_raw(),
+            gid.as_raw(),
+        )
+        .unwrap();
+
+        let mut cmd = Command::new("echo");
+        cmd.arg("hello");
+        io.set(&mut cmd).unwrap();
+        assert!(cmd.status().unwrap().success());
+        drop(cmd);
+        io.close_after_start();
+
+        let expected = "ns1/id1\nhello\n";
+        let mut content = String::new();
+        for _ in 0..50 {
+            content = std::fs::read_to_string(&out).unwrap_or_default();
+            if content == expected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(content, expected);
+    }
+
     #[test]
     fn test_null_io() {
         let io = NullIo::new().unwrap();
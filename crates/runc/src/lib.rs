@@ -35,32 +35,56 @@
 
 //! A crate for consuming the runc binary in your Rust applications, similar to
 //! [go-runc](https://github.com/containerd/go-runc) for Go.
+#[cfg(not(feature = "async"))]
+use std::os::unix::process::CommandExt;
 use std::{
+    convert::TryFrom,
     fmt::{self, Debug, Display},
+    os::unix::process::ExitStatusExt,
     path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
     sync::Arc,
+    time::Duration,
 };
 
 #[cfg(feature = "async")]
 use async_trait::async_trait;
-#[cfg(feature = "async")]
 use log::debug;
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::{setpgid, Pid},
+};
 use oci_spec::runtime::{LinuxResources, Process};
 
-use crate::{container::Container, error::Error, options::*, utils::write_value_to_temp_file};
+use crate::{
+    container::{Container, ContainerStatus, TopResults},
+    error::Error,
+    options::*,
+    utils::{write_value_to_pipe, write_value_to_temp_file},
+};
 
+pub mod bundle;
 pub mod container;
 pub mod error;
 pub mod events;
+pub mod hooks;
+pub mod ids;
 pub mod io;
 #[cfg(feature = "async")]
 pub mod monitor;
 pub mod options;
+pub mod runc_log;
+pub mod specs;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod testing;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, crate::error::Error>;
 
+/// Interval between the state checks of [`Runc::wait_for_status`] and [`Runc::kill_with_escalation`].
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Response is for (pid, exit status, outputs).
 #[derive(Debug, Clone)]
 pub struct Response {
@@ -69,6 +93,49 @@ pub struct Response {
     pub output: String,
 }
 
+impl Response {
+    pub fn exit_reason(&self) -> ExitReason {
+        self.status.into()
+    }
+}
+
+/// How a process terminated, either exiting with a code or killed by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Code(i32),
+    Signal(i32),
+}
+
+impl ExitReason {
+    /// The exit status reported to containerd, `128 + signal` for a process killed by a signal.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ExitReason::Code(code) => *code,
+            ExitReason::Signal(sig) => 128 + sig,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitReason {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => ExitReason::Code(code),
+            (None, Some(sig)) => ExitReason::Signal(sig),
+            // only exited or signaled processes are waited for
+            (None, None) => ExitReason::Code(-1),
+        }
+    }
+}
+
+/// Leftovers removed by [`Runc::cleanup`].
+#[derive(Debug, Clone, Default)]
+pub struct Cleanup {
+    /// Container state directory removed under root.
+    pub state_dir: Option<PathBuf>,
+    /// Number of mounts removed from the container rootfs.
+    pub unmounted: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Version {
     pub runc_version: Option<String>,
@@ -76,7 +143,39 @@ pub struct Version {
     pub commit: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+impl Version {
+    /// Parse the output of `runc --version`
+    pub fn parse(output: &str) -> Result<Self> {
+        let mut version = Version {
+            runc_version: None,
+            spec_version: None,
+            commit: None,
+        };
+        for line in output.lines() {
+            let line = line.trim();
+            if let Some(v) = line.strip_prefix("runc version ") {
+                version.runc_version = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("spec:") {
+                version.spec_version = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("commit:") {
+                version.commit = Some(v.trim().to_string());
+            }
+        }
+        if version.runc_version.is_none() {
+            return Err(Error::InvalidVersion);
+        }
+        Ok(version)
+    }
+
+    /// The `major.minor.patch` numbers of the runc version, ignoring any pre-release suffix.
+    pub fn numbers(&self) -> Option<(u64, u64, u64)> {
+        self.runc_version
+            .as_deref()
+            .and_then(utils::parse_version_numbers)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LogFormat {
     Json,
     Text,
@@ -107,7 +206,17 @@ pub type Command = tokio::process::Command;
 pub struct Runc {
     command: PathBuf,
     args: Vec<String>,
+    flags: GlobalFlags,
+    extra_args: Vec<String>,
     spawner: Arc<dyn Spawner + Send + Sync>,
+    timeout: Duration,
+    set_pgid: bool,
+    env: Vec<(String, String)>,
+    clear_env: bool,
+    temp_dir: Option<PathBuf>,
+    spec_stdin: bool,
+    retry: RetryPolicy,
+    version: Option<Version>,
 }
 
 impl Runc {
@@ -120,16 +229,173 @@ impl Runc {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
+        if self.clear_env {
+            cmd.env_clear();
+        }
         // NOTIFY_SOCKET introduces a special behavior in runc but should only be set if invoked from systemd
         cmd.args(&args).env_remove("NOTIFY_SOCKET");
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+
+        if self.set_pgid {
+            // Safety: setpgid is async-signal-safe and nothing is allocated in the closure
+            unsafe {
+                cmd.pre_exec(|| {
+                    setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(std::io::Error::from)
+                });
+            }
+        }
 
         Ok(cmd)
     }
+
+    /// Return whether the host is booted with systemd, which `--systemd-cgroup` requires
+    pub fn supports_systemd_cgroup(&self) -> bool {
+        Path::new("/run/systemd/system").is_dir()
+    }
+
+    /// Return whether a criu binary is available for checkpoint and restore
+    pub fn supports_criu(&self) -> bool {
+        let criu = self
+            .args
+            .iter()
+            .position(|arg| arg == CRIU)
+            .and_then(|i| self.args.get(i + 1))
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("criu"));
+        utils::binary_path(criu).is_some()
+    }
+
+    /// Return whether the runc commands run in rootless mode, auto-detected
+    /// from the effective user and its capabilities unless set explicitly
+    pub fn is_rootless(&self) -> bool {
+        let explicit = format!("{}=true", ROOTLESS);
+        if self.args.iter().any(|arg| *arg == explicit) {
+            return true;
+        }
+        let explicit = format!("{}=false", ROOTLESS);
+        if self.args.iter().any(|arg| *arg == explicit) {
+            return false;
+        }
+        utils::detect_rootless()
+    }
+
+    /// Send a signal to the process group led by `pid`.
+    ///
+    /// This is used to clean up lingering children of runc (e.g. stuck hooks) when runc
+    /// is running in its own process group, see [`GlobalOpts::set_pgid`].
+    pub fn kill_process_group(&self, pid: u32, signal: u32) -> Result<()> {
+        let signal = Signal::try_from(signal as i32).map_err(Error::KillProcessGroup)?;
+        killpg(Pid::from_raw(pid as i32), signal).map_err(Error::KillProcessGroup)
+    }
+
+    /// Remove what runc may leave behind for a deleted container: its state directory
+    /// under root and the mounts on `rootfs`. Missing leftovers are not an error.
+    pub fn cleanup(&self, id: &str, rootfs: Option<&Path>) -> Result<Cleanup> {
+        let mut cleanup = Cleanup::default();
+        let state_dir = self.root().join(id);
+        if state_dir.is_dir() {
+            std::fs::remove_dir_all(&state_dir).map_err(Error::FileSystemError)?;
+            cleanup.state_dir = Some(state_dir);
+        }
+        if let Some(rootfs) = rootfs {
+            cleanup.unmounted = utils::unmount_all(rootfs)?;
+        }
+        Ok(cleanup)
+    }
+
+    /// Return whether a container with `id` exists under the root of runc, see [`ids::in_use`]
+    pub fn container_exists(&self, id: &str) -> bool {
+        ids::in_use(self.root(), id)
+    }
+
+    /// Whether the container of a state query is stopped, a deleted container is.
+    fn is_stopped(state: Result<Container>) -> Result<bool> {
+        match state {
+            Ok(container) => Ok(container.status == ContainerStatus::Stopped),
+            Err(Error::ContainerNotFound { .. }) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Return the root directory runc stores containers' state in
+    fn root(&self) -> PathBuf {
+        self.args
+            .iter()
+            .position(|arg| arg == ROOT)
+            .and_then(|i| self.args.get(i + 1))
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_ROOT))
+    }
+
+    /// Return a client with the same settings running commands with another timeout.
+    ///
+    /// A zero timeout disables the timeout. This is only used by the async client.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        let mut runc = self.clone();
+        runc.timeout = timeout;
+        runc
+    }
+
+    /// Return the global flags passed to every runc command of the client.
+    pub fn global_flags(&self) -> &GlobalFlags {
+        &self.flags
+    }
+
+    /// Return a client with the same settings passing other global flags to runc,
+    /// e.g. to operate on containers under another root.
+    pub fn with_global_flags(&self, flags: GlobalFlags) -> Result<Self> {
+        let mut runc = self.clone();
+        runc.args = flags.args()?;
+        runc.args.extend_from_slice(&self.extra_args);
+        runc.flags = flags;
+        Ok(runc)
+    }
+
+    /// Return the version of runc checked when the client was built,
+    /// if a minimum version was required with [`GlobalOpts::min_version`].
+    pub fn checked_version(&self) -> Option<&Version> {
+        self.version.as_ref()
+    }
+
+    /// Return the path of the log file passed to runc with `--log`, if any
+    fn log_path(&self) -> Option<&Path> {
+        self.args
+            .iter()
+            .position(|arg| arg == LOG)
+            .and_then(|i| self.args.get(i + 1))
+            .map(Path::new)
+    }
+
+    /// Return the last error runc logged since `offset` of the log file
+    fn last_log_error(&self, offset: Option<u64>) -> Option<String> {
+        let path = self.log_path()?;
+        runc_log::last_error(path, offset.unwrap_or(0))
+    }
 }
 
 #[cfg(not(feature = "async"))]
 impl Runc {
+    /// Run `op` until it succeeds, fails with a non transient error,
+    /// or the attempts of the retry policy are exhausted.
+    fn retry<T>(&self, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e) if e.is_transient() => match self.retry.delay(attempt) {
+                    Some(delay) => {
+                        debug!("retry in {:?} after transient failure: {}", delay, e);
+                        std::thread::sleep(delay);
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                res => return res,
+            }
+        }
+    }
+
     fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+        let log_offset = self.log_path().map(runc_log::size);
         let (status, pid, stdout, stderr) = self.spawner.execute(cmd)?;
         if status.success() {
             let output = if combined_output {
@@ -143,15 +409,20 @@ impl Runc {
                 output,
             })
         } else {
-            Err(Error::CommandFailed {
+            Err(Error::from_command_failure(
                 status,
                 stdout,
                 stderr,
-            })
+                self.last_log_error(log_offset),
+            ))
         }
     }
 
     /// Create a new container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub fn create<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
         P: AsRef<Path>,
@@ -159,9 +430,10 @@ impl Runc {
         let mut args = vec![
             "create".to_string(),
             "--bundle".to_string(),
-            utils::abs_string(bundle)?,
+            utils::abs_string(bundle.as_ref())?,
         ];
         if let Some(opts) = opts {
+            opts.check_labels(bundle.as_ref())?;
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
@@ -178,39 +450,81 @@ impl Runc {
     }
 
     /// Delete a container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub fn delete(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
         let mut args = vec!["delete".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
+        let cleanup = opts.map(|opts| opts.cleanup).unwrap_or_default();
+        // the rootfs is only known while the container state exists
+        let rootfs = if cleanup {
+            self.state(id).ok().map(|c| PathBuf::from(c.rootfs))
+        } else {
+            None
+        };
+        self.retry(|| self.launch(self.command(&args)?, true))?;
+        if cleanup {
+            self.retry(|| self.cleanup(id, rootfs.as_deref()))?;
+        }
+        Ok(())
+    }
+
+    /// Generate a default spec file (`config.json`) for a bundle
+    pub fn spec(&self, opts: Option<&SpecOpts>) -> Result<()> {
+        let mut args = vec!["spec".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
         self.launch(self.command(&args)?, true)?;
         Ok(())
     }
 
     /// Execute an additional process inside the container
-    pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(spec)?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
+    pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<Response> {
+        let labelled = opts.and_then(|opts| opts.labelled(spec));
+        let spec = labelled.as_ref().unwrap_or(spec);
+        // stdin can only carry the spec if no io takes it over
+        let spec_stdin = self.spec_stdin && opts.and_then(|opts| opts.io.as_ref()).is_none();
+        let (_temp_file, filename) = if spec_stdin {
+            (None, "/dev/stdin".to_string())
+        } else {
+            let (f, filename) = write_value_to_temp_file(spec, self.temp_dir.as_deref())?;
+            (Some(f), filename)
+        };
         let mut args = vec!["exec".to_string(), "--process".to_string(), filename];
         if let Some(opts) = opts {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
         let mut cmd = self.command(&args)?;
+        if spec_stdin {
+            cmd.stdin(write_value_to_pipe(spec)?);
+        }
         match opts {
             Some(ExecOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
-                self.launch(cmd, true)?;
+                let res = self.launch(cmd, true)?;
                 io.close_after_start();
+                Ok(res)
             }
-            _ => {
-                self.launch(cmd, true)?;
-            }
+            _ => self.launch(cmd, true),
         }
-        Ok(())
     }
 
     /// Send the specified signal to processes inside the container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id, signal = sig))
+    )]
     pub fn kill(&self, id: &str, sig: u32, opts: Option<&KillOpts>) -> Result<()> {
         let mut args = vec!["kill".to_string()];
         if let Some(opts) = opts {
@@ -222,6 +536,52 @@ impl Runc {
         Ok(())
     }
 
+    /// Send `sig` to the container and wait up to `timeout` for it to stop,
+    /// then send `final_sig` to it, usually `SIGKILL`.
+    ///
+    /// Returns whether the container stopped before the escalation.
+    pub fn kill_with_escalation(
+        &self,
+        id: &str,
+        sig: u32,
+        timeout: Duration,
+        final_sig: u32,
+    ) -> Result<bool> {
+        match self.kill(id, sig, None) {
+            Ok(_) => {}
+            Err(Error::ContainerNotFound { .. }) => return Ok(true),
+            // e.g. the container is not running anymore
+            Err(e) => {
+                return match Self::is_stopped(self.state(id)) {
+                    Ok(true) => Ok(true),
+                    _ => Err(e),
+                }
+            }
+        }
+        let start = std::time::Instant::now();
+        loop {
+            if Self::is_stopped(self.state(id))? {
+                return Ok(true);
+            }
+            if start.elapsed() >= timeout {
+                break;
+            }
+            std::thread::sleep(STATUS_POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+        }
+        debug!(
+            "container {} still running after {:?}, sending {}",
+            id, timeout, final_sig
+        );
+        match self.kill(id, final_sig, None) {
+            Ok(_) => Ok(false),
+            // the container stopped meanwhile
+            Err(e) => match Self::is_stopped(self.state(id)) {
+                Ok(true) => Ok(true),
+                _ => Err(e),
+            },
+        }
+    }
+
     /// List all containers associated with this runc instance
     pub fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
@@ -250,12 +610,40 @@ impl Runc {
         Ok(())
     }
 
-    pub fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint a running container with criu
+    pub fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<Response> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        self.launch(self.command(&args)?, true)
     }
 
-    pub fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore a container from a previously performed checkpoint
+    pub fn restore<P>(&self, id: &str, bundle: P, opts: Option<&RestoreOpts>) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            utils::abs_string(bundle)?,
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self.launch(cmd, true)?;
+                io.close_after_start();
+                Ok(res)
+            }
+            _ => self.launch(cmd, true),
+        }
     }
 
     /// List all the processes inside the container, returning their pids
@@ -276,6 +664,19 @@ impl Runc {
         })
     }
 
+    /// List the processes inside the container with the columns of `ps`,
+    /// `ps_args` (e.g. `-ef` or `-o pid,cmd`) are split on whitespace and passed to ps
+    pub fn top(&self, id: &str, ps_args: &str) -> Result<TopResults> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        args.extend(ps_args.split_whitespace().map(String::from));
+        let res = self.launch(self.command(&args)?, false)?;
+        TopResults::parse(&res.output)
+    }
+
     /// Run the create, start, delete lifecycle of the container and return its exit status
     pub fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
@@ -298,6 +699,10 @@ impl Runc {
     }
 
     /// Start an already created container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub fn start(&self, id: &str) -> Result<Response> {
         let args = ["start".to_string(), id.to_string()];
         self.launch(self.command(&args)?, true)
@@ -310,12 +715,23 @@ impl Runc {
         serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
     }
 
-    /// Return the latest statistics for a container
-    pub fn stats(&self, id: &str) -> Result<events::Stats> {
+    /// Return the version of the runc binary
+    pub fn version(&self) -> Result<Version> {
+        let args = vec!["--version".to_string()];
+        let res = self.launch(self.command(&args)?, false)?;
+        Version::parse(&res.output)
+    }
+
+    /// Return a single stats event for a container
+    pub fn events_once(&self, id: &str) -> Result<events::Event> {
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
         let res = self.launch(self.command(&args)?, true)?;
-        let event: events::Event =
-            serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return the latest statistics for a container
+    pub fn stats(&self, id: &str) -> Result<events::Stats> {
+        let event = self.events_once(id)?;
         if let Some(stats) = event.stats {
             Ok(stats)
         } else {
@@ -325,7 +741,19 @@ impl Runc {
 
     /// Update a container with the provided resource spec
     pub fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(resources)?;
+        if self.spec_stdin {
+            let args = [
+                "update".to_string(),
+                "--resources".to_string(),
+                "-".to_string(),
+                id.to_string(),
+            ];
+            let mut cmd = self.command(&args)?;
+            cmd.stdin(write_value_to_pipe(resources)?);
+            self.launch(cmd, true)?;
+            return Ok(());
+        }
+        let (_temp_file, filename) = write_value_to_temp_file(resources, self.temp_dir.as_deref())?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
@@ -371,9 +799,39 @@ pub trait Spawner: Debug {
 /// and some other utilities.
 #[cfg(feature = "async")]
 impl Runc {
+    /// Run `op` until it succeeds, fails with a non transient error,
+    /// or the attempts of the retry policy are exhausted.
+    async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if e.is_transient() => match self.retry.delay(attempt) {
+                    Some(delay) => {
+                        debug!("retry in {:?} after transient failure: {}", delay, e);
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(e),
+                },
+                res => return res,
+            }
+        }
+    }
+
     async fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
         debug!("Execute command {:?}", cmd);
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd).await?;
+        let log_offset = self.log_path().map(runc_log::size);
+        let (status, pid, stdout, stderr) = if self.timeout.is_zero() {
+            self.spawner.execute(cmd).await?
+        } else {
+            tokio::time::timeout(self.timeout, self.spawner.execute(cmd))
+                .await
+                .map_err(Error::CommandTimeout)??
+        };
         if status.success() {
             let output = if combined_output {
                 stdout + stderr.as_str()
@@ -386,15 +844,20 @@ impl Runc {
                 output,
             })
         } else {
-            Err(Error::CommandFailed {
+            Err(Error::from_command_failure(
                 status,
                 stdout,
                 stderr,
-            })
+                self.last_log_error(log_offset),
+            ))
         }
     }
 
     /// Create a new container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub async fn create<P>(
         &self,
         id: &str,
@@ -407,9 +870,10 @@ impl Runc {
         let mut args = vec![
             "create".to_string(),
             "--bundle".to_string(),
-            utils::abs_string(bundle)?,
+            utils::abs_string(bundle.as_ref())?,
         ];
         if let Some(opts) = opts {
+            opts.check_labels(bundle.as_ref())?;
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
@@ -426,48 +890,125 @@ impl Runc {
     }
 
     /// Delete a container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub async fn delete(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
         let mut args = vec!["delete".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
+        let cleanup = opts.map(|opts| opts.cleanup).unwrap_or_default();
+        // the rootfs is only known while the container state exists
+        let rootfs = if cleanup {
+            self.state(id).await.ok().map(|c| PathBuf::from(c.rootfs))
+        } else {
+            None
+        };
+        self.retry(|| async { self.launch(self.command(&args)?, true).await })
+            .await?;
+        if cleanup {
+            self.retry(|| async { self.cleanup(id, rootfs.as_deref()) })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Generate a default spec file (`config.json`) for a bundle
+    pub async fn spec(&self, opts: Option<&SpecOpts>) -> Result<()> {
+        let mut args = vec!["spec".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
         let _ = self.launch(self.command(&args)?, true).await?;
         Ok(())
     }
 
     /// Return an event stream of container notifications
-    pub async fn events(&self, _id: &str, _interval: &std::time::Duration) -> Result<()> {
-        Err(Error::Unimplemented("events".to_string()))
+    pub async fn events(
+        &self,
+        id: &str,
+        interval: &std::time::Duration,
+    ) -> Result<events::EventStream> {
+        let args = vec![
+            "events".to_string(),
+            format!("--interval={}s", interval.as_secs()),
+            id.to_string(),
+        ];
+        let mut cmd = self.command(&args)?;
+        cmd.kill_on_drop(true);
+        let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
+        events::EventStream::new(child)
+    }
+
+    /// Run runc with `args`, streaming the lines of its outputs as they are printed
+    /// instead of buffering them until it exits. The timeout of the client does not apply.
+    pub async fn launch_streaming(&self, args: &[String]) -> Result<stream::OutputStream> {
+        let mut cmd = self.command(args)?;
+        cmd.kill_on_drop(true);
+        debug!("Execute command {:?}", cmd);
+        let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
+        stream::OutputStream::new(child)
     }
 
     /// Execute an additional process inside the container
-    pub async fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let f = write_value_to_temp_file(spec).await?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
+    pub async fn exec(
+        &self,
+        id: &str,
+        spec: &Process,
+        opts: Option<&ExecOpts>,
+    ) -> Result<Response> {
+        let labelled = opts.and_then(|opts| opts.labelled(spec));
+        let spec = labelled.as_ref().unwrap_or(spec);
+        // stdin can only carry the spec if no io takes it over
+        if self.spec_stdin && opts.and_then(|opts| opts.io.as_ref()).is_none() {
+            let mut args = vec![
+                "exec".to_string(),
+                "--process".to_string(),
+                "/dev/stdin".to_string(),
+            ];
+            if let Some(opts) = opts {
+                args.append(&mut opts.args()?);
+            }
+            args.push(id.to_string());
+            let mut cmd = self.command(&args)?;
+            cmd.stdin(write_value_to_pipe(spec)?);
+            return self.launch(cmd, true).await;
+        }
+        let f = write_value_to_temp_file(spec, self.temp_dir.as_deref()).await?;
         let mut args = vec!["exec".to_string(), "--process".to_string(), f.clone()];
         if let Some(opts) = opts {
             args.append(&mut tc!(opts.args(), &f));
         }
         args.push(id.to_string());
         let mut cmd = self.command(&args)?;
-        match opts {
+        let res = match opts {
             Some(ExecOpts { io: Some(io), .. }) => {
                 tc!(
                     io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string())),
                     &f
                 );
-                tc!(self.launch(cmd, true).await, &f);
+                let res = tc!(self.launch(cmd, true).await, &f);
                 io.close_after_start();
+                res
             }
-            _ => {
-                tc!(self.launch(cmd, true).await, &f);
-            }
-        }
+            _ => tc!(self.launch(cmd, true).await, &f),
+        };
         let _ = tokio::fs::remove_file(&f).await;
-        Ok(())
+        Ok(res)
     }
 
     /// Send the specified signal to processes inside the container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id, signal = sig))
+    )]
     pub async fn kill(&self, id: &str, sig: u32, opts: Option<&KillOpts>) -> Result<()> {
         let mut args = vec!["kill".to_string()];
         if let Some(opts) = opts {
@@ -479,6 +1020,53 @@ impl Runc {
         Ok(())
     }
 
+    /// Send `sig` to the container and wait up to `timeout` for it to stop,
+    /// then send `final_sig` to it, usually `SIGKILL`.
+    ///
+    /// Returns whether the container stopped before the escalation.
+    pub async fn kill_with_escalation(
+        &self,
+        id: &str,
+        sig: u32,
+        timeout: Duration,
+        final_sig: u32,
+    ) -> Result<bool> {
+        match self.kill(id, sig, None).await {
+            Ok(_) => {}
+            Err(Error::ContainerNotFound { .. }) => return Ok(true),
+            // e.g. the container is not running anymore
+            Err(e) => {
+                return match Self::is_stopped(self.state(id).await) {
+                    Ok(true) => Ok(true),
+                    _ => Err(e),
+                }
+            }
+        }
+        let poll = async {
+            loop {
+                if Self::is_stopped(self.state(id).await)? {
+                    return Ok::<_, Error>(());
+                }
+                tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+            }
+        };
+        match tokio::time::timeout(timeout, poll).await {
+            Ok(res) => return res.map(|_| true),
+            Err(_) => debug!(
+                "container {} still running after {:?}, sending {}",
+                id, timeout, final_sig
+            ),
+        }
+        match self.kill(id, final_sig, None).await {
+            Ok(_) => Ok(false),
+            // the container stopped meanwhile
+            Err(e) => match Self::is_stopped(self.state(id).await) {
+                Ok(true) => Ok(true),
+                _ => Err(e),
+            },
+        }
+    }
+
     /// List all containers associated with this runc instance
     pub async fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
@@ -507,12 +1095,45 @@ impl Runc {
         Ok(())
     }
 
-    pub async fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint a running container with criu
+    pub async fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<Response> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        self.launch(self.command(&args)?, true).await
     }
 
-    pub async fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore a container from a previously performed checkpoint
+    pub async fn restore<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&RestoreOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            utils::abs_string(bundle)?,
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(Error::UnavailableIO)?;
+                let res = self.launch(cmd, true).await?;
+                io.close_after_start();
+                Ok(res)
+            }
+            _ => self.launch(cmd, true).await,
+        }
     }
 
     /// List all the processes inside the container, returning their pids
@@ -533,6 +1154,19 @@ impl Runc {
         })
     }
 
+    /// List the processes inside the container with the columns of `ps`,
+    /// `ps_args` (e.g. `-ef` or `-o pid,cmd`) are split on whitespace and passed to ps
+    pub async fn top(&self, id: &str, ps_args: &str) -> Result<TopResults> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        args.extend(ps_args.split_whitespace().map(String::from));
+        let res = self.launch(self.command(&args)?, false).await?;
+        TopResults::parse(&res.output)
+    }
+
     /// Run the create, start, delete lifecycle of the container and return its exit status
     pub async fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<()>
     where
@@ -556,6 +1190,10 @@ impl Runc {
     }
 
     /// Start an already created container
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id))
+    )]
     pub async fn start(&self, id: &str) -> Result<()> {
         let args = vec!["start".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true).await?;
@@ -563,12 +1201,40 @@ impl Runc {
     }
 
     /// Return the state of a container
-    pub async fn state(&self, id: &str) -> Result<Vec<usize>> {
+    pub async fn state(&self, id: &str) -> Result<Container> {
         let args = vec!["state".to_string(), id.to_string()];
         let res = self.launch(self.command(&args)?, true).await?;
         serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
     }
 
+    /// Poll the state of the container until it has `status`, failing after `timeout`
+    pub async fn wait_for_status(
+        &self,
+        id: &str,
+        status: ContainerStatus,
+        timeout: Duration,
+    ) -> Result<Container> {
+        let poll = async {
+            loop {
+                let container = self.state(id).await?;
+                if container.status == status {
+                    return Ok(container);
+                }
+                tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, poll)
+            .await
+            .map_err(Error::CommandTimeout)?
+    }
+
+    /// Return the version of the runc binary
+    pub async fn version(&self) -> Result<Version> {
+        let args = vec!["--version".to_string()];
+        let res = self.launch(self.command(&args)?, false).await?;
+        Version::parse(&res.output)
+    }
+
     /// Return the latest statistics for a container
     pub async fn stats(&self, id: &str) -> Result<events::Stats> {
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
@@ -584,7 +1250,19 @@ impl Runc {
 
     /// Update a container with the provided resource spec
     pub async fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let f = write_value_to_temp_file(resources).await?;
+        if self.spec_stdin {
+            let args = [
+                "update".to_string(),
+                "--resources".to_string(),
+                "-".to_string(),
+                id.to_string(),
+            ];
+            let mut cmd = self.command(&args)?;
+            cmd.stdin(write_value_to_pipe(resources)?);
+            let _ = self.launch(cmd, true).await?;
+            return Ok(());
+        }
+        let f = write_value_to_temp_file(resources, self.temp_dir.as_deref()).await?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
@@ -660,6 +1338,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -689,6 +1368,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -705,18 +1385,21 @@ mod tests {
         let opts = ExecOpts::new();
         let ok_runc = ok_client();
         let proc = dummy_process();
-        ok_runc
+        let response = ok_runc
             .exec("fake-id", &proc, Some(&opts))
             .expect("true failed.");
-        eprintln!("ok_runc succeeded.");
-
-        let fail_runc = fail_client();
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+
+        let fail_runc = fail_client();
         match fail_runc.exec("fake-id", &proc, Some(&opts)) {
             Ok(_) => panic!("fail_runc returned exit status 0."),
             Err(Error::CommandFailed {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -728,6 +1411,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_spec_stdin() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script = dir.path().join("runc");
+        std::fs::write(&script, format!("#!/bin/sh\ncat > {}\n", out.display())).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .spec_stdin(true)
+            .build()
+            .expect("unable to create runc instance");
+
+        let proc = dummy_process();
+        runc.exec("fake-id", &proc, None).expect("exec failed");
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            serde_json::to_string(&proc).unwrap()
+        );
+
+        let resources = LinuxResources::default();
+        runc.update("fake-id", &resources).expect("update failed");
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            serde_json::to_string(&resources).unwrap()
+        );
+    }
+
     #[test]
     fn test_delete() {
         let opts = DeleteOpts::new();
@@ -744,6 +1457,37 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
+            }) => {
+                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                    eprintln!("fail_runc succeeded.");
+                } else {
+                    panic!("unexpected outputs from fail_runc.")
+                }
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let opts = CheckpointOpts::new().leave_running(true);
+        let ok_runc = ok_client();
+        let response = ok_runc
+            .checkpoint("fake-id", Some(&opts))
+            .expect("true failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+
+        let fail_runc = fail_client();
+        match fail_runc.checkpoint("fake-id", Some(&opts)) {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -755,6 +1499,233 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_restore() {
+        let opts = RestoreOpts::new().detach(true);
+        let ok_runc = ok_client();
+        let response = ok_runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .expect("true failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+
+        let fail_runc = fail_client();
+        match fail_runc.restore("fake-id", "fake-bundle", Some(&opts)) {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+                ..
+            }) => {
+                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                    eprintln!("fail_runc succeeded.");
+                } else {
+                    panic!("unexpected outputs from fail_runc.")
+                }
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_top() {
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "ps",
+            testing::MockResponse::success("UID PID CMD\nroot 1 sleep infinity\n"),
+        );
+        let top = mock.client().top("fake-id", "-ef").expect("top failed");
+        assert_eq!(top.headers, vec!["UID", "PID", "CMD"]);
+        assert_eq!(top.processes, vec![vec!["root", "1", "sleep infinity"]]);
+        assert_eq!(
+            mock.calls()[0],
+            vec!["ps", "--format=table", "fake-id", "-ef"]
+        );
+    }
+
+    #[test]
+    fn test_top_split_ps_args() {
+        let mock = testing::MockRunc::new();
+        let ps = testing::MockResponse::success("PID CMD\n1 sleep\n");
+        mock.on("ps", ps.clone()).on("ps", ps);
+        mock.client()
+            .top("fake-id", " -o  pid,cmd ")
+            .expect("top failed");
+        mock.client().top("fake-id", "").expect("top failed");
+        let calls = mock.calls();
+        assert_eq!(
+            calls[0],
+            vec!["ps", "--format=table", "fake-id", "-o", "pid,cmd"]
+        );
+        assert_eq!(calls[1], vec!["ps", "--format=table", "fake-id"]);
+    }
+
+    #[test]
+    fn test_version_parse() {
+        let output = "runc version 1.1.4\n\
+                      commit: v1.1.4-0-g5fd4c4d1\n\
+                      spec: 1.0.2-dev\n\
+                      go: go1.17.10\n\
+                      libseccomp: 2.5.4\n";
+        let version = Version::parse(output).expect("failed to parse version.");
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert_eq!(version.commit.as_deref(), Some("v1.1.4-0-g5fd4c4d1"));
+        assert_eq!(version.spec_version.as_deref(), Some("1.0.2-dev"));
+
+        match Version::parse("") {
+            Err(Error::InvalidVersion) => {}
+            other => panic!("unexpected result for empty output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_delete_cleanup() {
+        let root = tempfile::tempdir().unwrap();
+        let state_dir = root.path().join("fake-id");
+        std::fs::create_dir(&state_dir).unwrap();
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        let opts = DeleteOpts::new().force(true).cleanup(true);
+        runc.delete("fake-id", Some(&opts)).expect("true failed.");
+        assert!(!state_dir.exists());
+
+        let cleanup = runc.cleanup("fake-id", None).expect("cleanup failed.");
+        assert!(cleanup.state_dir.is_none());
+        assert_eq!(cleanup.unmounted, 0);
+    }
+
+    #[test]
+    fn test_kill_process_group() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()
+            .expect("failed to spawn sleep");
+        ok_client()
+            .kill_process_group(child.id(), libc::SIGKILL as u32)
+            .expect("failed to kill process group");
+        let status = child.wait().expect("failed to wait sleep");
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn test_kill_with_escalation() {
+        let mock = testing::MockRunc::new();
+        mock.on("state", testing::MockResponse::state("running"));
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::ZERO, 9)
+            .expect("kill with escalation failed");
+        assert!(!stopped);
+        let kills = mock
+            .calls()
+            .into_iter()
+            .filter(|call| call[0] == "kill")
+            .map(|call| call[2].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(kills, vec!["15", "9"]);
+
+        let mock = testing::MockRunc::new();
+        mock.on("state", testing::MockResponse::state("running"))
+            .on("state", testing::MockResponse::state("stopped"));
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .expect("kill with escalation failed");
+        assert!(stopped);
+        assert_eq!(mock.calls().len(), 3);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "kill",
+            testing::MockResponse::failure(1, "container not running"),
+        )
+        .on("state", testing::MockResponse::state("stopped"));
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .expect("kill with escalation failed");
+        assert!(stopped);
+        assert_eq!(mock.calls().len(), 2);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "kill",
+            testing::MockResponse::failure(1, "permission denied"),
+        )
+        .on("state", testing::MockResponse::state("running"));
+        assert!(mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .is_err());
+    }
+
+    #[test]
+    fn test_version() {
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "--version",
+            testing::MockResponse::success(
+                "runc version 1.1.4\ncommit: v1.1.4-0-g5fd4c4d1\nspec: 1.0.2-dev\n",
+            ),
+        );
+        let version = mock.client().version().expect("version failed");
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert_eq!(mock.calls()[0], vec!["--version"]);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "--version",
+            testing::MockResponse::failure(1, "unknown flag"),
+        );
+        assert!(mock.client().version().is_err());
+    }
+
+    #[test]
+    fn test_supports_criu() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu("/nonexistent/criu")
+            .build()
+            .expect("unable to create runc instance");
+        assert!(!runc.supports_criu());
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu("/bin/true")
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.supports_criu());
+    }
+
+    #[test]
+    fn test_is_rootless() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .rootless(true)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.is_rootless());
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .rootless(false)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(!runc.is_rootless());
+
+        let runc = ok_client();
+        assert_eq!(runc.is_rootless(), utils::detect_rootless());
+    }
+
     #[test]
     fn test_output() {
         // test create cmd with inherit Io, expect empty cmd output
@@ -813,6 +1784,20 @@ mod tests {
             .expect("unable to create runc instance")
     }
 
+    fn dummy_process() -> Process {
+        serde_json::from_str(
+            "
+            {
+                \"user\": {
+                    \"uid\": 1000,
+                    \"gid\": 1000
+                },
+                \"cwd\": \"/path/to/dir\"
+            }",
+        )
+        .unwrap()
+    }
+
     #[tokio::test]
     async fn test_async_create() {
         let opts = CreateOpts::new();
@@ -839,6 +1824,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -870,6 +1856,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -909,6 +1896,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -923,6 +1911,134 @@ mod tests {
         .expect("tokio spawn falied.");
     }
 
+    #[tokio::test]
+    async fn test_async_exec() {
+        let opts = ExecOpts::new();
+        let ok_runc = ok_client();
+        let ok_task = tokio::spawn(async move {
+            let proc = dummy_process();
+            let response = ok_runc
+                .exec("fake-id", &proc, Some(&opts))
+                .await
+                .expect("true failed.");
+            assert_ne!(response.pid, 0);
+            assert!(response.status.success());
+            assert!(response.output.is_empty());
+        });
+
+        let opts = ExecOpts::new();
+        let fail_runc = fail_client();
+        let fail_task = tokio::spawn(async move {
+            let proc = dummy_process();
+            match fail_runc.exec("fake-id", &proc, Some(&opts)).await {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                    ..
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        });
+
+        ok_task.await.expect("ok_task failed.");
+        fail_task.await.expect("fail_task unexpectedly succeeded.");
+    }
+
+    #[tokio::test]
+    async fn test_async_wait_for_status() {
+        let mock = testing::MockRunc::new();
+        mock.on("state", testing::MockResponse::state("created"))
+            .on("state", testing::MockResponse::state("running"));
+        let container = mock
+            .client()
+            .wait_for_status("fake-id", ContainerStatus::Running, Duration::from_secs(5))
+            .await
+            .expect("wait for running failed");
+        assert_eq!(container.pid, 42);
+        assert_eq!(mock.calls().len(), 2);
+
+        let mock = testing::MockRunc::new();
+        mock.on("state", testing::MockResponse::state("stopped"));
+        match mock
+            .client()
+            .wait_for_status(
+                "fake-id",
+                ContainerStatus::Running,
+                Duration::from_millis(50),
+            )
+            .await
+        {
+            Err(Error::CommandTimeout(_)) => {}
+            res => panic!("unexpected result of wait for status: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_kill_with_escalation() {
+        let mock = testing::MockRunc::new();
+        mock.on("state", testing::MockResponse::state("running"));
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::ZERO, 9)
+            .await
+            .expect("kill with escalation failed");
+        assert!(!stopped);
+        let kills = mock
+            .calls()
+            .into_iter()
+            .filter(|call| call[0] == "kill")
+            .map(|call| call[2].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(kills, vec!["15", "9"]);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "kill",
+            testing::MockResponse::failure(1, "container does not exist"),
+        );
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .await
+            .expect("kill with escalation failed");
+        assert!(stopped);
+        assert_eq!(mock.calls().len(), 1);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "kill",
+            testing::MockResponse::failure(1, "container not running"),
+        )
+        .on("state", testing::MockResponse::state("stopped"));
+        let stopped = mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .await
+            .expect("kill with escalation failed");
+        assert!(stopped);
+        assert_eq!(mock.calls().len(), 2);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "kill",
+            testing::MockResponse::failure(1, "permission denied"),
+        )
+        .on("state", testing::MockResponse::state("running"));
+        assert!(mock
+            .client()
+            .kill_with_escalation("fake-id", 15, Duration::from_secs(5), 9)
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     async fn test_async_delete() {
         let opts = DeleteOpts::new();
@@ -944,6 +2060,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -958,6 +2075,254 @@ mod tests {
         .expect("tokio spawn falied.");
     }
 
+    #[tokio::test]
+    async fn test_async_checkpoint() {
+        let opts = CheckpointOpts::new().leave_running(true);
+        let ok_runc = ok_client();
+        let ok_task = tokio::spawn(async move {
+            let response = ok_runc
+                .checkpoint("fake-id", Some(&opts))
+                .await
+                .expect("true failed.");
+            assert_ne!(response.pid, 0);
+            assert!(response.status.success());
+            assert!(response.output.is_empty());
+        });
+
+        let opts = CheckpointOpts::new().leave_running(true);
+        let fail_runc = fail_client();
+        let fail_task = tokio::spawn(async move {
+            match fail_runc.checkpoint("fake-id", Some(&opts)).await {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                    ..
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        });
+
+        ok_task.await.expect("ok_task failed.");
+        fail_task.await.expect("fail_task unexpectedly succeeded.");
+    }
+
+    #[tokio::test]
+    async fn test_async_restore() {
+        let opts = RestoreOpts::new().detach(true);
+        let ok_runc = ok_client();
+        let ok_task = tokio::spawn(async move {
+            let response = ok_runc
+                .restore("fake-id", "fake-bundle", Some(&opts))
+                .await
+                .expect("true failed.");
+            assert_ne!(response.pid, 0);
+            assert!(response.status.success());
+            assert!(response.output.is_empty());
+        });
+
+        let opts = RestoreOpts::new().detach(true);
+        let fail_runc = fail_client();
+        let fail_task = tokio::spawn(async move {
+            match fail_runc
+                .restore("fake-id", "fake-bundle", Some(&opts))
+                .await
+            {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                    ..
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        });
+
+        ok_task.await.expect("ok_task failed.");
+        fail_task.await.expect("fail_task unexpectedly succeeded.");
+    }
+
+    #[derive(Debug)]
+    struct SleepSpawner {}
+
+    #[async_trait]
+    impl Spawner for SleepSpawner {
+        async fn execute(&self, _cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Err(Error::Unimplemented("sleep".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout() {
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(Arc::new(SleepSpawner {})).timeout(100);
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.start("fake-id").await {
+            Err(Error::CommandTimeout(_)) => {}
+            other => panic!("unexpected result without timeout: {:?}", other),
+        }
+
+        // the timeout can be overridden per client clone
+        let runc = runc.with_timeout(Duration::from_millis(200));
+        match runc.start("fake-id").await {
+            Err(Error::CommandTimeout(_)) => {}
+            other => panic!("unexpected result with timeout override: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_events() {
+        use futures::StreamExt;
+
+        let ok_runc = ok_client();
+        let mut stream = ok_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .await
+            .expect("true failed.");
+        assert!(stream.next().await.is_none());
+
+        // echo prints its arguments, which is not a valid event
+        let echo_runc = echo_client();
+        let mut stream = echo_runc
+            .events("fake-id", &std::time::Duration::from_secs(1))
+            .await
+            .expect("echo failed.");
+        match stream.next().await {
+            Some(Err(Error::JsonDeserializationFailed(_))) => {}
+            other => panic!("unexpected event from echo_runc: {:?}", other),
+        }
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_launch_streaming() {
+        use futures::StreamExt;
+
+        use crate::stream::OutputLine;
+
+        let echo_runc = echo_client();
+        let mut stream = echo_runc
+            .launch_streaming(&["hello".to_string()])
+            .await
+            .expect("echo failed.");
+        match stream.next().await {
+            Some(Ok(OutputLine::Stdout(line))) => assert!(line.ends_with("hello")),
+            other => panic!("unexpected line from echo_runc: {:?}", other),
+        }
+        assert!(stream.next().await.is_none());
+        assert!(stream.wait().await.expect("wait failed.").success());
+    }
+
+    #[tokio::test]
+    async fn test_async_launch_streaming_wait_first() {
+        use futures::StreamExt;
+
+        use crate::stream::OutputLine;
+
+        // more lines than the stream buffers, printed before any is read
+        let sh_runc = GlobalOpts::new()
+            .command("/bin/sh")
+            .build()
+            .expect("unable to create runc instance");
+        let mut stream = sh_runc
+            .launch_streaming(&["-c".to_string(), "seq 1000".to_string()])
+            .await
+            .expect("sh failed.");
+        assert!(stream.wait().await.expect("wait failed.").success());
+        let lines: Vec<_> = stream.map(|line| line.unwrap()).collect().await;
+        assert_eq!(lines.len(), 1000);
+        assert_eq!(lines[999], OutputLine::Stdout("1000".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_async_top() {
+        let mock = testing::MockRunc::new();
+        mock.on("ps", testing::MockResponse::success("PID CMD\n1 sleep\n"));
+        let top = mock
+            .client()
+            .top("fake-id", "-o pid,cmd")
+            .await
+            .expect("top failed");
+        assert_eq!(top.processes, vec![vec!["1", "sleep"]]);
+        assert_eq!(
+            mock.calls()[0],
+            vec!["ps", "--format=table", "fake-id", "-o", "pid,cmd"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_version() {
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "--version",
+            testing::MockResponse::success(
+                "runc version 1.1.4\ncommit: v1.1.4-0-g5fd4c4d1\nspec: 1.0.2-dev\n",
+            ),
+        );
+        let version = mock.client().version().await.expect("version failed");
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert_eq!(mock.calls()[0], vec!["--version"]);
+
+        let mock = testing::MockRunc::new();
+        mock.on(
+            "--version",
+            testing::MockResponse::failure(1, "unknown flag"),
+        );
+        assert!(mock.client().version().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_supports_criu() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu("/nonexistent/criu")
+            .build()
+            .expect("unable to create runc instance");
+        assert!(!runc.supports_criu());
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu("/bin/true")
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.supports_criu());
+    }
+
+    #[tokio::test]
+    async fn test_async_is_rootless() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .rootless(true)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(runc.is_rootless());
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .rootless(false)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(!runc.is_rootless());
+
+        let runc = ok_client();
+        assert_eq!(runc.is_rootless(), utils::detect_rootless());
+    }
+
     #[tokio::test]
     async fn test_async_output() {
         // test create cmd with inherit Io, expect empty cmd output
@@ -993,12 +2358,16 @@ pub struct DefaultExecutor {}
 impl Spawner for DefaultExecutor {
     async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
         let mut cmd = cmd;
+        // make sure the runc process is killed if the command is cancelled by a timeout
+        cmd.kill_on_drop(true);
         let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
         let pid = child.id().unwrap();
+        let group = ProcessGroupGuard::new(pid);
         let result = child
             .wait_with_output()
             .await
             .map_err(Error::InvalidCommand)?;
+        group.disarm();
         let status = result.status;
         let stdout = String::from_utf8_lossy(&result.stdout).to_string();
         let stderr = String::from_utf8_lossy(&result.stderr).to_string();
@@ -1006,6 +2375,38 @@ impl Spawner for DefaultExecutor {
     }
 }
 
+/// Kill the process group led by runc if the command is cancelled before runc exits,
+/// so that its children do not linger. Nothing is done if runc is not a group leader.
+#[cfg(feature = "async")]
+struct ProcessGroupGuard {
+    pgid: Option<Pid>,
+}
+
+#[cfg(feature = "async")]
+impl ProcessGroupGuard {
+    fn new(pid: u32) -> Self {
+        let pid = Pid::from_raw(pid as i32);
+        let pgid = match nix::unistd::getpgid(Some(pid)) {
+            Ok(pgid) if pgid == pid => Some(pgid),
+            _ => None,
+        };
+        Self { pgid }
+    }
+
+    fn disarm(mut self) {
+        self.pgid = None;
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            killpg(pgid, Signal::SIGKILL).unwrap_or_default();
+        }
+    }
+}
+
 #[cfg(not(feature = "async"))]
 impl Spawner for DefaultExecutor {
     fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
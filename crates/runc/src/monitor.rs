@@ -14,17 +14,29 @@
    limitations under the License.
 */
 
-use std::process::{ExitStatus, Output};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    os::unix::process::ExitStatusExt,
+    process::{ExitStatus, Output},
+    sync::{Arc, Mutex, Weak},
+};
 
 use async_trait::async_trait;
-use log::error;
+use log::{debug, error};
+use nix::{
+    errno::Errno,
+    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
+};
 use time::OffsetDateTime;
 use tokio::{
+    io::AsyncReadExt,
     process::Command,
+    signal::unix::{signal, SignalKind},
     sync::oneshot::{channel, Receiver, Sender},
 };
 
-use crate::error::Error;
+use crate::{error::Error, ExitReason, Spawner};
 
 /// A trait for spawning and waiting for a process.
 ///
@@ -50,9 +62,13 @@ pub trait ProcessMonitor {
             .expect("failed to take pid of the container process.");
         let out = chi.wait_with_output().await?;
         let ts = OffsetDateTime::now_utc();
-        // On Unix, out.status.code() will return None if the process was terminated by a signal.
-        let status = out.status.code().unwrap_or(-1);
-        match tx.send(Exit { ts, pid, status }) {
+        let reason = ExitReason::from(out.status);
+        match tx.send(Exit {
+            ts,
+            pid,
+            status: reason.exit_status(),
+            reason,
+        }) {
             Ok(_) => Ok(out),
             Err(e) => {
                 error!("command {:?} exited but receiver dropped.", cmd);
@@ -84,11 +100,188 @@ impl DefaultMonitor {
 }
 
 /// Process exit status returned by [ProcessMonitor::wait()].
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Exit {
     pub ts: OffsetDateTime,
     pub pid: u32,
+    /// Exit status as reported to containerd, see [ExitReason::exit_status()].
     pub status: i32,
+    pub reason: ExitReason,
+}
+
+/// Set the current process as a child subreaper.
+///
+/// Orphaned descendants, such as the init of a container created by `runc create --detach`,
+/// are then re-parented to the current process, which has to reap them.
+#[cfg(target_os = "linux")]
+pub fn set_subreaper() -> Result<(), Error> {
+    let ret = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    Errno::result(ret).map(|_| ()).map_err(Error::SetSubreaper)
+}
+
+/// How long the exit of a descendant nobody waited for is kept by a [ReaperMonitor].
+const UNCLAIMED_EXIT_TTL: time::Duration = time::Duration::seconds(60);
+
+/// A [ProcessMonitor] for a subreaper, reaping every descendant of the current process.
+///
+/// Exits are resolved by pid, so that the exit of a process not spawned by the monitor, e.g. the
+/// init of a detached container, can be waited for with [ReaperMonitor::wait_pid()].
+/// As all the children are reaped on SIGCHLD, the process must not wait for its children by
+/// other means while the monitor is alive.
+#[derive(Debug, Clone)]
+pub struct ReaperMonitor {
+    state: Arc<Mutex<ReaperState>>,
+}
+
+#[derive(Debug, Default)]
+struct ReaperState {
+    waiters: HashMap<u32, Vec<Sender<(Exit, ExitStatus)>>>,
+    /// Exits nobody waited for yet, kept until [ReaperMonitor::wait_pid()] is called or for
+    /// [UNCLAIMED_EXIT_TTL] at most, as the pid can be reused afterwards.
+    exited: HashMap<u32, (Exit, ExitStatus)>,
+}
+
+impl ReaperState {
+    /// Send the exit to the waiters of the pid, or keep it for a later waiter.
+    fn exited(&mut self, exit: Exit, status: ExitStatus) {
+        match self.waiters.remove(&exit.pid) {
+            Some(waiters) => {
+                for tx in waiters {
+                    let _ = tx.send((exit.clone(), status));
+                }
+            }
+            None => {
+                let now = OffsetDateTime::now_utc();
+                self.exited
+                    .retain(|_, (e, _)| now - e.ts < UNCLAIMED_EXIT_TTL);
+                self.exited.insert(exit.pid, (exit, status));
+            }
+        }
+    }
+
+    /// Register a waiter for the pid, resolved at once by a kept exit if `claim` is set,
+    /// otherwise a kept exit belongs to an earlier process with the same pid and is dropped.
+    fn subscribe(&mut self, pid: u32, claim: bool) -> Receiver<(Exit, ExitStatus)> {
+        let (tx, rx) = channel();
+        match self.exited.remove(&pid) {
+            Some(exit) if claim => {
+                let _ = tx.send(exit);
+            }
+            _ => self.waiters.entry(pid).or_default().push(tx),
+        }
+        rx
+    }
+}
+
+impl ReaperMonitor {
+    /// Set the current process as a subreaper and start reaping on SIGCHLD.
+    ///
+    /// This must be called on a tokio runtime.
+    #[cfg(target_os = "linux")]
+    pub fn new() -> Result<Self, Error> {
+        set_subreaper()?;
+        let mut sigchld = signal(SignalKind::child()).map_err(Error::InvalidCommand)?;
+        let state = Arc::new(Mutex::new(ReaperState::default()));
+        let weak = Arc::downgrade(&state);
+        tokio::spawn(async move {
+            // reap once first, for the exits that happened before the handler was set
+            while Self::reap(&weak) && sigchld.recv().await.is_some() {}
+        });
+        Ok(Self { state })
+    }
+
+    /// Wait for the exit of a descendant, resolved at once if it already exited.
+    pub async fn wait_pid(&self, pid: u32) -> std::io::Result<Exit> {
+        let exited = self.state.lock().unwrap().subscribe(pid, true);
+        let (exit, _) = exited.await.map_err(|_| {
+            error!("reaper stopped before the exit of {}", pid);
+            std::io::Error::from(std::io::ErrorKind::BrokenPipe)
+        })?;
+        Ok(exit)
+    }
+
+    /// Reap all the exited children, returns false once the monitor is dropped.
+    fn reap(state: &Weak<Mutex<ReaperState>>) -> bool {
+        let state = match state.upgrade() {
+            Some(s) => s,
+            None => return false,
+        };
+        loop {
+            let (pid, raw) = match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, code)) => (pid, code << 8),
+                Ok(WaitStatus::Signaled(pid, sig, core)) => {
+                    (pid, sig as i32 | if core { 0x80 } else { 0 })
+                }
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return true,
+                Ok(_) => continue,
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    error!("failed to reap children: {}", e);
+                    return true;
+                }
+            };
+            let pid = pid.as_raw() as u32;
+            let exit_status = ExitStatus::from_raw(raw);
+            let reason = ExitReason::from(exit_status);
+            debug!("reaped {} with {:?}", pid, reason);
+            let exit = Exit {
+                ts: OffsetDateTime::now_utc(),
+                pid,
+                status: reason.exit_status(),
+                reason,
+            };
+            state.lock().unwrap().exited(exit, exit_status);
+        }
+    }
+}
+
+#[async_trait]
+impl ProcessMonitor for ReaperMonitor {
+    async fn start(&self, mut cmd: Command, tx: Sender<Exit>) -> std::io::Result<Output> {
+        // the reaper can not record the exit of the child before the subscription while the
+        // lock is held, so a kept exit for its pid is a stale one of an earlier process
+        let (mut child, exited) = {
+            let mut state = self.state.lock().unwrap();
+            let child = cmd.spawn()?;
+            let pid = child
+                .id()
+                .expect("failed to take pid of the container process.");
+            let exited = state.subscribe(pid, false);
+            (child, exited)
+        };
+        let mut child_stdout = child.stdout.take();
+        let mut child_stderr = child.stderr.take();
+        let read_stdout = async {
+            let mut buf = vec![];
+            if let Some(out) = child_stdout.as_mut() {
+                out.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+        let read_stderr = async {
+            let mut buf = vec![];
+            if let Some(err) = child_stderr.as_mut() {
+                err.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+        let (stdout, stderr) = tokio::try_join!(read_stdout, read_stderr)?;
+        let (exit, status) = exited
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        match tx.send(exit) {
+            Ok(_) => Ok(Output {
+                status,
+                stdout,
+                stderr,
+            }),
+            Err(e) => {
+                error!("command {:?} exited but receiver dropped.", cmd);
+                error!("couldn't send messages: {:?}", e);
+                Err(std::io::ErrorKind::ConnectionRefused.into())
+            }
+        }
+    }
 }
 
 /// Execution result returned by `execute()`.
@@ -130,6 +323,32 @@ pub async fn execute<T: ProcessMonitor + Send + Sync>(
     })
 }
 
+/// A [Spawner] running runc commands with a [ProcessMonitor], so that they can be spawned and
+/// reaped by the embedder, e.g. by a subreaper, instead of the default wait logic.
+///
+/// Set it with [GlobalOpts::monitor()](crate::options::GlobalOpts::monitor).
+#[derive(Debug)]
+pub struct MonitorSpawner<T> {
+    monitor: T,
+}
+
+impl<T> MonitorSpawner<T> {
+    pub fn new(monitor: T) -> Self {
+        Self { monitor }
+    }
+}
+
+#[async_trait]
+impl<T> Spawner for MonitorSpawner<T>
+where
+    T: ProcessMonitor + Debug + Send + Sync,
+{
+    async fn execute(&self, cmd: Command) -> crate::Result<(ExitStatus, u32, String, String)> {
+        let result = execute(&self.monitor, cmd).await?;
+        Ok((result.status, result.exit.pid, result.stdout, result.stderr))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::process::Stdio;
@@ -177,4 +396,101 @@ mod tests {
         assert!(!result.stdout.is_empty());
         assert_eq!(result.stderr.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_monitor_spawner() {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "echo -n hello; exit 3"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let spawner = MonitorSpawner::new(DefaultMonitor::new());
+        let (status, pid, stdout, stderr) = spawner.execute(cmd).await.unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        assert_ne!(pid, 0);
+        assert_eq!(stdout, "hello");
+        assert!(stderr.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exit_reason() {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "kill -9 $$"]);
+        let monitor = DefaultMonitor::new();
+        let result = execute(&monitor, cmd).await.unwrap();
+
+        assert_eq!(result.exit.reason, ExitReason::Signal(9));
+        assert_eq!(result.exit.status, 137);
+        assert_eq!(ExitReason::from(result.status), ExitReason::Signal(9));
+    }
+
+    fn exit_of(pid: u32, code: i32, ts: OffsetDateTime) -> (Exit, ExitStatus) {
+        let status = ExitStatus::from_raw(code << 8);
+        let reason = ExitReason::from(status);
+        let exit = Exit {
+            ts,
+            pid,
+            status: reason.exit_status(),
+            reason,
+        };
+        (exit, status)
+    }
+
+    #[tokio::test]
+    async fn test_reaper_kept_exit() {
+        let monitor = ReaperMonitor {
+            state: Default::default(),
+        };
+        let (exit, status) = exit_of(100, 3, OffsetDateTime::now_utc());
+        monitor.state.lock().unwrap().exited(exit, status);
+
+        let exit = monitor.wait_pid(100).await.unwrap();
+        assert_eq!(exit.pid, 100);
+        assert_eq!(exit.status, 3);
+        assert!(monitor.state.lock().unwrap().exited.is_empty());
+    }
+
+    #[test]
+    fn test_reaper_waiters() {
+        let mut state = ReaperState::default();
+        let mut first = state.subscribe(100, true);
+        let mut second = state.subscribe(100, true);
+        assert!(first.try_recv().is_err());
+
+        let (exit, status) = exit_of(100, 1, OffsetDateTime::now_utc());
+        state.exited(exit, status);
+        assert_eq!(first.try_recv().unwrap().0.status, 1);
+        assert_eq!(second.try_recv().unwrap().1.code(), Some(1));
+        assert!(state.waiters.is_empty());
+        assert!(state.exited.is_empty());
+    }
+
+    #[test]
+    fn test_reaper_stale_exit() {
+        let mut state = ReaperState::default();
+        let (exit, status) = exit_of(100, 1, OffsetDateTime::now_utc());
+        state.exited(exit, status);
+
+        // a new child with a reused pid must not get the exit of the earlier one
+        let mut exited = state.subscribe(100, false);
+        assert!(exited.try_recv().is_err());
+        assert!(state.exited.is_empty());
+
+        let (exit, status) = exit_of(100, 2, OffsetDateTime::now_utc());
+        state.exited(exit, status);
+        assert_eq!(exited.try_recv().unwrap().0.status, 2);
+    }
+
+    #[test]
+    fn test_reaper_unclaimed_exit_expires() {
+        let mut state = ReaperState::default();
+        let old = OffsetDateTime::now_utc() - UNCLAIMED_EXIT_TTL - time::Duration::seconds(1);
+        let (exit, status) = exit_of(100, 1, old);
+        state.exited(exit, status);
+        let (exit, status) = exit_of(200, 1, OffsetDateTime::now_utc());
+        state.exited(exit, status);
+
+        assert!(!state.exited.contains_key(&100));
+        assert!(state.exited.contains_key(&200));
+    }
 }
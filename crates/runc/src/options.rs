@@ -39,18 +39,26 @@ use std::{
     time::Duration,
 };
 
-use crate::{error::Error, io::Io, utils, DefaultExecutor, LogFormat, Runc, Spawner};
+use oci_spec::runtime::Process;
+
+#[cfg(feature = "async")]
+use crate::monitor::{MonitorSpawner, ProcessMonitor};
+use crate::{
+    bundle, error::Error, io::Io, utils, utils::RuncBinary, DefaultExecutor, LogFormat, Runc,
+    Spawner,
+};
 
 // constants for log format
 pub const JSON: &str = "json";
 pub const TEXT: &str = "text";
 
 // constants for runc global flags
+pub(crate) const CRIU: &str = "--criu";
 const DEBUG: &str = "--debug";
-const LOG: &str = "--log";
-const LOG_FORMAT: &str = "--log-format";
-const ROOT: &str = "--root";
-const ROOTLESS: &str = "--rootless";
+pub(crate) const LOG: &str = "--log";
+pub(crate) const LOG_FORMAT: &str = "--log-format";
+pub(crate) const ROOT: &str = "--root";
+pub(crate) const ROOTLESS: &str = "--rootless";
 const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
 
 // constants for runc-create/runc-exec flags
@@ -66,49 +74,191 @@ const ALL: &str = "--all";
 // constants for runc-delete flags
 const FORCE: &str = "--force";
 
+// constants for runc-checkpoint/runc-restore flags
+const IMAGE_PATH: &str = "--image-path";
+const WORK_PATH: &str = "--work-path";
+const PARENT_PATH: &str = "--parent-path";
+const LEAVE_RUNNING: &str = "--leave-running";
+const TCP_ESTABLISHED: &str = "--tcp-established";
+const EXT_UNIX_SK: &str = "--ext-unix-sk";
+const SHELL_JOB: &str = "--shell-job";
+const FILE_LOCKS: &str = "--file-locks";
+const PRE_DUMP: &str = "--pre-dump";
+const MANAGE_CGROUPS_MODE: &str = "--manage-cgroups-mode";
+const EMPTY_NS: &str = "--empty-ns";
+const NO_SUBREAPER: &str = "--no-subreaper";
+
+// constants for runc-spec flags
+const BUNDLE: &str = "--bundle";
+
 // constant for command
 pub const DEFAULT_COMMAND: &str = "runc";
 
+// default root directory of runc
+pub(crate) const DEFAULT_ROOT: &str = "/run/runc";
+
 pub trait Args {
     type Output;
 
     fn args(&self) -> Self::Output;
 }
 
-/// Global options builder for the runc binary.
+/// Retry policy of the runc client for `delete` and the unmount of a rootfs,
+/// which may fail transiently with `EBUSY` or `EAGAIN` right after a kill.
 ///
-/// These options will be passed for all subsequent runc calls.
-/// See <https://github.com/opencontainers/runc/blob/main/man/runc.8.md#global-options>
-#[derive(Debug, Default)]
-pub struct GlobalOpts {
-    /// Override the name of the runc binary. If [`None`], `runc` is used.
-    command: Option<PathBuf>,
+/// The default makes a single attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every following retry.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+        Self {
+            max_attempts,
+            backoff,
+        }
+    }
+
+    /// Delay before the retry following the failed `attempt`, counting from 1,
+    /// or [`None`] if no attempt is left.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        Some(self.backoff * 2u32.saturating_pow(attempt - 1))
+    }
+}
+
+/// Global flags of runc, passed before the subcommand of every command of a client.
+///
+/// They are set when building the client with [`GlobalOpts`], and can be replaced for
+/// some commands with [`Runc::with_global_flags`], e.g. to work on another root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlobalFlags {
+    /// Path to root directory of container rootfs.
+    pub root: Option<PathBuf>,
     /// Debug logging.
     ///
     /// If true, debug level logs are emitted.
-    debug: bool,
+    pub debug: bool,
     /// Path to log file.
-    log: Option<PathBuf>,
+    pub log: Option<PathBuf>,
     /// Log format to use.
-    log_format: LogFormat,
-    /// Path to root directory of container rootfs.
-    root: Option<PathBuf>,
+    pub log_format: LogFormat,
+    /// Use systemd cgroup.
+    pub systemd_cgroup: bool,
     /// Whether to use rootless mode.
     ///
     /// If [`None`], `auto` settings is used.
     /// Note that "auto" is different from explicit "true" or "false".
-    rootless: Option<bool>,
+    pub rootless: Option<bool>,
+    /// Path to the criu binary used for checkpoint and restore.
+    pub criu: Option<PathBuf>,
+}
+
+impl Args for GlobalFlags {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args = Vec::new();
+
+        // --root path : Set the root directory to store containers' state.
+        if let Some(root) = &self.root {
+            args.push(ROOT.into());
+            args.push(utils::abs_string(root)?);
+        }
+
+        // --debug : Enable debug logging.
+        if self.debug {
+            args.push(DEBUG.into());
+        }
+
+        // --log path : Set the log destination to path. The default is to log to stderr.
+        if let Some(log_path) = &self.log {
+            args.push(LOG.into());
+            args.push(utils::abs_string(log_path)?);
+        }
+
+        // --log-format text|json : Set the log format (default is text).
+        args.push(LOG_FORMAT.into());
+        args.push(self.log_format.to_string());
+
+        // --systemd-cgroup : Enable systemd cgroup support.
+        if self.systemd_cgroup {
+            args.push(SYSTEMD_CGROUP.into());
+        }
+
+        // --rootless true|false|auto : Enable or disable rootless mode.
+        if let Some(mode) = self.rootless {
+            let arg = format!("{}={}", ROOTLESS, mode);
+            args.push(arg);
+        }
+
+        // --criu path : Path to the criu binary used for checkpoint and restore.
+        if let Some(criu) = &self.criu {
+            args.push(CRIU.into());
+            // a bare name is passed as is, for runc to look it up in $PATH
+            if criu.parent() == Some(Path::new("")) {
+                args.push(criu.to_string_lossy().to_string());
+            } else {
+                args.push(utils::abs_string(criu)?);
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+/// Global options builder for the runc binary.
+///
+/// These options will be passed for all subsequent runc calls.
+/// See <https://github.com/opencontainers/runc/blob/main/man/runc.8.md#global-options>
+#[derive(Debug, Default)]
+pub struct GlobalOpts {
+    /// Override the name of the runc binary. If [`None`], `runc` is used.
+    command: Option<PathBuf>,
+    /// Global flags passed to every runc command.
+    flags: GlobalFlags,
     /// Set process group ID (gpid).
     set_pgid: bool,
-    /// Use systemd cgroup.
-    systemd_cgroup: bool,
     /// Timeout settings for runc command.
     ///
-    /// Default is 5 seconds.
+    /// Default is zero, meaning no timeout.
     /// This will be used only in AsyncClient.
     timeout: Duration,
     /// executor that runs the commands
     executor: Option<Arc<dyn Spawner + Send + Sync>>,
+    /// Extra global arguments passed to runc, after the ones generated from the options.
+    extra_args: Vec<String>,
+    /// Environment variables set for runc commands.
+    env: Vec<(String, String)>,
+    /// Clear the inherited environment before setting `env`.
+    clear_env: bool,
+    /// Directory of the temp files holding the specs passed to `exec` and `update`.
+    ///
+    /// If [`None`], `$XDG_RUNTIME_DIR` is used, or the system temp dir when it is unset.
+    temp_dir: Option<PathBuf>,
+    /// Pass the specs of `exec` and `update` to runc through its stdin instead of temp files.
+    ///
+    /// `exec` still uses a temp file when an [Io](crate::io::Io) is set, as it owns stdin.
+    spec_stdin: bool,
+    /// Retry policy for `delete` and the unmount of a rootfs failing transiently.
+    retry: RetryPolicy,
+    /// Minimum version of runc checked when the client is built.
+    min_version: Option<String>,
 }
 
 impl GlobalOpts {
@@ -127,13 +277,21 @@ impl GlobalOpts {
     /// The path should be located on tmpfs.
     /// Default is `/run/runc`, or `$XDG_RUNTIME_DIR/runc` for rootless containers.
     pub fn root(mut self, root: impl AsRef<Path>) -> Self {
-        self.root = Some(root.as_ref().to_path_buf());
+        self.flags.root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the path to the criu binary used for checkpoint and restore.
+    ///
+    /// Default is `criu` found in `$PATH`.
+    pub fn criu(mut self, criu: impl AsRef<Path>) -> Self {
+        self.flags.criu = Some(criu.as_ref().to_path_buf());
         self
     }
 
     /// Enable debug logging.
     pub fn debug(mut self, debug: bool) -> Self {
-        self.debug = debug;
+        self.flags.debug = debug;
         self
     }
 
@@ -141,13 +299,13 @@ impl GlobalOpts {
     ///
     /// The default is to log to stderr.
     pub fn log(mut self, log: impl AsRef<Path>) -> Self {
-        self.log = Some(log.as_ref().to_path_buf());
+        self.flags.log = Some(log.as_ref().to_path_buf());
         self
     }
 
     /// Set the log format (default is text).
     pub fn log_format(mut self, log_format: LogFormat) -> Self {
-        self.log_format = log_format;
+        self.flags.log_format = log_format;
         self
     }
 
@@ -166,7 +324,7 @@ impl GlobalOpts {
     /// If this is set, the container spec (`config.json`) is expected to have `cgroupsPath` value in
     // the `slice:prefix:name` form (e.g. `system.slice:runc:434234`).
     pub fn systemd_cgroup(mut self, systemd_cgroup: bool) -> Self {
-        self.systemd_cgroup = systemd_cgroup;
+        self.flags.systemd_cgroup = systemd_cgroup;
         self
     }
 
@@ -174,16 +332,17 @@ impl GlobalOpts {
     ///
     // Default is auto, meaning to auto-detect whether rootless should be enabled.
     pub fn rootless(mut self, rootless: bool) -> Self {
-        self.rootless = Some(rootless);
+        self.flags.rootless = Some(rootless);
         self
     }
 
     /// Set rootless mode to auto.
     pub fn rootless_auto(mut self) -> Self {
-        self.rootless = None;
+        self.flags.rootless = None;
         self
     }
 
+    /// Run runc in a new process group, so that it can be killed together with its children.
     pub fn set_pgid(mut self, set_pgid: bool) -> Self {
         self.set_pgid = set_pgid;
         self
@@ -194,57 +353,76 @@ impl GlobalOpts {
         self
     }
 
+    /// Set an environment variable for runc commands, e.g. `RUNC_LOG`.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Append extra global arguments, e.g. experimental runc flags.
+    pub fn extra_args(mut self, args: &[String]) -> Self {
+        self.extra_args.extend_from_slice(args);
+        self
+    }
+
+    /// Do not inherit the environment of the current process in runc commands.
+    pub fn clear_env(mut self) -> Self {
+        self.clear_env = true;
+        self
+    }
+
+    /// Write the spec temp files of `exec` and `update` under `dir`.
+    pub fn temp_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.temp_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Pass the specs of `exec` and `update` through stdin, no temp file is written.
+    pub fn spec_stdin(mut self, spec_stdin: bool) -> Self {
+        self.spec_stdin = spec_stdin;
+        self
+    }
+
+    /// Retry `delete` and the unmount of a rootfs on transient failures as `policy` says.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Fail to build the client with [`Error::IncompatibleVersion`] if runc is older than `version`.
+    pub fn min_version(mut self, version: impl Into<String>) -> Self {
+        self.min_version = Some(version.into());
+        self
+    }
+
     pub fn custom_spawner(&mut self, executor: Arc<dyn Spawner + Send + Sync>) -> &mut Self {
         self.executor = Some(executor);
         self
     }
 
+    /// Spawn and wait for runc commands with `monitor`, e.g. to integrate with a shim's reaper.
+    #[cfg(feature = "async")]
+    pub fn monitor<T>(&mut self, monitor: T) -> &mut Self
+    where
+        T: ProcessMonitor + std::fmt::Debug + Send + Sync + 'static,
+    {
+        self.custom_spawner(Arc::new(MonitorSpawner::new(monitor)))
+    }
+
     pub fn build(self) -> Result<Runc, Error> {
         self.args()
     }
 
-    fn output(&self) -> Result<(PathBuf, Vec<String>), Error> {
+    fn output(&self) -> Result<(RuncBinary, Vec<String>), Error> {
         let path = self
             .command
             .clone()
             .unwrap_or_else(|| PathBuf::from("runc"));
 
-        let command = utils::binary_path(path).ok_or(Error::NotFound)?;
-
-        let mut args = Vec::new();
-
-        // --root path : Set the root directory to store containers' state.
-        if let Some(root) = &self.root {
-            args.push(ROOT.into());
-            args.push(utils::abs_string(root)?);
-        }
-
-        // --debug : Enable debug logging.
-        if self.debug {
-            args.push(DEBUG.into());
-        }
-
-        // --log path : Set the log destination to path. The default is to log to stderr.
-        if let Some(log_path) = &self.log {
-            args.push(LOG.into());
-            args.push(utils::abs_string(log_path)?);
-        }
-
-        // --log-format text|json : Set the log format (default is text).
-        args.push(LOG_FORMAT.into());
-        args.push(self.log_format.to_string());
-
-        // --systemd-cgroup : Enable systemd cgroup support.
-        if self.systemd_cgroup {
-            args.push(SYSTEMD_CGROUP.into());
-        }
-
-        // --rootless true|false|auto : Enable or disable rootless mode.
-        if let Some(mode) = self.rootless {
-            let arg = format!("{}={}", ROOTLESS, mode);
-            args.push(arg);
-        }
-        Ok((command, args))
+        let binary = RuncBinary::resolve(path)?;
+        let mut args = self.flags.args()?;
+        args.extend_from_slice(&self.extra_args);
+        Ok((binary, args))
     }
 }
 
@@ -252,16 +430,30 @@ impl Args for GlobalOpts {
     type Output = Result<Runc, Error>;
 
     fn args(&self) -> Self::Output {
-        let (command, args) = self.output()?;
+        let (binary, args) = self.output()?;
+        let version = match &self.min_version {
+            Some(min) => Some(binary.check_version(min)?),
+            None => None,
+        };
         let executor = if let Some(exec) = self.executor.clone() {
             exec
         } else {
             Arc::new(DefaultExecutor {})
         };
         Ok(Runc {
-            command,
+            command: binary.path().to_path_buf(),
             args,
+            flags: self.flags.clone(),
+            extra_args: self.extra_args.clone(),
             spawner: executor,
+            timeout: self.timeout,
+            set_pgid: self.set_pgid,
+            env: self.env.clone(),
+            clear_env: self.clear_env,
+            temp_dir: self.temp_dir.clone(),
+            spec_stdin: self.spec_stdin,
+            retry: self.retry,
+            version,
         })
     }
 }
@@ -279,6 +471,10 @@ pub struct CreateOpts {
     pub no_pivot: bool,
     /// A new session keyring for the container will not be created.
     pub no_new_keyring: bool,
+    /// AppArmor profile the process of the container's `config.json` must run with.
+    pub apparmor_profile: Option<String>,
+    /// SELinux label the process of the container's `config.json` must run with.
+    pub selinux_label: Option<String>,
 }
 
 impl Args for CreateOpts {
@@ -347,6 +543,49 @@ impl CreateOpts {
         self.no_new_keyring = no_new_keyring;
         self
     }
+
+    pub fn apparmor_profile(mut self, profile: impl Into<String>) -> Self {
+        self.apparmor_profile = Some(profile.into());
+        self
+    }
+
+    pub fn selinux_label(mut self, label: impl Into<String>) -> Self {
+        self.selinux_label = Some(label.into());
+        self
+    }
+
+    /// Make sure the security labels asked for are the ones of the bundle's `config.json`,
+    /// as runc takes them from there for the container's process.
+    pub(crate) fn check_labels(&self, bundle: &Path) -> Result<(), Error> {
+        if self.apparmor_profile.is_none() && self.selinux_label.is_none() {
+            return Ok(());
+        }
+        let spec = bundle::read_spec(bundle)?;
+        let process = spec.process().as_ref();
+        let checks = [
+            (
+                "apparmor profile",
+                &self.apparmor_profile,
+                process.and_then(|p| p.apparmor_profile().as_deref()),
+            ),
+            (
+                "selinux label",
+                &self.selinux_label,
+                process.and_then(|p| p.selinux_label().as_deref()),
+            ),
+        ];
+        for (name, expected, actual) in checks.iter() {
+            if let Some(expected) = expected {
+                if *actual != Some(expected.as_str()) {
+                    return Err(Error::InvalidProcessSpec(format!(
+                        "{} of config.json is {:?}, expected {}",
+                        name, actual, expected
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Container execution options
@@ -359,6 +598,10 @@ pub struct ExecOpts {
     pub console_socket: Option<PathBuf>,
     /// Detach from the container's process (only available for run)
     pub detach: bool,
+    /// AppArmor profile to run the process with, overriding the one of its spec.
+    pub apparmor_profile: Option<String>,
+    /// SELinux label to run the process with, overriding the one of its spec.
+    pub selinux_label: Option<String>,
 }
 
 impl Args for ExecOpts {
@@ -411,6 +654,32 @@ impl ExecOpts {
         self.detach = detach;
         self
     }
+
+    pub fn apparmor_profile(mut self, profile: impl Into<String>) -> Self {
+        self.apparmor_profile = Some(profile.into());
+        self
+    }
+
+    pub fn selinux_label(mut self, label: impl Into<String>) -> Self {
+        self.selinux_label = Some(label.into());
+        self
+    }
+
+    /// Return the process spec with the security labels of the options injected,
+    /// or [`None`] if no label is overridden.
+    pub(crate) fn labelled(&self, spec: &Process) -> Option<Process> {
+        if self.apparmor_profile.is_none() && self.selinux_label.is_none() {
+            return None;
+        }
+        let mut spec = spec.clone();
+        if let Some(profile) = &self.apparmor_profile {
+            spec.set_apparmor_profile(Some(profile.clone()));
+        }
+        if let Some(label) = &self.selinux_label {
+            spec.set_selinux_label(Some(label.clone()));
+        }
+        Some(spec)
+    }
 }
 
 /// Container deletion options
@@ -418,6 +687,9 @@ impl ExecOpts {
 pub struct DeleteOpts {
     /// Forcibly delete the container if it is still running
     pub force: bool,
+    /// Also remove what runc may leave behind: the container state directory
+    /// under root and the mounts on the container rootfs.
+    pub cleanup: bool,
 }
 
 impl Args for DeleteOpts {
@@ -441,6 +713,11 @@ impl DeleteOpts {
         self.force = force;
         self
     }
+
+    pub fn cleanup(mut self, cleanup: bool) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
 }
 
 /// Container killing options
@@ -473,6 +750,374 @@ impl KillOpts {
     }
 }
 
+/// Container checkpoint options
+///
+/// See <https://github.com/opencontainers/runc/blob/main/man/runc-checkpoint.8.md>
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointOpts {
+    /// Path for saving criu image files.
+    pub image_path: Option<PathBuf>,
+    /// Path for saving work files and logs.
+    pub work_path: Option<PathBuf>,
+    /// Path for previous criu image files in pre-dump.
+    pub parent_path: Option<PathBuf>,
+    /// Leave the process running after checkpointing.
+    pub leave_running: bool,
+    /// Allow open tcp connections.
+    pub tcp_established: bool,
+    /// Allow external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Allow shell jobs.
+    pub shell_job: bool,
+    /// Handle file locks, for safety.
+    pub file_locks: bool,
+    /// Dump container's memory information only, leave the container running after this.
+    pub pre_dump: bool,
+    /// Cgroups mode: "soft" (default), "full" and "strict".
+    pub manage_cgroups_mode: Option<String>,
+    /// Namespaces which should be created but not restored.
+    pub empty_namespaces: Vec<String>,
+}
+
+impl Args for CheckpointOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if let Some(image_path) = &self.image_path {
+            args.push(IMAGE_PATH.to_string());
+            args.push(utils::abs_string(image_path)?);
+        }
+        if let Some(work_path) = &self.work_path {
+            args.push(WORK_PATH.to_string());
+            args.push(utils::abs_string(work_path)?);
+        }
+        if let Some(parent_path) = &self.parent_path {
+            args.push(PARENT_PATH.to_string());
+            args.push(utils::abs_string(parent_path)?);
+        }
+        if self.leave_running {
+            args.push(LEAVE_RUNNING.to_string());
+        }
+        if self.tcp_established {
+            args.push(TCP_ESTABLISHED.to_string());
+        }
+        if self.ext_unix_sk {
+            args.push(EXT_UNIX_SK.to_string());
+        }
+        if self.shell_job {
+            args.push(SHELL_JOB.to_string());
+        }
+        if self.file_locks {
+            args.push(FILE_LOCKS.to_string());
+        }
+        if self.pre_dump {
+            args.push(PRE_DUMP.to_string());
+        }
+        if let Some(mode) = &self.manage_cgroups_mode {
+            args.push(MANAGE_CGROUPS_MODE.to_string());
+            args.push(mode.to_string());
+        }
+        for ns in &self.empty_namespaces {
+            args.push(EMPTY_NS.to_string());
+            args.push(ns.to_string());
+        }
+        Ok(args)
+    }
+}
+
+impl CheckpointOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn parent_path<P>(mut self, parent_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.parent_path = Some(parent_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn leave_running(mut self, leave_running: bool) -> Self {
+        self.leave_running = leave_running;
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    pub fn pre_dump(mut self, pre_dump: bool) -> Self {
+        self.pre_dump = pre_dump;
+        self
+    }
+
+    pub fn manage_cgroups_mode(mut self, mode: impl Into<String>) -> Self {
+        self.manage_cgroups_mode = Some(mode.into());
+        self
+    }
+
+    pub fn empty_namespace(mut self, ns: impl Into<String>) -> Self {
+        self.empty_namespaces.push(ns.into());
+        self
+    }
+}
+
+/// Container restore options
+///
+/// See <https://github.com/opencontainers/runc/blob/main/man/runc-restore.8.md>
+#[derive(Clone, Default)]
+pub struct RestoreOpts {
+    pub io: Option<Arc<dyn Io>>,
+    /// Path to criu image files to restore from.
+    pub image_path: Option<PathBuf>,
+    /// Path for saving work files and logs.
+    pub work_path: Option<PathBuf>,
+    /// Allow open tcp connections.
+    pub tcp_established: bool,
+    /// Allow external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Allow shell jobs.
+    pub shell_job: bool,
+    /// Handle file locks, for safety.
+    pub file_locks: bool,
+    /// Cgroups mode: "soft" (default), "full" and "strict".
+    pub manage_cgroups_mode: Option<String>,
+    /// Namespaces which should be created but not restored.
+    pub empty_namespaces: Vec<String>,
+    /// Path to where a pid file should be created.
+    pub pid_file: Option<PathBuf>,
+    /// Path to where a console socket should be created.
+    pub console_socket: Option<PathBuf>,
+    /// Detach from the container's process.
+    pub detach: bool,
+    /// Disable the use of the subreaper used to reap reparented processes.
+    pub no_subreaper: bool,
+    /// Don't use pivot_root to jail process inside rootfs.
+    pub no_pivot: bool,
+}
+
+impl Args for RestoreOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if let Some(image_path) = &self.image_path {
+            args.push(IMAGE_PATH.to_string());
+            args.push(utils::abs_string(image_path)?);
+        }
+        if let Some(work_path) = &self.work_path {
+            args.push(WORK_PATH.to_string());
+            args.push(utils::abs_string(work_path)?);
+        }
+        if self.tcp_established {
+            args.push(TCP_ESTABLISHED.to_string());
+        }
+        if self.ext_unix_sk {
+            args.push(EXT_UNIX_SK.to_string());
+        }
+        if self.shell_job {
+            args.push(SHELL_JOB.to_string());
+        }
+        if self.file_locks {
+            args.push(FILE_LOCKS.to_string());
+        }
+        if let Some(mode) = &self.manage_cgroups_mode {
+            args.push(MANAGE_CGROUPS_MODE.to_string());
+            args.push(mode.to_string());
+        }
+        for ns in &self.empty_namespaces {
+            args.push(EMPTY_NS.to_string());
+            args.push(ns.to_string());
+        }
+        if let Some(pid_file) = &self.pid_file {
+            args.push(PID_FILE.to_string());
+            args.push(utils::abs_string(pid_file)?);
+        }
+        if let Some(console_socket) = &self.console_socket {
+            args.push(CONSOLE_SOCKET.to_string());
+            args.push(utils::abs_string(console_socket)?);
+        }
+        if self.detach {
+            args.push(DETACH.to_string());
+        }
+        if self.no_subreaper {
+            args.push(NO_SUBREAPER.to_string());
+        }
+        if self.no_pivot {
+            args.push(NO_PIVOT.to_string());
+        }
+        Ok(args)
+    }
+}
+
+impl RestoreOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn io(mut self, io: Arc<dyn Io>) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    pub fn manage_cgroups_mode(mut self, mode: impl Into<String>) -> Self {
+        self.manage_cgroups_mode = Some(mode.into());
+        self
+    }
+
+    pub fn empty_namespace(mut self, ns: impl Into<String>) -> Self {
+        self.empty_namespaces.push(ns.into());
+        self
+    }
+
+    pub fn pid_file<P>(mut self, pid_file: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.pid_file = Some(pid_file.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn console_socket<P>(mut self, console_socket: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.console_socket = Some(console_socket.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    pub fn no_subreaper(mut self, no_subreaper: bool) -> Self {
+        self.no_subreaper = no_subreaper;
+        self
+    }
+
+    pub fn no_pivot(mut self, no_pivot: bool) -> Self {
+        self.no_pivot = no_pivot;
+        self
+    }
+}
+
+/// Spec generation options
+///
+/// See <https://github.com/opencontainers/runc/blob/main/man/runc-spec.8.md>
+#[derive(Debug, Clone, Default)]
+pub struct SpecOpts {
+    /// Path to the bundle directory where `config.json` is generated.
+    pub bundle: Option<PathBuf>,
+    /// Generate a configuration for a rootless container.
+    pub rootless: bool,
+}
+
+impl Args for SpecOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if let Some(bundle) = &self.bundle {
+            args.push(BUNDLE.to_string());
+            args.push(utils::abs_string(bundle)?);
+        }
+        if self.rootless {
+            args.push(ROOTLESS.to_string());
+        }
+        Ok(args)
+    }
+}
+
+impl SpecOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bundle<P>(mut self, bundle: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.bundle = Some(bundle.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn rootless(mut self, rootless: bool) -> Self {
+        self.rootless = rootless;
+        self
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -481,6 +1126,17 @@ mod tests {
 
     const ARGS_FAIL_MSG: &str = "Args.args() failed.";
 
+    #[test]
+    fn retry_policy_test() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), None);
+
+        let policy = RetryPolicy::new(3, Duration::from_millis(10));
+        assert_eq!(policy.delay(1), Some(Duration::from_millis(10)));
+        assert_eq!(policy.delay(2), Some(Duration::from_millis(20)));
+        assert_eq!(policy.delay(3), None);
+    }
+
     #[test]
     fn create_opts_test() {
         assert_eq!(
@@ -594,6 +1250,111 @@ mod tests {
         assert_eq!(KillOpts::new().all(true).args(), vec!["--all".to_string()],);
     }
 
+    #[test]
+    fn checkpoint_opts_test() {
+        assert_eq!(
+            CheckpointOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path(".")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .leave_running(true)
+                .tcp_established(true)
+                .file_locks(true)
+                .pre_dump(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--leave-running".to_string(),
+                "--tcp-established".to_string(),
+                "--file-locks".to_string(),
+                "--pre-dump".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_opts_test() {
+        assert_eq!(
+            SpecOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            SpecOpts::new()
+                .bundle(".")
+                .rootless(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--bundle".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap(),
+                "--rootless".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_opts_test() {
+        assert_eq!(
+            RestoreOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .work_path("..")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--work-path".to_string(),
+                env::current_dir()
+                    .unwrap()
+                    .parent()
+                    .unwrap()
+                    .to_string_lossy()
+                    .parse::<String>()
+                    .unwrap()
+            ]
+        );
+
+        assert_eq!(
+            RestoreOpts::new()
+                .shell_job(true)
+                .detach(true)
+                .no_subreaper(true)
+                .no_pivot(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--shell-job".to_string(),
+                "--detach".to_string(),
+                "--no-subreaper".to_string(),
+                "--no-pivot".to_string(),
+            ]
+        );
+    }
+
     #[cfg(target_os = "linux")]
     #[test]
     fn global_opts_test() {
@@ -628,5 +1389,89 @@ mod tests {
         assert!(args.contains(&"--rootless=true".to_string()));
         assert!(args.contains(&SYSTEMD_CGROUP.to_string()));
         assert_eq!(args.len(), 9);
+
+        let cfg = GlobalOpts::default().command("true").criu("/usr/sbin/criu");
+        let runc = cfg.build().unwrap();
+        let args = &runc.args;
+        assert!(args.contains(&CRIU.to_string()));
+        assert!(args.contains(&"/usr/sbin/criu".to_string()));
+        assert_eq!(args.len(), 4);
+
+        let runc = GlobalOpts::default()
+            .command("true")
+            .criu("criu")
+            .build()
+            .unwrap();
+        assert_eq!(runc.args[runc.args.len() - 1], "criu");
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .extra_args(&["--experimental".to_string()])
+            .env("RUNC_LOG", "debug")
+            .clear_env();
+        let runc = cfg.build().unwrap();
+        assert_eq!(runc.args.last(), Some(&"--experimental".to_string()));
+        assert_eq!(runc.args.len(), 3);
+        assert_eq!(
+            runc.env,
+            vec![("RUNC_LOG".to_string(), "debug".to_string())]
+        );
+        assert!(runc.clear_env);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn global_flags_override_test() {
+        let runc = GlobalOpts::default()
+            .command("true")
+            .root("/tmp")
+            .extra_args(&["--experimental".to_string()])
+            .build()
+            .unwrap();
+        let mut flags = runc.global_flags().clone();
+        assert_eq!(flags.root, Some(PathBuf::from("/tmp")));
+
+        flags.root = Some(PathBuf::from("/run/other"));
+        flags.debug = true;
+        let other = runc.with_global_flags(flags).unwrap();
+        assert!(other.args.contains(&"/run/other".to_string()));
+        assert!(other.args.contains(&DEBUG.to_string()));
+        assert_eq!(other.args.last(), Some(&"--experimental".to_string()));
+        // the original client is left untouched
+        assert!(runc.args.contains(&"/tmp".to_string()));
+        assert!(!runc.args.contains(&DEBUG.to_string()));
+    }
+
+    #[test]
+    fn security_labels_test() {
+        let process = Process::default();
+        assert!(ExecOpts::new().labelled(&process).is_none());
+        let labelled = ExecOpts::new()
+            .apparmor_profile("docker-default")
+            .selinux_label("system_u:system_r:container_t:s0")
+            .labelled(&process)
+            .unwrap();
+        assert_eq!(
+            labelled.apparmor_profile().as_deref(),
+            Some("docker-default")
+        );
+        assert_eq!(
+            labelled.selinux_label().as_deref(),
+            Some("system_u:system_r:container_t:s0")
+        );
+
+        let bundle = tempfile::tempdir().unwrap();
+        let mut spec = oci_spec::runtime::Spec::default();
+        spec.set_process(Some(labelled));
+        bundle::write_spec(bundle.path(), &spec).unwrap();
+        assert!(CreateOpts::new().check_labels(bundle.path()).is_ok());
+        assert!(CreateOpts::new()
+            .apparmor_profile("docker-default")
+            .check_labels(bundle.path())
+            .is_ok());
+        assert!(CreateOpts::new()
+            .selinux_label("system_u:system_r:spc_t:s0")
+            .check_labels(bundle.path())
+            .is_err());
     }
 }
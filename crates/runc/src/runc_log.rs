@@ -0,0 +1,92 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Parsing of the log file written by runc with `--log` and `--log-format json`.
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A single entry of the runc log file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(default)]
+    pub level: String,
+    #[serde(default)]
+    pub msg: String,
+    #[serde(default)]
+    pub time: String,
+}
+
+/// Return the current size of the log file, or 0 if it does not exist yet.
+pub fn size<P: AsRef<Path>>(path: P) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Return the message of the last error level entry written after `offset`.
+///
+/// Lines which are not valid JSON entries (e.g. text formatted logs) are ignored.
+pub fn last_error<P: AsRef<Path>>(path: P, offset: u64) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    BufReader::new(file)
+        .lines()
+        .filter_map(|line| line.ok())
+        .filter_map(|line| serde_json::from_str::<Entry>(&line).ok())
+        .filter(|entry| entry.level == "error")
+        .last()
+        .map(|entry| entry.msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_last_error() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"level":"error","msg":"old failure","time":"2022-01-01T00:00:00Z"}}"#
+        )
+        .unwrap();
+        let offset = size(file.path());
+        writeln!(
+            file,
+            r#"{{"level":"warning","msg":"some warning","time":"2022-01-01T00:00:01Z"}}"#
+        )
+        .unwrap();
+        writeln!(file, "not a json line").unwrap();
+        writeln!(
+            file,
+            r#"{{"level":"error","msg":"container not running","time":"2022-01-01T00:00:02Z"}}"#
+        )
+        .unwrap();
+
+        assert_eq!(
+            last_error(file.path(), offset).as_deref(),
+            Some("container not running")
+        );
+        assert_eq!(last_error(file.path(), size(file.path())), None);
+        assert_eq!(last_error("/non/existent/log.json", 0), None);
+    }
+}
@@ -0,0 +1,199 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! OCI runtime spec types used to build bundles and processes for runc.
+//!
+//! The complete model (mounts, namespaces, hooks, seccomp, capabilities...) is provided by
+//! the [oci-spec](https://docs.rs/oci-spec) crate, and re-exported here so that users of this
+//! crate always use the same version as the runc client.
+
+use std::path::Path;
+
+pub use oci_spec::runtime::*;
+
+use crate::{bundle, error::Error};
+
+/// Builder of the [`Process`] spec passed to `Runc::exec`.
+///
+/// It starts from the oci-spec defaults, or from the process of the container
+/// when created with [`ExecProcessBuilder::from_bundle`].
+#[derive(Debug, Clone, Default)]
+pub struct ExecProcessBuilder {
+    process: Process,
+}
+
+impl ExecProcessBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the process of the container's `config.json` as defaults, without its terminal.
+    pub fn from_bundle<P: AsRef<Path>>(bundle: P) -> Result<Self, Error> {
+        let spec = bundle::read_spec(bundle)?;
+        let mut process = spec.process().clone().unwrap_or_default();
+        process.set_terminal(Some(false));
+        Ok(Self { process })
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.process
+            .set_args(Some(args.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Set an environment variable, replacing any previous value of `key`.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        let prefix = format!("{}=", key);
+        let mut env = self.process.env().clone().unwrap_or_default();
+        env.retain(|e| !e.starts_with(&prefix));
+        env.push(format!("{}{}", prefix, value));
+        self.process.set_env(Some(env));
+        self
+    }
+
+    pub fn cwd<P: AsRef<Path>>(mut self, cwd: P) -> Self {
+        self.process.set_cwd(cwd.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn uid(mut self, uid: u32) -> Self {
+        let mut user = self.process.user().clone();
+        user.set_uid(uid);
+        self.process.set_user(user);
+        self
+    }
+
+    pub fn gid(mut self, gid: u32) -> Self {
+        let mut user = self.process.user().clone();
+        user.set_gid(gid);
+        self.process.set_user(user);
+        self
+    }
+
+    pub fn additional_gids(mut self, gids: Vec<u32>) -> Self {
+        let mut user = self.process.user().clone();
+        user.set_additional_gids(Some(gids));
+        self.process.set_user(user);
+        self
+    }
+
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.process.set_terminal(Some(terminal));
+        self
+    }
+
+    /// Set the bounding, effective, inheritable and permitted capabilities.
+    pub fn capabilities(mut self, caps: Capabilities) -> Self {
+        let mut linux_caps = self.process.capabilities().clone().unwrap_or_default();
+        linux_caps
+            .set_bounding(Some(caps.clone()))
+            .set_effective(Some(caps.clone()))
+            .set_inheritable(Some(caps.clone()))
+            .set_permitted(Some(caps));
+        self.process.set_capabilities(Some(linux_caps));
+        self
+    }
+
+    /// Validate and return the process spec.
+    pub fn build(self) -> Result<Process, Error> {
+        if self.process.args().as_ref().map_or(true, |a| a.is_empty()) {
+            return Err(Error::InvalidProcessSpec(
+                "args must not be empty".to_string(),
+            ));
+        }
+        if !self.process.cwd().is_absolute() {
+            return Err(Error::InvalidProcessSpec(format!(
+                "cwd {:?} must be an absolute path",
+                self.process.cwd()
+            )));
+        }
+        Ok(self.process)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_round_trip() {
+        let spec = Spec::default();
+        let data = serde_json::to_string(&spec).expect("failed to serialize spec");
+        let loaded: Spec = serde_json::from_str(&data).expect("failed to deserialize spec");
+        assert_eq!(
+            serde_json::to_value(&spec).unwrap(),
+            serde_json::to_value(&loaded).unwrap()
+        );
+
+        let linux = loaded.linux().as_ref().expect("no linux section");
+        assert!(linux.namespaces().is_some());
+        assert!(loaded.mounts().is_some());
+        let process = loaded.process().as_ref().expect("no process section");
+        assert!(process.capabilities().is_some());
+    }
+
+    #[test]
+    fn test_exec_process_builder() {
+        let process = ExecProcessBuilder::new()
+            .args(["ls", "-l"])
+            .env("FOO", "bar")
+            .env("FOO", "baz")
+            .cwd("/tmp")
+            .uid(1000)
+            .gid(1000)
+            .additional_gids(vec![10])
+            .terminal(true)
+            .build()
+            .expect("failed to build process");
+        assert_eq!(
+            process.args().as_deref(),
+            Some(&["ls".to_string(), "-l".to_string()][..])
+        );
+        let env = process.env().clone().unwrap_or_default();
+        assert_eq!(env.iter().filter(|e| e.starts_with("FOO=")).count(), 1);
+        assert!(env.contains(&"FOO=baz".to_string()));
+        assert_eq!(process.user().uid(), &1000);
+        assert_eq!(process.terminal(), &Some(true));
+
+        assert!(ExecProcessBuilder::new()
+            .args(Vec::<String>::new())
+            .build()
+            .is_err());
+        assert!(ExecProcessBuilder::new().cwd("relative").build().is_err());
+    }
+
+    #[test]
+    fn test_exec_process_builder_from_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spec = Spec::default();
+        let mut process = Process::default();
+        process.set_cwd("/work".into()).set_terminal(Some(true));
+        spec.set_process(Some(process));
+        bundle::write_spec(dir.path(), &spec).unwrap();
+
+        let process = ExecProcessBuilder::from_bundle(dir.path())
+            .expect("failed to load bundle")
+            .args(["true"])
+            .build()
+            .expect("failed to build process");
+        assert_eq!(process.cwd(), &std::path::PathBuf::from("/work"));
+        assert_eq!(process.terminal(), &Some(false));
+    }
+}
@@ -0,0 +1,130 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Streaming of the outputs of a runc command line by line, as they are printed,
+//! for commands which run for long such as `runc events` or `runc run` in the foreground.
+
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    process::ExitStatus,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Child,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use crate::{error::Error, Result};
+
+/// Lines buffered before the readers wait for the stream to be polled.
+const LINE_BUFFER: usize = 128;
+
+/// A line printed by a runc command, without its newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Stream of the stdout and stderr lines of a runc command, in the order they are read.
+///
+/// The stream ends when runc closes both outputs. The runc process is killed
+/// when the stream is dropped before it exits.
+#[derive(Debug)]
+pub struct OutputStream {
+    child: Child,
+    lines: Receiver<Result<OutputLine>>,
+    /// lines drained while waiting for runc, returned before the buffered ones
+    drained: VecDeque<Result<OutputLine>>,
+}
+
+impl OutputStream {
+    pub(crate) fn new(mut child: Child) -> Result<Self> {
+        let not_piped = |name: &str| {
+            Error::UnavailableIO(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} of runc is not piped", name),
+            ))
+        };
+        let stdout = child.stdout.take().ok_or_else(|| not_piped("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| not_piped("stderr"))?;
+        let (tx, rx) = channel(LINE_BUFFER);
+        tokio::spawn(forward_lines(stdout, OutputLine::Stdout, tx.clone()));
+        tokio::spawn(forward_lines(stderr, OutputLine::Stderr, tx));
+        Ok(Self {
+            child,
+            lines: rx,
+            drained: VecDeque::new(),
+        })
+    }
+
+    /// Return the pid of the underlying runc process, if it is still running
+    pub fn pid(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Wait for runc to exit, the lines not read yet are still available afterwards.
+    ///
+    /// The lines printed meanwhile are drained into an unbounded buffer, as runc
+    /// would otherwise block on its outputs once the line buffer is full.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        let mut open = true;
+        loop {
+            tokio::select! {
+                res = self.child.wait() => return res.map_err(Error::InvalidCommand),
+                line = self.lines.recv(), if open => match line {
+                    Some(line) => self.drained.push_back(line),
+                    None => open = false,
+                },
+            }
+        }
+    }
+}
+
+impl Stream for OutputStream {
+    type Item = Result<OutputLine>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(line) = self.drained.pop_front() {
+            return Poll::Ready(Some(line));
+        }
+        self.lines.poll_recv(cx)
+    }
+}
+
+async fn forward_lines<R>(output: R, line: fn(String) -> OutputLine, tx: Sender<Result<OutputLine>>)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(output).lines();
+    loop {
+        let item = match lines.next_line().await {
+            Ok(Some(l)) => Ok(line(l)),
+            Ok(None) => return,
+            Err(e) => Err(Error::InvalidCommand(e)),
+        };
+        let failed = item.is_err();
+        // the stream was dropped, nobody reads the lines anymore
+        if tx.send(item).await.is_err() || failed {
+            return;
+        }
+    }
+}
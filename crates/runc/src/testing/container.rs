@@ -0,0 +1,254 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Disposable bundles and containers for end-to-end tests against a real runc, run as root.
+//!
+//! The rootfs of the bundles is taken from `RUNC_TEST_ROOTFS`, either a directory or a
+//! tarball, and otherwise is made of the host's busybox, which must then be statically linked.
+
+use std::{
+    env, fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use oci_spec::runtime::Spec;
+use tempfile::TempDir;
+
+use crate::{
+    bundle, error::Error, options::DEFAULT_COMMAND, specs::ExecProcessBuilder, utils, Runc,
+};
+
+/// Directory or tarball of the rootfs used by the test bundles.
+pub const ROOTFS_ENV: &str = "RUNC_TEST_ROOTFS";
+
+/// Applets linked to busybox in a rootfs made of the host's busybox.
+const BUSYBOX_APPLETS: &[&str] = &["sh", "true", "false", "sleep", "echo", "cat", "ls", "ps"];
+
+/// Return whether end-to-end tests can run here: as root with a runc binary in `PATH`.
+pub fn can_run_containers() -> bool {
+    nix::unistd::geteuid().is_root() && utils::binary_path(DEFAULT_COMMAND).is_some()
+}
+
+fn command_failed(what: &str, status: std::process::ExitStatus) -> Error {
+    Error::FileSystemError(io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} failed: {}", what, status),
+    ))
+}
+
+/// Fill `rootfs` from `RUNC_TEST_ROOTFS`, or from the host's busybox.
+fn populate_rootfs(rootfs: &Path) -> Result<(), Error> {
+    if let Some(source) = env::var_os(ROOTFS_ENV).map(PathBuf::from) {
+        let mut cmd = if source.is_dir() {
+            let mut cmd = Command::new("cp");
+            cmd.arg("-a").arg(source.join(".")).arg(rootfs);
+            cmd
+        } else {
+            let mut cmd = Command::new("tar");
+            cmd.arg("-xf").arg(&source).arg("-C").arg(rootfs);
+            cmd
+        };
+        let status = cmd.status().map_err(Error::FileSystemError)?;
+        if !status.success() {
+            return Err(command_failed(
+                &format!("copy of rootfs {}", source.display()),
+                status,
+            ));
+        }
+        return Ok(());
+    }
+
+    let busybox = utils::binary_path("busybox").ok_or_else(|| {
+        Error::FileSystemError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no rootfs for tests: set {} or install busybox", ROOTFS_ENV),
+        ))
+    })?;
+    let bin = rootfs.join("bin");
+    fs::create_dir_all(&bin).map_err(Error::FileSystemError)?;
+    fs::copy(&busybox, bin.join("busybox")).map_err(Error::FileSystemError)?;
+    for applet in BUSYBOX_APPLETS {
+        symlink("busybox", bin.join(applet)).map_err(Error::FileSystemError)?;
+    }
+    // mount points of the default spec
+    for dir in ["proc", "dev", "sys", "tmp"].iter() {
+        fs::create_dir_all(rootfs.join(dir)).map_err(Error::FileSystemError)?;
+    }
+    Ok(())
+}
+
+/// A bundle in a temporary directory, removed when dropped.
+#[derive(Debug)]
+pub struct TestBundle {
+    dir: TempDir,
+    rootfs: PathBuf,
+}
+
+impl TestBundle {
+    /// Create a bundle running `args` with the oci-spec defaults otherwise.
+    pub fn new<I, S>(args: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ExecProcessBuilder::new()
+                .args(args)
+                .terminal(false)
+                .build()?,
+        ));
+        Self::with_spec(&spec)
+    }
+
+    /// Create a bundle with `spec` as its `config.json`.
+    pub fn with_spec(spec: &Spec) -> Result<Self, Error> {
+        let dir = tempfile::Builder::new()
+            .prefix("runc-test-bundle")
+            .tempdir()
+            .map_err(Error::FileSystemError)?;
+        let rootfs = bundle::create_bundle(dir.path(), spec)?;
+        populate_rootfs(&rootfs)?;
+        Ok(Self { dir, rootfs })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn rootfs(&self) -> &Path {
+        &self.rootfs
+    }
+}
+
+/// A container id and its bundle, the container is deleted with `--force`
+/// and its rootfs unmounted when the guard is dropped.
+///
+/// The guard does not create the container, the test does with [`TestContainer::runc`].
+#[derive(Debug)]
+pub struct TestContainer {
+    id: String,
+    runc: Runc,
+    bundle: TestBundle,
+}
+
+impl TestContainer {
+    /// Create a bundle running `args`, for a container with a random id.
+    pub fn new<I, S>(runc: &Runc, args: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Ok(Self::with_bundle(runc, TestBundle::new(args)?))
+    }
+
+    pub fn with_bundle(runc: &Runc, bundle: TestBundle) -> Self {
+        Self {
+            id: format!("runc-test-{}", uuid::Uuid::new_v4()),
+            runc: runc.clone(),
+            bundle,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn runc(&self) -> &Runc {
+        &self.runc
+    }
+
+    pub fn bundle(&self) -> &Path {
+        self.bundle.path()
+    }
+}
+
+impl Drop for TestContainer {
+    fn drop(&mut self) {
+        // run runc directly, as drop can not wait for the async client
+        let res = Command::new(&self.runc.command)
+            .args(&self.runc.args)
+            .args(&["delete", "--force", self.id.as_str()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Err(e) = res {
+            log::warn!("failed to delete test container {}: {}", self.id, e);
+        }
+        if let Err(e) = self.runc.cleanup(&self.id, Some(self.bundle.rootfs())) {
+            log::warn!("failed to clean up test container {}: {}", self.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unwrap the result, or return `None` when there is neither RUNC_TEST_ROOTFS nor busybox
+    /// here to populate the rootfs from.
+    fn skip_without_rootfs<T>(res: Result<T, Error>) -> Option<T> {
+        match res {
+            Ok(t) => Some(t),
+            Err(Error::FileSystemError(e))
+                if e.kind() == io::ErrorKind::NotFound && e.to_string().contains(ROOTFS_ENV) =>
+            {
+                None
+            }
+            Err(e) => panic!("failed to create test bundle: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_bundle() {
+        let bundle = match skip_without_rootfs(TestBundle::new(["sh", "-c", "true"])) {
+            Some(bundle) => bundle,
+            None => return,
+        };
+        let spec = bundle::read_spec(bundle.path()).unwrap();
+        let args = spec.process().as_ref().unwrap().args().clone().unwrap();
+        assert_eq!(args, vec!["sh", "-c", "true"]);
+        assert!(bundle.rootfs().join("bin/sh").exists());
+
+        let path = bundle.path().to_path_buf();
+        drop(bundle);
+        assert!(!path.exists());
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_run_container() {
+        if !can_run_containers() {
+            return;
+        }
+        let runc = crate::GlobalOpts::new().build().unwrap();
+        let container = match skip_without_rootfs(TestContainer::new(&runc, ["sh", "-c", "exit 0"]))
+        {
+            Some(container) => container,
+            None => return,
+        };
+        container
+            .runc()
+            .run(container.id(), container.bundle(), None)
+            .expect("run failed");
+        let bundle = container.bundle().to_path_buf();
+        drop(container);
+        assert!(!bundle.exists());
+    }
+}
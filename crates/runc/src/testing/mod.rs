@@ -0,0 +1,274 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Mock runc backend to test code using [`Runc`] without root or a runc binary.
+//!
+//! [`MockRunc`] hands out a [`Runc`] client whose commands are answered by scripted
+//! responses instead of being executed, and records every call made.
+//!
+//! The [`container`] module provides disposable bundles and containers for
+//! end-to-end tests against a real runc.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+use crate::{
+    options::{CRIU, LOG, LOG_FORMAT, ROOT},
+    Command, Result, Runc, Spawner,
+};
+
+pub mod container;
+
+/// Canned output of a mocked runc command.
+#[derive(Debug, Clone, Default)]
+pub struct MockResponse {
+    pub pid: u32,
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl MockResponse {
+    /// A successful command printing `stdout`.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A successful `state` of the container `fake-id` with pid 42 in `status`.
+    pub fn state(status: &str) -> Self {
+        Self::success(format!(
+            r#"{{"id":"fake-id","pid":42,"status":"{}","bundle":"/bundle","rootfs":"/bundle/rootfs","created":1431684000,"annotations":{{}}}}"#,
+            status
+        ))
+    }
+
+    /// A failed command exiting with `exit_code` and printing `stderr`.
+    pub fn failure(exit_code: i32, stderr: impl Into<String>) -> Self {
+        Self {
+            exit_code,
+            stderr: stderr.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Global flags of runc taking their value as the next argument.
+const GLOBAL_VALUE_FLAGS: &[&str] = &[ROOT, LOG, LOG_FORMAT, CRIU];
+
+/// Global flags which run on their own, scripted as if they were subcommands.
+const STANDALONE_FLAGS: &[&str] = &["--version"];
+
+/// Find the runc subcommand in the arguments of a command, after the global flags.
+fn subcommand(args: &[String]) -> Option<&String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if GLOBAL_VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') || STANDALONE_FLAGS.contains(&arg.as_str()) {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+/// Spawner answering commands with the responses scripted on [`MockRunc`].
+#[derive(Debug, Default)]
+pub struct MockSpawner {
+    responses: Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl MockSpawner {
+    fn respond(&self, cmd: &std::process::Command) -> (ExitStatus, u32, String, String) {
+        let args = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let response = subcommand(&args)
+            .and_then(|subcommand| {
+                self.responses
+                    .lock()
+                    .unwrap()
+                    .get_mut(subcommand)
+                    .and_then(|queue| queue.pop_front())
+            })
+            .unwrap_or_default();
+        self.calls.lock().unwrap().push(args);
+        (
+            ExitStatus::from_raw(response.exit_code << 8),
+            response.pid,
+            response.stdout,
+            response.stderr,
+        )
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Spawner for MockSpawner {
+    fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        Ok(self.respond(&cmd))
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl Spawner for MockSpawner {
+    async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        Ok(self.respond(cmd.as_std()))
+    }
+}
+
+/// Scriptable runc backend.
+///
+/// Responses are queued per runc subcommand (e.g. `"state"`) and consumed in order,
+/// commands without a queued response succeed with an empty output.
+#[derive(Debug, Default)]
+pub struct MockRunc {
+    spawner: Arc<MockSpawner>,
+}
+
+impl MockRunc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `response` for the next call of `subcommand`.
+    pub fn on(&self, subcommand: &str, response: MockResponse) -> &Self {
+        self.spawner
+            .responses
+            .lock()
+            .unwrap()
+            .entry(subcommand.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Return a runc client backed by this mock.
+    pub fn client(&self) -> Runc {
+        Runc {
+            command: PathBuf::from(crate::options::DEFAULT_COMMAND),
+            args: vec![],
+            flags: Default::default(),
+            extra_args: vec![],
+            spawner: self.spawner.clone(),
+            timeout: Default::default(),
+            set_pgid: false,
+            env: vec![],
+            clear_env: false,
+            temp_dir: None,
+            spec_stdin: false,
+            retry: Default::default(),
+            version: None,
+        }
+    }
+
+    /// Return the arguments of all the commands run so far.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.spawner.calls.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_subcommand() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            subcommand(&args(&["state", "fake-id"])).map(String::as_str),
+            Some("state")
+        );
+        assert_eq!(
+            subcommand(&args(&[
+                "--root",
+                "/run/runc",
+                "--log-format",
+                "text",
+                "--debug",
+                "--rootless=true",
+                "kill",
+                "fake-id",
+                "9"
+            ]))
+            .map(String::as_str),
+            Some("kill")
+        );
+        assert_eq!(subcommand(&args(&["--log", "/log.json"])), None);
+        assert_eq!(
+            subcommand(&args(&["--root", "/run/runc", "--version"])).map(String::as_str),
+            Some("--version")
+        );
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_mock_runc() {
+        let mock = MockRunc::new();
+        mock.on("state", MockResponse::state("running"))
+            .on("kill", MockResponse::failure(1, "container not running"));
+        let runc = mock.client();
+
+        let state = runc.state("fake-id").expect("failed to get state");
+        assert_eq!(state.pid, 42);
+        match runc.kill("fake-id", 9, None) {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr, "container not running"),
+            other => panic!("unexpected kill result: {:?}", other),
+        }
+        runc.start("fake-id").expect("failed to start");
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], vec!["state".to_string(), "fake-id".to_string()]);
+        assert_eq!(calls[2], vec!["start".to_string(), "fake-id".to_string()]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_mock_runc() {
+        let mock = MockRunc::new();
+        mock.on("state", MockResponse::state("running"))
+            .on("kill", MockResponse::failure(1, "container not running"));
+        let runc = mock.client();
+
+        let state = runc.state("fake-id").await.expect("failed to get state");
+        assert_eq!(state.pid, 42);
+        assert_eq!(state.created.unix_timestamp(), 1431684000);
+        match runc.kill("fake-id", 9, None).await {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr, "container not running"),
+            other => panic!("unexpected kill result: {:?}", other),
+        }
+        runc.start("fake-id").await.expect("failed to start");
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], vec!["state".to_string(), "fake-id".to_string()]);
+        assert_eq!(calls[2], vec!["start".to_string(), "fake-id".to_string()]);
+    }
+}
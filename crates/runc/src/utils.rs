@@ -14,13 +14,15 @@
    limitations under the License.
 */
 
-#[cfg(not(feature = "async"))]
-use std::io::Write;
 use std::{
     env,
+    io::Write,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
+use log::debug;
+use os_pipe::PipeReader;
 use path_absolutize::*;
 use serde::Serialize;
 #[cfg(not(feature = "async"))]
@@ -29,7 +31,7 @@ use tempfile::{Builder, NamedTempFile};
 use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
-use crate::error::Error;
+use crate::{error::Error, Version};
 
 // helper to resolve path (such as path for runc binary, pid files, etc. )
 pub fn abs_path_buf<P>(path: P) -> Result<PathBuf, Error>
@@ -70,10 +72,21 @@ fn xdg_runtime_dir() -> String {
         .unwrap_or_else(|_| abs_string(env::temp_dir()).unwrap_or_else(|_| ".".to_string()))
 }
 
-/// Write the serialized 'value' to a temp file
+fn temp_file_name(dir: Option<&Path>) -> Result<String, Error> {
+    let name = format!("runc-process-{}", Uuid::new_v4());
+    match dir {
+        Some(dir) => path_to_string(dir.join(name)),
+        None => Ok(format!("{}/{}", xdg_runtime_dir(), name)),
+    }
+}
+
+/// Write the serialized 'value' to a temp file in `dir`, or in [xdg_runtime_dir] if [`None`]
 #[cfg(not(feature = "async"))]
-pub fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<(NamedTempFile, String), Error> {
-    let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
+pub fn write_value_to_temp_file<T: Serialize>(
+    value: &T,
+    dir: Option<&Path>,
+) -> Result<(NamedTempFile, String), Error> {
+    let filename = temp_file_name(dir)?;
     let mut temp_file = Builder::new()
         .prefix(&filename)
         .rand_bytes(0)
@@ -87,15 +100,18 @@ pub fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<(NamedTempFil
     Ok((temp_file, filename))
 }
 
-/// Write the serialized 'value' to a temp file
+/// Write the serialized 'value' to a temp file in `dir`, or in [xdg_runtime_dir] if [`None`]
 /// Unlike the same function in non-async feature,
 /// it returns the filename, without the NamedTempFile object,
 /// which implements Drop trait to remove the file if it goes out of scope.
 /// the async Drop is still not supported in rust,
 /// in async context, the created file should be removed by the caller
 #[cfg(feature = "async")]
-pub async fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<String, Error> {
-    let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
+pub async fn write_value_to_temp_file<T: Serialize>(
+    value: &T,
+    dir: Option<&Path>,
+) -> Result<String, Error> {
+    let filename = temp_file_name(dir)?;
     let mut f = tokio::fs::OpenOptions::new()
         .create(true)
         .write(true)
@@ -110,6 +126,21 @@ pub async fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<String,
     Ok(filename)
 }
 
+/// Returns the read side of a pipe carrying the serialized 'value', to be used as the
+/// stdin of runc in place of a temp file.
+///
+/// The value is written from a thread, so that it may be larger than the pipe buffer.
+pub fn write_value_to_pipe<T: Serialize>(value: &T) -> Result<PipeReader, Error> {
+    let json = serde_json::to_vec(value).map_err(Error::JsonDeserializationFailed)?;
+    let (rd, mut wr) = os_pipe::pipe().map_err(Error::SpecFileCreationFailed)?;
+    std::thread::spawn(move || {
+        if let Err(e) = wr.write_all(&json) {
+            debug!("failed to write value to pipe: {}", e);
+        }
+    });
+    Ok(rd)
+}
+
 /// Resolve a binary path according to the `PATH` environment variable.
 ///
 /// Note, the case that `path` is already an absolute path is implicitly handled by
@@ -130,3 +161,177 @@ where
         })
     })
 }
+
+/// Name of the architecture of the host in the runc release binaries, e.g. `runc.amd64`.
+fn release_arch() -> Option<&'static str> {
+    match env::consts::ARCH {
+        "x86_64" => Some("amd64"),
+        "x86" => Some("386"),
+        "aarch64" => Some("arm64"),
+        "arm" => Some("armhf"),
+        "powerpc64" => Some("ppc64le"),
+        "riscv64" => Some("riscv64"),
+        "s390x" => Some("s390x"),
+        _ => None,
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// A runc binary found in `PATH` and checked to be executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuncBinary {
+    path: PathBuf,
+}
+
+impl RuncBinary {
+    /// Resolve `name` in `PATH`, falling back to the release binary of the host
+    /// architecture, e.g. `runc.arm64`, when `name` itself is not found.
+    pub fn resolve<P: AsRef<Path>>(name: P) -> Result<Self, Error> {
+        let name = name.as_ref();
+        let find = |name: &Path| {
+            let paths = env::var_os("PATH").unwrap_or_default();
+            env::split_paths(&paths)
+                .map(|dir| dir.join(name))
+                .find(|path| is_executable(path))
+        };
+        let path = find(name)
+            .or_else(|| {
+                let mut arch_name = name.as_os_str().to_os_string();
+                arch_name.push(format!(".{}", release_arch()?));
+                find(Path::new(&arch_name))
+            })
+            .ok_or(Error::NotFound)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run `runc --version` and parse its output.
+    pub fn version(&self) -> Result<Version, Error> {
+        let output = std::process::Command::new(&self.path)
+            .arg("--version")
+            .output()
+            .map_err(Error::ProcessSpawnFailed)?;
+        Version::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Return the version of the binary if it is at least `min`, e.g. `1.1.0`.
+    pub fn check_version(&self, min: &str) -> Result<Version, Error> {
+        let version = self.version()?;
+        let found = version.runc_version.clone().unwrap_or_default();
+        match (version.numbers(), parse_version_numbers(min)) {
+            (Some(v), Some(m)) if v >= m => Ok(version),
+            (_, None) => Err(Error::InvalidVersion),
+            _ => Err(Error::IncompatibleVersion {
+                found,
+                min: min.to_string(),
+            }),
+        }
+    }
+}
+
+/// Parse the leading `major.minor.patch` of a version, e.g. `(1, 1, 0)` for `1.1.0-rc.1`,
+/// the missing components count as 0.
+pub(crate) fn parse_version_numbers(version: &str) -> Option<(u64, u64, u64)> {
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let mut parts = version[..end].split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+const CAP_SYS_ADMIN: u32 = 21;
+
+/// Detect whether runc would run in rootless mode with `--rootless=auto`,
+/// that is the effective user is not root and does not hold `CAP_SYS_ADMIN`.
+pub fn detect_rootless() -> bool {
+    if nix::unistd::geteuid().is_root() {
+        return false;
+    }
+    let status = match std::fs::read_to_string("/proc/self/status") {
+        Ok(s) => s,
+        Err(_) => return true,
+    };
+    let cap_eff = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .unwrap_or(0);
+    cap_eff & (1 << CAP_SYS_ADMIN) == 0
+}
+
+/// Unmount all the mounts stacked on `target`, returning how many were removed.
+#[cfg(target_os = "linux")]
+pub fn unmount_all<P: AsRef<Path>>(target: P) -> Result<usize, Error> {
+    use nix::{
+        errno::Errno,
+        mount::{umount2, MntFlags},
+    };
+
+    let mut unmounted = 0;
+    loop {
+        match umount2(target.as_ref(), MntFlags::MNT_DETACH) {
+            Ok(_) => unmounted += 1,
+            Err(Errno::EINVAL) | Err(Errno::ENOENT) => return Ok(unmounted),
+            Err(e) => return Err(Error::FileSystemError(e.into())),
+        }
+    }
+}
+
+/// Unmount all the mounts stacked on `target`, returning how many were removed.
+#[cfg(not(target_os = "linux"))]
+pub fn unmount_all<P: AsRef<Path>>(_target: P) -> Result<usize, Error> {
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_version_numbers() {
+        assert_eq!(parse_version_numbers("1.1.4"), Some((1, 1, 4)));
+        assert_eq!(parse_version_numbers("1.2.0-rc.1"), Some((1, 2, 0)));
+        assert_eq!(parse_version_numbers("1.0"), Some((1, 0, 0)));
+        assert_eq!(parse_version_numbers("v1.1"), None);
+    }
+
+    #[test]
+    fn test_runc_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = dir.path().join("runc");
+        fs::write(
+            &runc,
+            "#!/bin/sh\necho 'runc version 1.1.4'\necho 'spec: 1.0.2-dev'\n",
+        )
+        .unwrap();
+
+        // not executable yet
+        assert!(matches!(RuncBinary::resolve(&runc), Err(Error::NotFound)));
+        fs::set_permissions(&runc, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let binary = RuncBinary::resolve(&runc).expect("runc not found");
+        assert_eq!(binary.path(), runc.as_path());
+        let version = binary.check_version("1.1.0").expect("version check failed");
+        assert_eq!(version.spec_version.as_deref(), Some("1.0.2-dev"));
+        match binary.check_version("1.2") {
+            Err(Error::IncompatibleVersion { found, min }) => {
+                assert_eq!(found, "1.1.4");
+                assert_eq!(min, "1.2");
+            }
+            other => panic!("unexpected version check result: {:?}", other),
+        }
+    }
+}
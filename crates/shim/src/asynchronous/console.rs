@@ -14,14 +14,17 @@
    limitations under the License.
 */
 
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use log::warn;
 use tokio::net::{UnixListener, UnixStream};
+#[cfg(target_os = "linux")]
 use uuid::Uuid;
 
+#[cfg(target_os = "linux")]
+use crate::util::bind_abstract;
 use crate::{
-    util::{mkdir, xdg_runtime_dir},
+    util::{console_socket_path, mkdir},
     Error, Result,
 };
 
@@ -33,9 +36,10 @@ pub struct ConsoleSocket {
 
 impl ConsoleSocket {
     pub async fn new() -> Result<ConsoleSocket> {
-        let dir = format!("{}/pty{}", xdg_runtime_dir(), Uuid::new_v4());
-        mkdir(&dir, 0o711).await?;
-        let file_name = Path::new(&dir).join("pty.sock");
+        let file_name = console_socket_path()?;
+        if let Some(dir) = file_name.parent() {
+            mkdir(dir, 0o711).await?;
+        }
         let listener = UnixListener::bind(&file_name).map_err(io_error!(
             e,
             "bind socket {}",
@@ -48,6 +52,24 @@ impl ConsoleSocket {
         })
     }
 
+    /// Listen on a socket in the Linux abstract namespace instead of the filesystem,
+    /// its path is the `@` prefixed name that runc understands.
+    #[cfg(target_os = "linux")]
+    pub async fn new_abstract() -> Result<ConsoleSocket> {
+        let name = format!("pty{}", Uuid::new_v4());
+        let listener = bind_abstract(&name)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(io_error!(e, "set console socket nonblocking"))?;
+        let listener =
+            UnixListener::from_std(listener).map_err(io_error!(e, "register console socket"))?;
+        Ok(ConsoleSocket {
+            listener,
+            path: PathBuf::from(format!("@{}", name)),
+            rmdir: false,
+        })
+    }
+
     pub async fn accept(&self) -> Result<UnixStream> {
         let (stream, _addr) = self
             .listener
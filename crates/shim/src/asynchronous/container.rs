@@ -14,19 +14,39 @@
    limitations under the License.
 */
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
 use containerd_shim_protos::{
     api::{CreateTaskRequest, ExecProcessRequest, ProcessInfo, StateResponse},
     cgroups::metrics::Metrics,
+    shim::oci::{CheckpointOptions, ProcessDetails},
 };
 use log::debug;
-use oci_spec::runtime::LinuxResources;
+use oci_spec::runtime::{LinuxResources, Spec};
 use time::OffsetDateTime;
 use tokio::sync::oneshot::Receiver;
 
-use crate::{asynchronous::processes::Process, error::Result, Error};
+use crate::{
+    asynchronous::{
+        processes::{Process, StdioStreams},
+        util::{read_file_to_str, write_str_to_file},
+    },
+    error::Result,
+    util::{apply_spec_changes, convert_to_any, CONFIG_FILE_NAME},
+    Error,
+};
+
+/// Metadata of a container, kept from its creation for grouping and filtering.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub bundle: String,
+    /// Annotations of the spec of the bundle
+    pub annotations: HashMap<String, String>,
+    /// Group the container is created in, e.g. the sandbox of a pod
+    pub group: Option<String>,
+}
 
 #[async_trait]
 pub trait Container {
@@ -38,6 +58,9 @@ pub trait Container {
         &self,
         exec_id: Option<&str>,
     ) -> Result<(i32, i32, Option<OffsetDateTime>)>;
+    async fn try_wait(&self, _exec_id: Option<&str>) -> Result<Option<(i32, OffsetDateTime)>> {
+        Err(Error::Unimplemented("try_wait".to_string()))
+    }
     async fn delete(
         &mut self,
         exec_id_opt: Option<&str>,
@@ -46,9 +69,37 @@ pub trait Container {
     async fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()>;
     async fn pid(&self) -> i32;
     async fn id(&self) -> String;
+    /// Metadata of the container, only its id unless implemented.
+    async fn info(&self) -> ContainerInfo {
+        ContainerInfo {
+            id: self.id().await,
+            ..Default::default()
+        }
+    }
     async fn update(&mut self, resources: &LinuxResources) -> Result<()>;
     async fn stats(&self) -> Result<Metrics>;
     async fn all_processes(&self) -> Result<Vec<ProcessInfo>>;
+    async fn pause(&mut self) -> Result<()> {
+        Err(Error::Unimplemented("pause".to_string()))
+    }
+    async fn resume(&mut self) -> Result<()> {
+        Err(Error::Unimplemented("resume".to_string()))
+    }
+    async fn checkpoint(&mut self, _path: &str, _opts: &CheckpointOptions) -> Result<()> {
+        Err(Error::Unimplemented("checkpoint".to_string()))
+    }
+    async fn close_io(&mut self, _exec_id: Option<&str>) -> Result<()> {
+        Err(Error::Unimplemented("close_io".to_string()))
+    }
+    /// Streaming handles to the stdio of the init process or of an exec process,
+    /// for embedders to implement attach or logs.
+    async fn stdio_streams(&self, _exec_id: Option<&str>) -> Result<StdioStreams> {
+        Err(Error::Unimplemented("stdio_streams".to_string()))
+    }
+    /// Reopen the stdin fifo of the init process or of an exec process.
+    async fn reopen_stdin(&mut self, _exec_id: Option<&str>) -> Result<()> {
+        Err(Error::Unimplemented("reopen_stdin".to_string()))
+    }
 }
 
 #[async_trait]
@@ -57,11 +108,82 @@ pub trait ContainerFactory<C> {
     async fn cleanup(&self, ns: &str, c: &C) -> Result<()>;
 }
 
+/// Hook run in process with the spec of the bundle before a container is created,
+/// it can deny the creation by returning an error, e.g. to refuse privileged containers,
+/// or change the spec, which is then written back to the bundle.
+#[async_trait]
+pub trait CreateHook: Send + Sync {
+    async fn pre_create(&self, req: &CreateTaskRequest, spec: &mut Spec) -> Result<()>;
+}
+
+/// A [ContainerFactory] running the registered [CreateHook]s before the creation of a container
+/// by the factory it wraps.
+#[derive(Default)]
+pub struct HookedFactory<F> {
+    pub factory: F,
+    hooks: Vec<Arc<dyn CreateHook>>,
+}
+
+impl<F> HookedFactory<F> {
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            hooks: vec![],
+        }
+    }
+
+    /// Run `hook` before the creation of the containers, after the hooks registered before.
+    pub fn register_create_hook(&mut self, hook: Arc<dyn CreateHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Run the hooks on the spec of the bundle, writing it back if they changed it.
+    async fn run_create_hooks(&self, req: &CreateTaskRequest) -> Result<()> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let path = std::path::Path::new(&req.bundle).join(CONFIG_FILE_NAME);
+        let mut raw: serde_json::Value = serde_json::from_str(&read_file_to_str(&path).await?)?;
+        let spec: Spec =
+            serde_json::from_value(raw.clone()).map_err(other_error!(e, "read spec"))?;
+        let mut hooked = spec.clone();
+        for hook in self.hooks.iter() {
+            hook.pre_create(req, &mut hooked).await?;
+        }
+        if hooked != spec {
+            debug!("create hooks changed the spec of {}", req.id);
+            // only the changes are applied, config.json can hold fields unknown to oci-spec
+            apply_spec_changes(&mut raw, &spec, &hooked)?;
+            write_str_to_file(&path, serde_json::to_string(&raw)?).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F, C> ContainerFactory<C> for HookedFactory<F>
+where
+    F: ContainerFactory<C> + Send + Sync,
+    C: Send + Sync,
+{
+    async fn create(&self, ns: &str, req: &CreateTaskRequest) -> Result<C> {
+        self.run_create_hooks(req).await?;
+        self.factory.create(ns, req).await
+    }
+
+    async fn cleanup(&self, ns: &str, c: &C) -> Result<()> {
+        self.factory.cleanup(ns, c).await
+    }
+}
+
 #[async_trait]
 pub trait ProcessFactory<E> {
     async fn create(&self, req: &ExecProcessRequest) -> Result<E>;
 }
 
+/// How many exits of deleted exec processes a container keeps.
+const MAX_DELETED_PROCESSES: usize = 32;
+
 /// ContainerTemplate is a template struct to implement Container,
 /// most of the methods can be delegated to either init process or exec process.
 /// that's why we provides a ContainerTemplate struct,
@@ -77,6 +199,13 @@ pub struct ContainerTemplate<T, E, P> {
     pub process_factory: P,
     /// exec processes of this container
     pub processes: HashMap<String, E>,
+    /// pid, exit code and exit time of the last deleted exec processes,
+    /// so that a repeated delete of an exec still reports how it exited
+    pub deleted_processes: HashMap<String, (i32, i32, Option<OffsetDateTime>)>,
+    /// annotations of the spec of the bundle
+    pub annotations: HashMap<String, String>,
+    /// group the container is created in, e.g. the sandbox of a pod
+    pub group: Option<String>,
 }
 
 #[async_trait]
@@ -122,10 +251,24 @@ where
         ))
     }
 
+    async fn try_wait(&self, exec_id: Option<&str>) -> Result<Option<(i32, OffsetDateTime)>> {
+        // a deleted exec has exited, and its exit is kept
+        if let Some((_, code, Some(exited_at))) =
+            exec_id.and_then(|id| self.deleted_processes.get(id))
+        {
+            return Ok(Some((*code, *exited_at)));
+        }
+        let process = self.get_process(exec_id)?;
+        Ok(process.try_wait().await)
+    }
+
     async fn delete(
         &mut self,
         exec_id_opt: Option<&str>,
     ) -> Result<(i32, i32, Option<OffsetDateTime>)> {
+        if let Some(exit_info) = exec_id_opt.and_then(|id| self.deleted_processes.get(id)) {
+            return Ok(*exit_info);
+        }
         let (pid, code, exited_at) = self.get_exit_info(exec_id_opt).await?;
         let process = self.get_mut_process(exec_id_opt);
         match process {
@@ -135,6 +278,7 @@ where
         }
         if let Some(exec_id) = exec_id_opt {
             self.processes.remove(exec_id);
+            self.record_deleted(exec_id, (pid, code, exited_at));
         }
         Ok((pid, code, exited_at))
     }
@@ -142,6 +286,8 @@ where
     async fn exec(&mut self, req: ExecProcessRequest) -> Result<()> {
         let exec_id = req.exec_id.to_string();
         let exec_process = self.process_factory.create(&req).await?;
+        // the id of a deleted exec can be taken again by a new one
+        self.deleted_processes.remove(&exec_id);
         self.processes.insert(exec_id, exec_process);
         Ok(())
     }
@@ -159,6 +305,15 @@ where
         self.id.to_string()
     }
 
+    async fn info(&self) -> ContainerInfo {
+        ContainerInfo {
+            id: self.id.to_string(),
+            bundle: self.bundle.to_string(),
+            annotations: self.annotations.clone(),
+            group: self.group.clone(),
+        }
+    }
+
     #[cfg(target_os = "linux")]
     async fn update(&mut self, resources: &LinuxResources) -> Result<()> {
         self.init.update(resources).await
@@ -180,7 +335,46 @@ where
     }
 
     async fn all_processes(&self) -> Result<Vec<ProcessInfo>> {
-        self.init.ps().await
+        let mut processes = self.init.ps().await?;
+        // annotate the processes started by exec with their exec id
+        for (exec_id, p) in self.processes.iter() {
+            let pid = p.pid().await as u32;
+            if let Some(info) = processes.iter_mut().find(|info| info.pid == pid) {
+                let details = ProcessDetails {
+                    exec_id: exec_id.to_string(),
+                    ..Default::default()
+                };
+                info.set_info(convert_to_any(Box::new(details))?);
+            }
+        }
+        Ok(processes)
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        self.init.pause().await
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        self.init.resume().await
+    }
+
+    async fn checkpoint(&mut self, path: &str, opts: &CheckpointOptions) -> Result<()> {
+        self.init.checkpoint(path, opts).await
+    }
+
+    async fn close_io(&mut self, exec_id: Option<&str>) -> Result<()> {
+        let process = self.get_mut_process(exec_id)?;
+        process.close_io().await
+    }
+
+    async fn stdio_streams(&self, exec_id: Option<&str>) -> Result<StdioStreams> {
+        let process = self.get_process(exec_id)?;
+        process.stdio_streams().await
+    }
+
+    async fn reopen_stdin(&mut self, exec_id: Option<&str>) -> Result<()> {
+        let process = self.get_mut_process(exec_id)?;
+        process.reopen_stdin().await
     }
 }
 
@@ -189,6 +383,22 @@ where
     T: Process + Send + Sync,
     E: Process + Send + Sync,
 {
+    /// Keep the exit of a deleted exec, forgetting the oldest exits beyond
+    /// MAX_DELETED_PROCESSES, as execs with unique ids may run for the whole life of a container.
+    fn record_deleted(&mut self, exec_id: &str, exit: (i32, i32, Option<OffsetDateTime>)) {
+        self.deleted_processes.insert(exec_id.to_string(), exit);
+        if self.deleted_processes.len() > MAX_DELETED_PROCESSES {
+            if let Some(oldest) = self
+                .deleted_processes
+                .iter()
+                .min_by_key(|(_, (_, _, exited_at))| *exited_at)
+                .map(|(id, _)| id.clone())
+            {
+                self.deleted_processes.remove(&oldest);
+            }
+        }
+    }
+
     pub fn get_process(&self, exec_id: Option<&str>) -> Result<&(dyn Process + Send + Sync)> {
         match exec_id {
             Some(exec_id) => {
@@ -216,3 +426,248 @@ where
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        asynchronous::processes::{ProcessLifecycle, ProcessTemplate},
+        io::Stdio,
+    };
+
+    pub(crate) struct NoopLifecycle;
+
+    pub(crate) type NoopProcess = ProcessTemplate<NoopLifecycle>;
+
+    #[async_trait]
+    impl ProcessLifecycle<NoopProcess> for NoopLifecycle {
+        async fn start(&self, _p: &mut NoopProcess) -> Result<()> {
+            Ok(())
+        }
+
+        async fn kill(&self, _p: &mut NoopProcess, _signal: u32, _all: bool) -> Result<()> {
+            Ok(())
+        }
+
+        async fn delete(&self, _p: &mut NoopProcess) -> Result<()> {
+            Ok(())
+        }
+
+        async fn update(&self, _p: &mut NoopProcess, _resources: &LinuxResources) -> Result<()> {
+            Ok(())
+        }
+
+        async fn stats(&self, _p: &NoopProcess) -> Result<Metrics> {
+            Ok(Metrics::new())
+        }
+
+        async fn ps(&self, _p: &NoopProcess) -> Result<Vec<ProcessInfo>> {
+            Ok(vec![])
+        }
+    }
+
+    pub(crate) struct NoopFactory;
+
+    #[async_trait]
+    impl ProcessFactory<NoopProcess> for NoopFactory {
+        async fn create(&self, req: &ExecProcessRequest) -> Result<NoopProcess> {
+            Ok(NoopProcess::new(
+                req.exec_id(),
+                Stdio::new("", "", "", false),
+                NoopLifecycle,
+            ))
+        }
+    }
+
+    pub(crate) fn container(
+        annotations: HashMap<String, String>,
+        group: Option<String>,
+    ) -> ContainerTemplate<NoopProcess, NoopProcess, NoopFactory> {
+        ContainerTemplate {
+            id: "fake-id".to_string(),
+            bundle: "/bundle".to_string(),
+            init: NoopProcess::new("fake-id", Stdio::new("", "", "", false), NoopLifecycle),
+            process_factory: NoopFactory,
+            processes: Default::default(),
+            deleted_processes: Default::default(),
+            annotations,
+            group,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_wait() {
+        let mut c = container(HashMap::new(), None);
+        assert_eq!(c.try_wait(None).await.unwrap(), None);
+        c.init.set_exited(3).await;
+        assert_eq!(c.try_wait(None).await.unwrap().unwrap().0, 3);
+
+        let mut req = ExecProcessRequest::new();
+        req.exec_id = "exec".to_string();
+        c.exec(req).await.unwrap();
+        assert_eq!(c.try_wait(Some("exec")).await.unwrap(), None);
+        c.processes.get_mut("exec").unwrap().set_exited(1).await;
+        c.delete(Some("exec")).await.unwrap();
+        // the exit of a deleted exec is kept
+        assert_eq!(c.try_wait(Some("exec")).await.unwrap().unwrap().0, 1);
+        assert!(matches!(
+            c.try_wait(Some("unknown")).await,
+            Err(Error::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deleted_processes_bounded() {
+        let mut c = container(HashMap::new(), None);
+        for i in 0..MAX_DELETED_PROCESSES + 8 {
+            let mut req = ExecProcessRequest::new();
+            req.exec_id = format!("exec-{}", i);
+            c.exec(req).await.unwrap();
+            let p = c.processes.get_mut(&format!("exec-{}", i)).unwrap();
+            p.set_exited(0).await;
+            c.delete(Some(&format!("exec-{}", i))).await.unwrap();
+        }
+        assert_eq!(c.deleted_processes.len(), MAX_DELETED_PROCESSES);
+        assert!(c
+            .deleted_processes
+            .contains_key(&format!("exec-{}", MAX_DELETED_PROCESSES + 7)));
+    }
+
+    #[tokio::test]
+    async fn test_container_info() {
+        let annotations = HashMap::from([(
+            "io.kubernetes.cri.sandbox-id".to_string(),
+            "sandbox".to_string(),
+        )]);
+        let info = container(annotations.clone(), Some("sandbox".to_string()))
+            .info()
+            .await;
+        assert_eq!(
+            info,
+            ContainerInfo {
+                id: "fake-id".to_string(),
+                bundle: "/bundle".to_string(),
+                annotations,
+                group: Some("sandbox".to_string()),
+            }
+        );
+
+        let info = container(HashMap::new(), None).info().await;
+        assert!(info.annotations.is_empty());
+        assert_eq!(info.group, None);
+        assert_eq!(ContainerInfo::default().group, None);
+    }
+
+    /// Creates the spec of the bundle as the container, counting the creations.
+    #[derive(Default)]
+    struct SpecFactory {
+        created: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ContainerFactory<Spec> for SpecFactory {
+        async fn create(&self, _ns: &str, req: &CreateTaskRequest) -> Result<Spec> {
+            self.created
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            crate::asynchronous::util::read_spec(&req.bundle).await
+        }
+
+        async fn cleanup(&self, _ns: &str, _c: &Spec) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct DenyPrivileged;
+
+    #[async_trait]
+    impl CreateHook for DenyPrivileged {
+        async fn pre_create(&self, req: &CreateTaskRequest, spec: &mut Spec) -> Result<()> {
+            let privileged = spec
+                .annotations()
+                .as_ref()
+                .map(|a| a.contains_key("privileged"))
+                .unwrap_or_default();
+            if privileged {
+                return Err(Error::InvalidArgument(format!(
+                    "privileged container {} denied",
+                    req.id
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    struct Annotate;
+
+    #[async_trait]
+    impl CreateHook for Annotate {
+        async fn pre_create(&self, _req: &CreateTaskRequest, spec: &mut Spec) -> Result<()> {
+            let mut annotations = spec.annotations().clone().unwrap_or_default();
+            annotations.insert("policy".to_string(), "checked".to_string());
+            spec.set_annotations(Some(annotations));
+            Ok(())
+        }
+    }
+
+    fn bundle_with(annotations: &[(&str, &str)]) -> (tempfile::TempDir, CreateTaskRequest) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spec = serde_json::to_value(&Spec::default()).unwrap();
+        spec["annotations"] = annotations
+            .iter()
+            .map(|(k, v)| (k.to_string(), serde_json::Value::from(*v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        spec["x-unknown"] = serde_json::Value::from("kept");
+        std::fs::write(dir.path().join(CONFIG_FILE_NAME), spec.to_string()).unwrap();
+        let req = CreateTaskRequest {
+            id: "fake-id".to_string(),
+            bundle: dir.path().display().to_string(),
+            ..Default::default()
+        };
+        (dir, req)
+    }
+
+    #[tokio::test]
+    async fn test_create_hook_deny() {
+        let mut factory = HookedFactory::new(SpecFactory::default());
+        factory.register_create_hook(Arc::new(DenyPrivileged));
+
+        let (_dir, req) = bundle_with(&[("privileged", "true")]);
+        match factory.create("default", &req).await {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("denied")),
+            res => panic!("unexpected create result: {:?}", res.map(|_| ())),
+        }
+        assert_eq!(
+            factory
+                .factory
+                .created
+                .load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+
+        let (_dir, req) = bundle_with(&[]);
+        factory.create("default", &req).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_hook_mutate() {
+        let mut factory = HookedFactory::new(SpecFactory::default());
+        factory.register_create_hook(Arc::new(DenyPrivileged));
+        factory.register_create_hook(Arc::new(Annotate));
+
+        let (dir, req) = bundle_with(&[("owner", "test")]);
+        let spec = factory.create("default", &req).await.unwrap();
+        let annotations = spec.annotations().clone().unwrap();
+        assert_eq!(
+            annotations.get("policy").map(String::as_str),
+            Some("checked")
+        );
+        assert_eq!(annotations.get("owner").map(String::as_str), Some("test"));
+
+        let raw: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(raw["x-unknown"], "kept");
+    }
+}
@@ -53,7 +53,7 @@ use crate::{
     args,
     asynchronous::{monitor::monitor_notify_by_pid, publisher::RemotePublisher},
     error::{Error, Result},
-    logger, parse_sockaddr, reap, socket_address,
+    listener_fd, logger, parse_sockaddr, reap, socket_address,
     util::{asyncify, read_file_to_str, write_str_to_file},
     Config, StartOpts, SOCKET_FD, TTRPC_ADDRESS,
 };
@@ -160,6 +160,7 @@ where
                 handle_signals(signals).await;
             });
             let response = shim.delete_shim().await?;
+            remove_stale_address().await;
             let resp_bytes = response.write_to_bytes()?;
             tokio::io::stdout()
                 .write_all(resp_bytes.as_slice())
@@ -170,14 +171,14 @@ where
         }
         _ => {
             if !config.no_setup_logger {
-                logger::init(flags.debug)?;
+                logger::init(flags.debug || config.debug)?;
             }
 
             let publisher = RemotePublisher::new(&ttrpc_address).await?;
             let task = shim.create_task_service(publisher).await;
             let task_service = create_task(Arc::new(Box::new(task)));
             let mut server = Server::new().register_service(task_service);
-            server = server.add_listener(SOCKET_FD)?;
+            server = server.add_listener(listener_fd(&flags.socket)?)?;
             server = server.set_domain_unix();
             server.start().await?;
 
@@ -195,6 +196,9 @@ where
             if let Ok(address) = read_file_to_str("address").await {
                 remove_socket_silently(&address).await;
             }
+            if !flags.socket.is_empty() {
+                remove_socket_silently(&flags.socket).await;
+            }
             Ok(())
         }
     }
@@ -362,6 +366,21 @@ async fn remove_socket_silently(address: &str) {
         .unwrap_or_else(|e| warn!("failed to remove socket: {}", e))
 }
 
+/// Remove the shim socket recorded in the bundle's address file if nothing is
+/// listening on it anymore, together with the address file itself.
+async fn remove_stale_address() {
+    if let Ok(address) = read_file_to_str("address").await {
+        if Client::connect(&address).is_err() {
+            remove_socket_silently(&address).await;
+        }
+    }
+    tokio::fs::remove_file("address").await.unwrap_or_else(|e| {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("failed to remove address file: {}", e)
+        }
+    });
+}
+
 async fn remove_socket(address: &str) -> Result<()> {
     let path = parse_sockaddr(address);
     if let Ok(md) = Path::new(path).metadata() {
@@ -14,19 +14,45 @@
    limitations under the License.
 */
 
-use std::{os::unix::io::AsRawFd, sync::Arc};
+use std::{
+    os::unix::io::AsRawFd,
+    sync::{Arc, Mutex},
+};
 
 use async_trait::async_trait;
 use containerd_shim_protos::{
     api::{ProcessInfo, StateResponse, Status},
     cgroups::metrics::Metrics,
     protobuf::well_known_types::timestamp::Timestamp,
+    shim::oci::CheckpointOptions,
 };
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use oci_spec::runtime::LinuxResources;
 use time::OffsetDateTime;
-use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncRead, AsyncWrite},
+    sync::{
+        mpsc::UnboundedSender,
+        oneshot::{channel, Receiver, Sender},
+    },
+};
+
+use crate::{io::Stdio, io_error, ioctl_set_winsz, other, util::asyncify, Console, Error};
 
-use crate::{io::Stdio, ioctl_set_winsz, util::asyncify, Console};
+/// Streaming handles to the stdio of a process, attached to its console if it
+/// has one, or to its stdio fifos otherwise.
+///
+/// Note that the fifos are shared with the other readers such as containerd,
+/// so reading from `stdout` or `stderr` takes the output away from them.
+/// The console is read by the copy of the shim to the stdout fifo, so only its
+/// `stdin` is given out, as a second reader would steal bytes from that copy.
+#[derive(Default)]
+pub struct StdioStreams {
+    pub stdin: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    pub stdout: Option<Box<dyn AsyncRead + Send + Unpin>>,
+    pub stderr: Option<Box<dyn AsyncRead + Send + Unpin>>,
+}
 
 #[async_trait]
 pub trait Process {
@@ -39,10 +65,35 @@ pub trait Process {
     async fn wait_channel(&mut self) -> crate::Result<Receiver<()>>;
     async fn exit_code(&self) -> i32;
     async fn exited_at(&self) -> Option<OffsetDateTime>;
+    /// Exit code and time of the process if it has exited, without waiting for it.
+    async fn try_wait(&self) -> Option<(i32, OffsetDateTime)> {
+        let exited_at = self.exited_at().await?;
+        Some((self.exit_code().await, exited_at))
+    }
     async fn resize_pty(&mut self, height: u32, width: u32) -> crate::Result<()>;
     async fn update(&mut self, resources: &LinuxResources) -> crate::Result<()>;
     async fn stats(&self) -> crate::Result<Metrics>;
     async fn ps(&self) -> crate::Result<Vec<ProcessInfo>>;
+    async fn pause(&mut self) -> crate::Result<()> {
+        Err(Error::Unimplemented("pause".to_string()))
+    }
+    async fn resume(&mut self) -> crate::Result<()> {
+        Err(Error::Unimplemented("resume".to_string()))
+    }
+    async fn checkpoint(&mut self, _path: &str, _opts: &CheckpointOptions) -> crate::Result<()> {
+        Err(Error::Unimplemented("checkpoint".to_string()))
+    }
+    async fn close_io(&mut self) -> crate::Result<()> {
+        Err(Error::Unimplemented("close_io".to_string()))
+    }
+    async fn stdio_streams(&self) -> crate::Result<StdioStreams> {
+        Err(Error::Unimplemented("stdio_streams".to_string()))
+    }
+    /// Reopen the stdin fifo, e.g. after it was recreated for a client attaching again,
+    /// the copy of stdin moves to the new fifo once the current one is drained.
+    async fn reopen_stdin(&mut self) -> crate::Result<()> {
+        Err(Error::Unimplemented("reopen_stdin".to_string()))
+    }
 }
 
 #[async_trait]
@@ -53,6 +104,29 @@ pub trait ProcessLifecycle<P: Process> {
     async fn update(&self, p: &mut P, resources: &LinuxResources) -> crate::Result<()>;
     async fn stats(&self, p: &P) -> crate::Result<Metrics>;
     async fn ps(&self, p: &P) -> crate::Result<Vec<ProcessInfo>>;
+    async fn pause(&self, _p: &mut P) -> crate::Result<()>
+    where
+        P: Send,
+    {
+        Err(Error::Unimplemented("pause".to_string()))
+    }
+    async fn resume(&self, _p: &mut P) -> crate::Result<()>
+    where
+        P: Send,
+    {
+        Err(Error::Unimplemented("resume".to_string()))
+    }
+    async fn checkpoint(
+        &self,
+        _p: &mut P,
+        _path: &str,
+        _opts: &CheckpointOptions,
+    ) -> crate::Result<()>
+    where
+        P: Send,
+    {
+        Err(Error::Unimplemented("checkpoint".to_string()))
+    }
 }
 
 pub struct ProcessTemplate<S> {
@@ -64,6 +138,11 @@ pub struct ProcessTemplate<S> {
     pub exited_at: Option<OffsetDateTime>,
     pub wait_chan_tx: Vec<Sender<()>>,
     pub console: Option<Console>,
+    /// write end of the stdin fifo held by the shim, dropped when stdin is closed
+    pub stdin: Arc<Mutex<Option<File>>>,
+    /// hands the read end of a reopened stdin fifo to the copy of stdin,
+    /// dropped when stdin is closed so that the copy ends
+    pub stdin_reopen: Option<UnboundedSender<File>>,
     pub lifecycle: Arc<S>,
 }
 
@@ -78,6 +157,8 @@ impl<S> ProcessTemplate<S> {
             exited_at: None,
             wait_chan_tx: vec![],
             console: None,
+            stdin: Arc::new(Mutex::new(None)),
+            stdin_reopen: None,
             lifecycle: Arc::new(lifecycle),
         }
     }
@@ -148,6 +229,10 @@ where
         self.exited_at
     }
 
+    async fn try_wait(&self) -> Option<(i32, OffsetDateTime)> {
+        self.exited_at.map(|exited_at| (self.exit_code, exited_at))
+    }
+
     async fn resize_pty(&mut self, height: u32, width: u32) -> crate::Result<()> {
         if let Some(console) = self.console.as_ref() {
             let w = libc::winsize {
@@ -176,4 +261,101 @@ where
     async fn ps(&self) -> crate::Result<Vec<ProcessInfo>> {
         self.lifecycle.ps(self).await
     }
+
+    async fn pause(&mut self) -> crate::Result<()> {
+        self.lifecycle.clone().pause(self).await
+    }
+
+    async fn resume(&mut self) -> crate::Result<()> {
+        self.lifecycle.clone().resume(self).await
+    }
+
+    async fn checkpoint(&mut self, path: &str, opts: &CheckpointOptions) -> crate::Result<()> {
+        self.lifecycle.clone().checkpoint(self, path, opts).await
+    }
+
+    async fn close_io(&mut self) -> crate::Result<()> {
+        self.stdin_reopen.take();
+        self.stdin.lock().unwrap().take();
+        Ok(())
+    }
+
+    async fn reopen_stdin(&mut self) -> crate::Result<()> {
+        let reopen = self
+            .stdin_reopen
+            .as_ref()
+            .ok_or_else(|| other!("stdin of process {} can not be reopened", self.id))?;
+        let (stdin, stdin_w) = open_stdin_fifo(&self.stdio.stdin).await?;
+        // hold the new fifo before releasing the old one, whose copy then ends at its EOF
+        let old = self.stdin.lock().unwrap().replace(stdin_w);
+        reopen
+            .send(stdin)
+            .map_err(|_| other!("stdin copy of process {} has stopped", self.id))?;
+        drop(old);
+        Ok(())
+    }
+
+    async fn stdio_streams(&self) -> crate::Result<StdioStreams> {
+        if let Some(console) = self.console.as_ref() {
+            let stdin = console
+                .file
+                .try_clone()
+                .map_err(io_error!(e, "dup console"))?;
+            return Ok(StdioStreams {
+                stdin: Some(Box::new(File::from_std(stdin))),
+                ..Default::default()
+            });
+        }
+
+        let mut streams = StdioStreams::default();
+        if is_fifo_path(&self.stdio.stdin) {
+            streams.stdin = Some(Box::new(open_fifo_writer(&self.stdio.stdin).await?));
+        }
+        if is_fifo_path(&self.stdio.stdout) {
+            streams.stdout = Some(Box::new(open_fifo_reader(&self.stdio.stdout).await?));
+        }
+        if is_fifo_path(&self.stdio.stderr) {
+            streams.stderr = Some(Box::new(open_fifo_reader(&self.stdio.stderr).await?));
+        }
+        Ok(streams)
+    }
+}
+
+/// Open the read end of the stdin fifo of a process together with a write end, held by
+/// the shim so that the read end does not reach EOF when a client closes its side.
+pub async fn open_stdin_fifo(path: &str) -> crate::Result<(File, File)> {
+    let stdin = async { OpenOptions::new().read(true).open(path).await };
+    let stdin_w = async { OpenOptions::new().write(true).open(path).await };
+    tokio::try_join!(stdin, stdin_w).map_err(io_error!(e, "open stdin {}", path))
+}
+
+/// Stdio given as an uri, such as a log file or a logging binary, can not be attached to.
+fn is_fifo_path(path: &str) -> bool {
+    !path.is_empty() && !path.contains("://")
+}
+
+/// Open the read end of a fifo without blocking on a missing writer,
+/// the returned file is switched back to blocking mode.
+async fn open_fifo_reader(path: &str) -> crate::Result<File> {
+    let f = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .await
+        .map_err(io_error!(e, "open {}", path))?;
+    fcntl(f.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+    Ok(f)
+}
+
+/// Open the write end of a fifo, failing with `ENXIO` instead of blocking when it has
+/// no reader, the returned file is switched back to blocking mode.
+async fn open_fifo_writer(path: &str) -> crate::Result<File> {
+    let f = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .await
+        .map_err(io_error!(e, "open {}", path))?;
+    fcntl(f.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+    Ok(f)
 }
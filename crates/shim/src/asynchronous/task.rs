@@ -14,23 +14,36 @@
    limitations under the License.
 */
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use containerd_shim_protos::{
     api::{
-        CloseIORequest, ConnectRequest, ConnectResponse, DeleteResponse, PidsRequest, PidsResponse,
-        StatsRequest, StatsResponse, UpdateTaskRequest,
+        CheckpointTaskRequest, CloseIORequest, ConnectRequest, ConnectResponse, DeleteResponse,
+        PauseRequest, PidsRequest, PidsResponse, ResumeRequest, StatsRequest, StatsResponse,
+        UpdateTaskRequest,
     },
-    events::task::{TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskStart},
-    protobuf::MessageDyn,
+    cgroups::metrics::Metrics,
+    events::task::{
+        TaskCheckpointed, TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskOOM,
+        TaskPaused, TaskResumed, TaskStart,
+    },
+    protobuf::{CodedInputStream, Message, MessageDyn},
+    shim::oci::CheckpointOptions,
     shim_async::Task,
     ttrpc,
     ttrpc::r#async::TtrpcContext,
 };
 use log::{debug, info, warn};
 use oci_spec::runtime::LinuxResources;
-use tokio::sync::{mpsc::Sender, MappedMutexGuard, Mutex, MutexGuard};
+use time::OffsetDateTime;
+use tokio::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        MappedMutexGuard, Mutex, MutexGuard,
+    },
+    time::{Instant, MissedTickBehavior},
+};
 
 use crate::{
     api::{
@@ -39,16 +52,19 @@ use crate::{
         StateResponse, Status, WaitRequest, WaitResponse,
     },
     asynchronous::{
-        container::{Container, ContainerFactory},
+        container::{Container, ContainerFactory, CreateHook, HookedFactory},
         ExitSignal,
     },
     event::Event,
     util::{convert_to_any, convert_to_timestamp, AsOption},
-    TtrpcResult,
+    Error, TtrpcResult,
 };
 
 type EventSender = Sender<(String, Box<dyn MessageDyn>)>;
 
+/// How long a forced shutdown waits for the killed containers to exit.
+const SHUTDOWN_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// TaskService is a Task template struct, it is considered a helper struct,
 /// which has already implemented `Task` trait, so that users can make it the type `T`
 /// parameter of `Service`, and implements their own `ContainerFactory` and `Container`.
@@ -75,6 +91,13 @@ where
     }
 }
 
+impl<F, C> TaskService<HookedFactory<F>, C> {
+    /// Run `hook` before the creation of the containers, after the hooks registered before.
+    pub fn register_create_hook(&mut self, hook: Arc<dyn CreateHook>) {
+        self.factory.register_create_hook(hook);
+    }
+}
+
 impl<F, C> TaskService<F, C> {
     pub async fn get_container(&self, id: &str) -> TtrpcResult<MappedMutexGuard<'_, C>> {
         let mut containers = self.containers.lock().await;
@@ -95,6 +118,101 @@ impl<F, C> TaskService<F, C> {
             .await
             .unwrap_or_else(|e| warn!("send {} to publisher: {}", topic, e));
     }
+
+    /// Publish a TaskOOM event every time the container's memory cgroup hits an OOM
+    #[cfg(target_os = "linux")]
+    fn monitor_oom(&self, id: &str, pid: u32) {
+        let rx = match crate::cgroup::register_oom_event(id, pid) {
+            Ok(rx) => rx,
+            Err(e) => {
+                warn!("failed to register oom event for {}: {}", id, e);
+                return;
+            }
+        };
+        let tx = self.tx.clone();
+        std::thread::spawn(move || {
+            while let Ok(container_id) = rx.recv() {
+                debug!("oom event for container {}", container_id);
+                let event = TaskOOM {
+                    container_id,
+                    ..Default::default()
+                };
+                let topic = event.topic();
+                if let Err(e) = tx.blocking_send((topic.to_string(), Box::new(event))) {
+                    warn!("send {} to publisher: {}", topic, e);
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl<F, C> TaskService<F, C>
+where
+    C: Container + Sync + Send + 'static,
+{
+    /// Spawn a task sampling the metrics of all live containers every `interval`,
+    /// the samples are sent along with the container id to the returned receiver,
+    /// so that embedders can export them without polling through the Stats rpc.
+    /// Sampling stops when the shim exits or the receiver is dropped.
+    pub fn sample_metrics(&self, interval: Duration) -> Receiver<(String, Metrics)> {
+        let (tx, rx) = channel(128);
+        let containers = self.containers.clone();
+        let exit = self.exit.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = exit.wait() => return,
+                }
+                let mut samples = vec![];
+                for (id, container) in containers.lock().await.iter() {
+                    match container.stats().await {
+                        Ok(metrics) => samples.push((id.clone(), metrics)),
+                        Err(e) => debug!("failed to sample metrics of {}: {}", id, e),
+                    }
+                }
+                for sample in samples {
+                    if tx.send(sample).await.is_err() {
+                        debug!("metrics receiver dropped, stop sampling");
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    /// Wait for at most `timeout` for a process to exit, returning its exit code and time,
+    /// or `None` if it still runs by then. The container is only locked to look the process
+    /// up, as its exit is recorded under the same lock.
+    ///
+    /// An exec deleted right after its exit is still reported, as the container keeps the
+    /// exits of its deleted execs, while a deleted container is not found.
+    pub async fn wait_timeout(
+        &self,
+        id: &str,
+        exec_id: Option<&str>,
+        timeout: Duration,
+    ) -> crate::Result<Option<(i32, OffsetDateTime)>> {
+        let not_found = || Error::NotFoundError(format!("can not find container by id {}", id));
+        let wait_rx = {
+            let mut containers = self.containers.lock().await;
+            let container = containers.get_mut(id).ok_or_else(not_found)?;
+            if let Some(exit) = container.try_wait(exec_id).await? {
+                return Ok(Some(exit));
+            }
+            container.wait_channel(exec_id).await?
+        };
+        if tokio::time::timeout(timeout, wait_rx).await.is_err() {
+            return Ok(None);
+        }
+        let containers = self.containers.lock().await;
+        let container = containers.get(id).ok_or_else(not_found)?;
+        container.try_wait(exec_id).await
+    }
 }
 
 #[async_trait]
@@ -110,6 +228,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, pid = tracing::field::Empty))
+    )]
     async fn create(
         &self,
         _ctx: &TtrpcContext,
@@ -127,6 +249,8 @@ where
         let mut resp = CreateTaskResponse::new();
         let pid = container.pid().await as u32;
         resp.pid = pid;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pid", pid);
 
         containers.insert(id.to_string(), container);
 
@@ -151,10 +275,16 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id, pid = tracing::field::Empty))
+    )]
     async fn start(&self, _ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
         info!("Start request for {:?}", &req);
         let mut container = self.get_container(req.id()).await?;
         let pid = container.start(req.exec_id.as_str().as_option()).await?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pid", pid);
 
         let mut resp = StartResponse::new();
         resp.pid = pid as u32;
@@ -166,6 +296,8 @@ where
                 ..Default::default()
             })
             .await;
+            #[cfg(target_os = "linux")]
+            self.monitor_oom(req.id(), pid as u32);
         } else {
             self.send_event(TaskExecStarted {
                 container_id: req.id.to_string(),
@@ -180,6 +312,10 @@ where
         Ok(resp)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id))
+    )]
     async fn delete(&self, _ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
         info!("Delete request for {:?}", &req);
         let mut containers = self.containers.lock().await;
@@ -192,21 +328,21 @@ where
         let id = container.id().await;
         let exec_id_opt = req.exec_id().as_option();
         let (pid, exit_status, exited_at) = container.delete(exec_id_opt).await?;
-        self.factory.cleanup(&self.namespace, container).await?;
-        if req.exec_id().is_empty() {
+        let ts = convert_to_timestamp(exited_at);
+        // deleting an exec only removes the process, the container is kept
+        if exec_id_opt.is_none() {
+            self.factory.cleanup(&self.namespace, container).await?;
             containers.remove(req.id());
+            self.send_event(TaskDelete {
+                container_id: id,
+                pid: pid as u32,
+                exit_status: exit_status as u32,
+                exited_at: Some(ts.clone()).into(),
+                ..Default::default()
+            })
+            .await;
         }
 
-        let ts = convert_to_timestamp(exited_at);
-        self.send_event(TaskDelete {
-            container_id: id,
-            pid: pid as u32,
-            exit_status: exit_status as u32,
-            exited_at: Some(ts.clone()).into(),
-            ..Default::default()
-        })
-        .await;
-
         let mut resp = DeleteResponse::new();
         resp.set_exited_at(ts);
         resp.set_pid(pid as u32);
@@ -231,6 +367,10 @@ where
         })
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id, signal = req.signal))
+    )]
     async fn kill(&self, _ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
         info!("Kill request for {:?}", req);
         let mut container = self.get_container(req.id()).await?;
@@ -241,6 +381,60 @@ where
         Ok(Empty::new())
     }
 
+    async fn pause(&self, _ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        info!("Pause request for {:?}", req);
+        let mut container = self.get_container(req.id()).await?;
+        container.pause().await?;
+        self.send_event(TaskPaused {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        })
+        .await;
+        info!("Pause request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    async fn resume(&self, _ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        info!("Resume request for {:?}", req);
+        let mut container = self.get_container(req.id()).await?;
+        container.resume().await?;
+        self.send_event(TaskResumed {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        })
+        .await;
+        info!("Resume request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    async fn checkpoint(
+        &self,
+        _ctx: &TtrpcContext,
+        req: CheckpointTaskRequest,
+    ) -> TtrpcResult<Empty> {
+        info!("Checkpoint request for {:?}", req);
+        let mut opts = CheckpointOptions::new();
+        if let Some(any) = req.options.as_ref() {
+            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
+            opts.merge_from(&mut input)
+                .map_err(other_error!(e, "failed to parse checkpoint options"))?;
+        }
+        let mut container = self.get_container(req.id()).await?;
+        container.checkpoint(req.path(), &opts).await?;
+        self.send_event(TaskCheckpointed {
+            container_id: req.id.to_string(),
+            checkpoint: req.path.to_string(),
+            ..Default::default()
+        })
+        .await;
+        info!("Checkpoint request for {:?} returns successfully", req);
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %req.id, exec_id = %req.exec_id))
+    )]
     async fn exec(&self, _ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
         info!("Exec request for {:?}", req);
         let exec_id = req.exec_id().to_string();
@@ -269,8 +463,12 @@ where
         Ok(Empty::new())
     }
 
-    async fn close_io(&self, _ctx: &TtrpcContext, _req: CloseIORequest) -> TtrpcResult<Empty> {
-        // TODO call close_io of container
+    async fn close_io(&self, _ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!("Close io request for {:?}", req);
+        if req.stdin {
+            let mut container = self.get_container(req.id()).await?;
+            container.close_io(req.exec_id().as_option()).await?;
+        }
         Ok(Empty::new())
     }
 
@@ -350,13 +548,134 @@ where
         })
     }
 
-    async fn shutdown(&self, _ctx: &TtrpcContext, _req: ShutdownRequest) -> TtrpcResult<Empty> {
+    async fn shutdown(&self, _ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
         debug!("Shutdown request");
-        let containers = self.containers.lock().await;
-        if containers.len() > 0 {
-            return Ok(Empty::new());
+        let mut containers = self.containers.lock().await;
+        if !containers.is_empty() {
+            if !req.now {
+                return Ok(Empty::new());
+            }
+            // kill all the containers and wait for their init processes to exit,
+            // the lock is released while waiting so that exits can be processed.
+            // A container which could not be killed is not waited for, nor the others
+            // for longer than SHUTDOWN_WAIT_TIMEOUT.
+            let mut waits = vec![];
+            for (id, container) in containers.iter_mut() {
+                match container.kill(None, libc::SIGKILL as u32, true).await {
+                    Ok(_) => waits.push((id.clone(), container.wait_channel(None).await?)),
+                    Err(e) => warn!("failed to kill container {} on shutdown: {}", id, e),
+                }
+            }
+            drop(containers);
+            let deadline = Instant::now() + SHUTDOWN_WAIT_TIMEOUT;
+            for (id, rx) in waits {
+                if tokio::time::timeout_at(deadline, rx).await.is_err() {
+                    warn!("container {} did not exit on shutdown", id);
+                }
+            }
+            containers = self.containers.lock().await;
+            for (id, container) in containers.iter_mut() {
+                if let Err(e) = container.delete(None).await {
+                    warn!("failed to delete container {} on shutdown: {}", id, e);
+                }
+                if let Err(e) = self.factory.cleanup(&self.namespace, container).await {
+                    warn!("failed to cleanup container {} on shutdown: {}", id, e);
+                }
+            }
+            containers.clear();
         }
         self.exit.signal();
         Ok(Empty::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asynchronous::{
+        container::{
+            tests::{container, NoopFactory, NoopProcess},
+            ContainerTemplate,
+        },
+        processes::Process,
+    };
+
+    type TestContainer = ContainerTemplate<NoopProcess, NoopProcess, NoopFactory>;
+
+    async fn task_service() -> TaskService<(), TestContainer> {
+        let (tx, _rx) = channel(1);
+        let task = TaskService::new("test", Arc::new(ExitSignal::default()), tx);
+        let c = container(HashMap::new(), None);
+        task.containers.lock().await.insert(c.id.clone(), c);
+        task
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout() {
+        let task = task_service().await;
+        let timeout = Duration::from_millis(50);
+        assert_eq!(
+            task.wait_timeout("fake-id", None, timeout).await.unwrap(),
+            None
+        );
+        assert!(matches!(
+            task.wait_timeout("unknown", None, timeout).await,
+            Err(Error::NotFoundError(_))
+        ));
+
+        let containers = task.containers.clone();
+        let exit = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut containers = containers.lock().await;
+            containers
+                .get_mut("fake-id")
+                .unwrap()
+                .init
+                .set_exited(7)
+                .await;
+        });
+        let res = task
+            .wait_timeout("fake-id", None, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(res.unwrap().0, 7);
+        exit.await.unwrap();
+        // an exited process is reported at once
+        let res = task
+            .wait_timeout("fake-id", None, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(res.unwrap().0, 7);
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout_deleted_exec() {
+        let task = task_service().await;
+        let mut req = ExecProcessRequest::new();
+        req.exec_id = "exec".to_string();
+        task.containers
+            .lock()
+            .await
+            .get_mut("fake-id")
+            .unwrap()
+            .exec(req)
+            .await
+            .unwrap();
+
+        // the exec is deleted before the waiter gets the lock again
+        let containers = task.containers.clone();
+        let exit = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut containers = containers.lock().await;
+            let c = containers.get_mut("fake-id").unwrap();
+            c.processes.get_mut("exec").unwrap().set_exited(2).await;
+            c.delete(Some("exec")).await.unwrap();
+        });
+        let res = task
+            .wait_timeout("fake-id", Some("exec"), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(res.unwrap().0, 2);
+        exit.await.unwrap();
+    }
+}
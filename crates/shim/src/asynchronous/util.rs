@@ -26,6 +26,11 @@ use tokio::{
     task::spawn_blocking,
 };
 
+#[cfg(target_os = "linux")]
+use crate::{
+    asynchronous::monitor::{monitor_subscribe, monitor_unsubscribe},
+    monitor::{ExitEvent, Subject, Topic},
+};
 use crate::{
     error::{Error, Result},
     util::{AsOption, JsonOptions, CONFIG_FILE_NAME, OPTIONS_FILE_NAME, RUNTIME_FILE_NAME},
@@ -121,12 +126,57 @@ pub async fn mount_rootfs(m: &Mount, target: impl AsRef<Path>) -> Result<()> {
     let source = m.source.to_string();
     let options = m.options.to_vec();
     let rootfs = target.as_ref().to_owned();
-    asyncify(move || -> Result<()> {
-        let mount_type = mount_type.as_option();
-        let source = source.as_option();
-        crate::mount::mount_rootfs(mount_type, source, options.as_slice(), &rootfs)
-    })
-    .await
+    let res = {
+        let mount_type = mount_type.clone();
+        let options = options.clone();
+        let rootfs = rootfs.clone();
+        asyncify(move || -> Result<()> {
+            let mount_type = mount_type.as_option();
+            let source = source.as_option();
+            crate::mount::mount_rootfs(mount_type, source, options.as_slice(), &rootfs)
+        })
+        .await
+    };
+    match res {
+        #[cfg(target_os = "linux")]
+        Err(Error::MountError { err, .. })
+            if crate::mount::use_fuse_overlayfs(mount_type.as_option(), err) =>
+        {
+            mount_fuse_overlayfs(&options, &rootfs).await
+        }
+        r => r,
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn mount_fuse_overlayfs(options: &[String], target: &Path) -> Result<()> {
+    let mut s = monitor_subscribe(Topic::Pid).await?;
+    let child = match crate::mount::fuse_overlayfs_command(options, target).spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            monitor_unsubscribe(s.id).await.unwrap_or_default();
+            return Err(Error::IoError {
+                context: "spawn fuse-overlayfs".to_string(),
+                err: e,
+            });
+        }
+    };
+    let pid = child.id() as i32;
+    let code = loop {
+        match s.rx.recv().await {
+            Some(ExitEvent {
+                subject: Subject::Pid(epid),
+                exit_code,
+            }) if epid == pid => break exit_code,
+            Some(_) => continue,
+            None => break -1,
+        }
+    };
+    monitor_unsubscribe(s.id).await.unwrap_or_default();
+    match code {
+        0 => Ok(()),
+        code => Err(other!("fuse-overlayfs exited with {}", code)),
+    }
 }
 
 pub async fn mkdir(path: impl AsRef<Path>, mode: mode_t) -> Result<()> {
@@ -16,20 +16,25 @@
 
 #![cfg(target_os = "linux")]
 
-use std::{fs, io::Read, path::Path};
+use std::{fs, io::Read, path::Path, sync::mpsc::Receiver};
 
 use cgroups_rs::{
-    cgroup::get_cgroups_relative_paths_by_pid, hierarchies, Cgroup, CgroupPid, MaxValue, Subsystem,
+    cgroup::get_cgroups_relative_paths_by_pid, hierarchies, memory::MemController, Cgroup,
+    CgroupPid, MaxValue, Subsystem,
 };
 use containerd_shim_protos::{
     cgroups::metrics::{CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics},
     protobuf::{well_known_types::any::Any, Message},
     shim::oci::Options,
 };
+use nix::sys::statfs::statfs;
 use oci_spec::runtime::LinuxResources;
 
 use crate::error::{Error, Result};
 
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_HYBRID_UNIFIED: &str = "/sys/fs/cgroup/unified";
+
 // OOM_SCORE_ADJ_MAX is from https://github.com/torvalds/linux/blob/master/include/uapi/linux/oom.h#L10
 const OOM_SCORE_ADJ_MAX: i64 = 1000;
 
@@ -93,13 +98,89 @@ fn write_process_oom_score(pid: u32, score: i64) -> Result<()> {
         .map_err(io_error!(e, "write oom score"))
 }
 
+/// Layout of the cgroup hierarchies of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupMode {
+    /// cgroup v1 hierarchies only.
+    Legacy,
+    /// cgroup v1 controllers, with a cgroup v2 hierarchy mounted at `/sys/fs/cgroup/unified`.
+    Hybrid,
+    /// A single cgroup v2 hierarchy.
+    Unified,
+}
+
+/// Detect the cgroup mode of the host from the filesystem mounted on `/sys/fs/cgroup`.
+pub fn cgroup_mode() -> CgroupMode {
+    let is_cgroup2 = |path: &str| {
+        statfs(path)
+            .map(|fs| fs.filesystem_type().0 as i64 == libc::CGROUP2_SUPER_MAGIC as i64)
+            .unwrap_or_default()
+    };
+    if is_cgroup2(CGROUP_ROOT) {
+        CgroupMode::Unified
+    } else if is_cgroup2(CGROUP_HYBRID_UNIFIED) {
+        CgroupMode::Hybrid
+    } else {
+        CgroupMode::Legacy
+    }
+}
+
+/// Whether a cgroups path is in the `slice:prefix:name` form of the systemd cgroup driver,
+/// in which case runc must be run with `--systemd-cgroup`.
+pub fn is_systemd_cgroups_path(path: &str) -> bool {
+    let parts: Vec<&str> = path.split(':').collect();
+    parts.len() == 3 && (parts[0].is_empty() || parts[0].ends_with(".slice"))
+}
+
+/// Get the cgroup of a process, relative to the cgroup root: the cgroup v2 path in the
+/// unified mode, or the path in the memory hierarchy otherwise.
+pub fn process_cgroup_path(pid: u32) -> Result<String> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).map_err(io_error!(
+        e,
+        "read cgroup of process {}",
+        pid
+    ))?;
+    parse_cgroup_path(&content, cgroup_mode() == CgroupMode::Unified)
+        .ok_or_else(|| other!("no cgroup found for process {}", pid))
+}
+
+/// Find the cgroup path in the content of a `/proc/<pid>/cgroup` file.
+fn parse_cgroup_path(content: &str, unified: bool) -> Option<String> {
+    content.lines().find_map(|line| {
+        // hierarchy-id:controller-list:cgroup-path
+        let fields: Vec<&str> = line.splitn(3, ':').collect();
+        if fields.len() != 3 {
+            return None;
+        }
+        let found = if unified {
+            fields[0] == "0" && fields[1].is_empty()
+        } else {
+            fields[1].split(',').any(|c| c == "memory")
+        };
+        found.then(|| fields[2].to_string())
+    })
+}
+
+/// Load the cgroup of a process.
+///
+/// The v1 controllers are taken from their own hierarchies, while in the unified mode the
+/// single v2 cgroup of the process is loaded, as a relative path of "." is the root cgroup there.
+fn load_process_cgroup(pid: u32) -> Result<Cgroup> {
+    let h = hierarchies::auto();
+    if cgroup_mode() == CgroupMode::Unified {
+        let path = process_cgroup_path(pid)?;
+        return Ok(Cgroup::load(h, path.trim_start_matches('/')));
+    }
+    let paths =
+        get_cgroups_relative_paths_by_pid(pid).map_err(other_error!(e, "get process cgroup"))?;
+    Ok(Cgroup::load_with_relative_paths(h, Path::new("."), paths))
+}
+
 /// Collect process cgroup stats, return only necessary parts of it
 pub fn collect_metrics(pid: u32) -> Result<Metrics> {
     let mut metrics = Metrics::new();
     // get container main process cgroup
-    let path =
-        get_cgroups_relative_paths_by_pid(pid).map_err(other_error!(e, "get process cgroup"))?;
-    let cgroup = Cgroup::load_with_relative_paths(hierarchies::auto(), Path::new("."), path);
+    let cgroup = load_process_cgroup(pid)?;
 
     // to make it easy, fill the necessary metrics only.
     for sub_system in Cgroup::subsystems(&cgroup) {
@@ -126,12 +207,29 @@ pub fn collect_metrics(pid: u32) -> Result<Metrics> {
     Ok(metrics)
 }
 
+/// Register an OOM notifier on the memory cgroup of the process.
+///
+/// Cgroup v1 is watched through an eventfd on `memory.oom_control` and cgroup v2 through inotify
+/// on `memory.events`. `id` is sent to the returned receiver each time an OOM kill happens, and
+/// the receiver is disconnected once the cgroup is removed.
+pub fn register_oom_event(id: &str, pid: u32) -> Result<Receiver<String>> {
+    let cgroup = load_process_cgroup(pid)?;
+    let mem_ctr: &MemController = cgroup.controller_of().ok_or_else(|| {
+        other!(
+            "no memory controller for process {} in {:?} cgroup mode",
+            pid,
+            cgroup_mode()
+        )
+    })?;
+    mem_ctr
+        .register_oom_event(id)
+        .map_err(other_error!(e, "register oom event"))
+}
+
 /// Update process cgroup limits
 pub fn update_resources(pid: u32, resources: &LinuxResources) -> Result<()> {
     // get container main process cgroup
-    let path =
-        get_cgroups_relative_paths_by_pid(pid).map_err(other_error!(e, "get process cgroup"))?;
-    let cgroup = Cgroup::load_with_relative_paths(hierarchies::auto(), Path::new("."), path);
+    let cgroup = load_process_cgroup(pid)?;
 
     for sub_system in Cgroup::subsystems(&cgroup) {
         match sub_system {
@@ -222,7 +320,8 @@ mod tests {
     use cgroups_rs::{hierarchies, Cgroup, CgroupPid};
 
     use crate::cgroup::{
-        add_task_to_cgroup, adjust_oom_score, read_process_oom_score, OOM_SCORE_ADJ_MAX,
+        add_task_to_cgroup, adjust_oom_score, is_systemd_cgroups_path, parse_cgroup_path,
+        read_process_oom_score, OOM_SCORE_ADJ_MAX,
     };
 
     #[test]
@@ -256,4 +355,32 @@ mod tests {
             assert_eq!(new, OOM_SCORE_ADJ_MAX)
         }
     }
+
+    #[test]
+    fn test_systemd_cgroups_path() {
+        assert!(is_systemd_cgroups_path("system.slice:containerd:abc"));
+        assert!(is_systemd_cgroups_path(":cri-containerd:abc"));
+        assert!(!is_systemd_cgroups_path("/default/abc"));
+        assert!(!is_systemd_cgroups_path("system:containerd:abc"));
+    }
+
+    #[test]
+    fn test_process_cgroup_path() {
+        let v1 = "12:pids:/system.slice/containerd.service\n\
+                  11:cpu,cpuacct:/default/abc\n\
+                  10:memory:/default/abc\n\
+                  0::/system.slice/containerd.service\n";
+        assert_eq!(
+            parse_cgroup_path(v1, false).as_deref(),
+            Some("/default/abc")
+        );
+
+        let v2 = "0::/default/abc\n";
+        assert_eq!(parse_cgroup_path(v2, true).as_deref(), Some("/default/abc"));
+        assert_eq!(parse_cgroup_path(v2, false), None);
+
+        let hybrid = "10:memory:/default/abc\n1:name=systemd:/user.slice\n";
+        assert_eq!(parse_cgroup_path(hybrid, true), None);
+        assert_eq!(parse_cgroup_path("", false), None);
+    }
 }
@@ -14,6 +14,18 @@
    limitations under the License.
 */
 
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use nix::{errno::Errno, sys::stat::Mode, unistd::mkfifo};
+
+use crate::{error::Result, io_error, Error};
+
 #[derive(Clone, Debug)]
 pub struct Stdio {
     pub stdin: String,
@@ -36,3 +48,95 @@ impl Stdio {
         self.stdin.is_empty() && self.stdout.is_empty() && self.stderr.is_empty()
     }
 }
+
+/// A set of stdio fifos allocated by the shim, named the way containerd's fifo package
+/// does: `<dir>/<id>-stdin`, `<dir>/<id>-stdout` and `<dir>/<id>-stderr`.
+///
+/// The directory holding the fifos is removed when the set is dropped.
+#[derive(Debug)]
+pub struct FifoSet {
+    pub dir: PathBuf,
+    pub stdio: Stdio,
+}
+
+impl FifoSet {
+    /// Create the fifos in a new directory under `root`, there is no stderr fifo with a terminal.
+    pub fn new_in_dir(root: impl AsRef<Path>, id: &str, terminal: bool) -> Result<Self> {
+        let dir = root.as_ref().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).map_err(io_error!(e, "create fifo dir {:?}", dir))?;
+        // build the set first, so that the directory is cleaned up on failure
+        let mut set = Self {
+            dir,
+            stdio: Stdio::new("", "", "", terminal),
+        };
+        set.stdio.stdin = set.create_fifo(id, "stdin")?;
+        set.stdio.stdout = set.create_fifo(id, "stdout")?;
+        if !terminal {
+            set.stdio.stderr = set.create_fifo(id, "stderr")?;
+        }
+        Ok(set)
+    }
+
+    fn create_fifo(&self, id: &str, name: &str) -> Result<String> {
+        let path = self.dir.join(format!("{}-{}", id, name));
+        create_fifo(&path)?;
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+impl Drop for FifoSet {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("failed to remove fifo dir {:?}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+/// Create a fifo with 0700 permissions, like containerd does with `O_CREAT`,
+/// an existing fifo at `path` is left untouched.
+pub fn create_fifo(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match mkfifo(path, Mode::S_IRWXU) {
+        Ok(_) => Ok(()),
+        Err(Errno::EEXIST) => {
+            let meta = fs::metadata(path).map_err(io_error!(e, "stat {:?}", path))?;
+            if meta.file_type().is_fifo() {
+                Ok(())
+            } else {
+                Err(Error::InvalidArgument(format!("{:?} is not a fifo", path)))
+            }
+        }
+        Err(e) => Err(Error::Nix(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_set() {
+        let root = tempfile::tempdir().unwrap();
+        let set = FifoSet::new_in_dir(root.path(), "id1", false).unwrap();
+        let dir = set.dir.clone();
+        for (path, name) in [
+            (&set.stdio.stdin, "id1-stdin"),
+            (&set.stdio.stdout, "id1-stdout"),
+            (&set.stdio.stderr, "id1-stderr"),
+        ] {
+            let path = Path::new(path);
+            assert_eq!(path, dir.join(name));
+            assert!(fs::metadata(path).unwrap().file_type().is_fifo());
+        }
+        // creating an existing fifo again is fine
+        create_fifo(&set.stdio.stdin).unwrap();
+        drop(set);
+        assert!(!dir.exists());
+
+        let set = FifoSet::new_in_dir(root.path(), "id2", true).unwrap();
+        assert!(set.stdio.stderr.is_empty());
+        assert!(set.stdio.terminal);
+    }
+}
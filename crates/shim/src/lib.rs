@@ -34,9 +34,13 @@
 
 use std::{
     collections::hash_map::DefaultHasher,
+    env,
     fs::File,
     hash::Hasher,
-    os::unix::{io::RawFd, net::UnixListener},
+    os::unix::{
+        io::{IntoRawFd, RawFd},
+        net::UnixListener,
+    },
     path::{Path, PathBuf},
 };
 
@@ -65,6 +69,7 @@ pub mod io;
 mod logger;
 pub mod monitor;
 pub mod mount;
+pub mod pty;
 mod reap;
 #[cfg(not(feature = "async"))]
 pub mod synchronous;
@@ -74,7 +79,10 @@ pub mod util;
 pub mod api {
     pub use super::protos::{
         api::Status,
-        shim::{oci::Options, shim::*},
+        shim::{
+            oci::{CheckpointOptions, Options},
+            shim::*,
+        },
         types::empty::Empty,
     };
 }
@@ -131,6 +139,8 @@ pub struct Config {
     pub no_reaper: bool,
     /// Disables setting the shim as a child subreaper.
     pub no_sub_reaper: bool,
+    /// Enables debug logging even if the shim is not started with `-debug`.
+    pub debug: bool,
 }
 
 /// Startup options received from containerd to start new shim instance.
@@ -157,6 +167,34 @@ pub struct StartOpts {
 /// through a file descriptor during forking, which is the fourth(3) file descriptor.
 const SOCKET_FD: RawFd = 3;
 
+/// The first file descriptor passed by socket activation, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Get the file descriptor of the socket the TTRPC server listens on.
+///
+/// This is a socket created at `socket` if the shim was started with `-socket`,
+/// the socket passed by socket activation (`LISTEN_FDS`/`LISTEN_PID`) if any,
+/// or the socket created by [`spawn`] and inherited from the start command.
+fn listener_fd(socket: &str) -> Result<RawFd> {
+    if !socket.is_empty() {
+        let listener = start_listener(socket).map_err(io_error!(e, "listen on {}", socket))?;
+        return Ok(listener.into_raw_fd());
+    }
+    let listen_pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    let listen_fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok());
+    if listen_pid == Some(std::process::id()) && listen_fds.unwrap_or_default() > 0 {
+        // do not pass the activation down to the children
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        return Ok(LISTEN_FDS_START);
+    }
+    Ok(SOCKET_FD)
+}
+
 #[cfg(target_os = "linux")]
 pub const SOCKET_ROOT: &str = "/run/containerd";
 
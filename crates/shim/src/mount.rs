@@ -21,13 +21,18 @@ use std::{
     ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not},
     path::Path,
 };
+#[cfg(target_os = "linux")]
+use std::{fs, process::Command, thread, time::Duration};
 
 use lazy_static::lazy_static;
 use log::error;
 #[cfg(target_os = "linux")]
-use nix::mount::{mount, MsFlags};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
 #[cfg(target_os = "linux")]
-use nix::unistd::{fork, ForkResult};
+use nix::{
+    errno::Errno,
+    unistd::{fork, ForkResult},
+};
 use regex::Regex;
 
 use crate::error::{Error, Result};
@@ -41,6 +46,9 @@ struct Flag {
 }
 
 const OVERLAY_LOWERDIR_PREFIX: &str = "lowerdir=";
+const OVERLAY_UPPERDIR_PREFIX: &str = "upperdir=";
+const OVERLAY_WORKDIR_PREFIX: &str = "workdir=";
+const FUSE_OVERLAYFS: &str = "fuse-overlayfs";
 
 #[cfg(target_os = "linux")]
 lazy_static! {
@@ -418,6 +426,82 @@ impl From<MountExitCode> for Result<()> {
     }
 }
 
+/// Validate the options of an overlay mount, creating the upperdir and the
+/// workdir if they do not exist yet.
+#[cfg(target_os = "linux")]
+pub fn prepare_overlay(options: &[String]) -> Result<()> {
+    let find = |prefix: &str| options.iter().find_map(|x| x.strip_prefix(prefix));
+    match find(OVERLAY_LOWERDIR_PREFIX) {
+        Some(lowerdir) if !lowerdir.is_empty() => {}
+        _ => {
+            return Err(Error::InvalidArgument(
+                "overlay mount requires lowerdir".to_string(),
+            ))
+        }
+    }
+    match (find(OVERLAY_UPPERDIR_PREFIX), find(OVERLAY_WORKDIR_PREFIX)) {
+        // a read-only overlay made of lowerdirs only
+        (None, None) => Ok(()),
+        (Some(upperdir), Some(workdir)) => {
+            for dir in [upperdir, workdir].iter() {
+                fs::create_dir_all(dir).map_err(io_error!(e, "create overlay dir {}", dir))?;
+            }
+            Ok(())
+        }
+        _ => Err(Error::InvalidArgument(
+            "overlay mount requires both upperdir and workdir".to_string(),
+        )),
+    }
+}
+
+/// A shim in a user namespace, such as a rootless one, may not be permitted to mount overlay,
+/// in which case the mount is retried with fuse-overlayfs.
+#[cfg(target_os = "linux")]
+pub(crate) fn use_fuse_overlayfs(fs_type: Option<&str>, err: Errno) -> bool {
+    fs_type == Some("overlay") && err == Errno::EPERM && in_user_namespace()
+}
+
+/// Whether the current process runs in a user namespace, that is its uid map is not the
+/// identity map of the initial namespace.
+#[cfg(target_os = "linux")]
+fn in_user_namespace() -> bool {
+    match std::fs::read_to_string("/proc/self/uid_map") {
+        Ok(uid_map) => !is_initial_uid_map(&uid_map),
+        // no user namespaces in this kernel
+        Err(_) => false,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_initial_uid_map(uid_map: &str) -> bool {
+    let lines: Vec<Vec<&str>> = uid_map
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .filter(|fields: &Vec<&str>| !fields.is_empty())
+        .collect();
+    lines.len() == 1 && lines[0] == ["0", "0", "4294967295"]
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn fuse_overlayfs_command(options: &[String], target: &Path) -> Command {
+    let mut cmd = Command::new(FUSE_OVERLAYFS);
+    cmd.arg("-o").arg(options.join(",")).arg(target);
+    cmd
+}
+
+#[cfg(not(feature = "async"))]
+#[cfg(target_os = "linux")]
+fn mount_fuse_overlayfs(options: &[String], target: &Path) -> Result<()> {
+    let s = monitor_subscribe(Topic::All)?;
+    let child = fuse_overlayfs_command(options, target)
+        .spawn()
+        .map_err(io_error!(e, "spawn {}", FUSE_OVERLAYFS))?;
+    match wait_pid(child.id() as i32, s) {
+        0 => Ok(()),
+        code => Err(other!("{} exited with {}", FUSE_OVERLAYFS, code)),
+    }
+}
+
 #[cfg(not(feature = "async"))]
 #[cfg(target_os = "linux")]
 pub fn mount_rootfs(
@@ -426,7 +510,10 @@ pub fn mount_rootfs(
     options: &[String],
     target: impl AsRef<Path>,
 ) -> Result<()> {
-    //TODO add helper to mount fuse
+    if fs_type == Some("overlay") {
+        prepare_overlay(options)?;
+    }
+    let origin_options = options;
     let max_size = page_size::get();
     // avoid hitting one page limit of mount argument buffer
     //
@@ -467,6 +554,11 @@ pub fn mount_rootfs(
     match unsafe { fork() } {
         Ok(ForkResult::Parent { child, .. }) => {
             let code: MountExitCode = wait_pid(i32::from(child), s).into();
+            if let MountExitCode::NixOtherErr(errno) = code {
+                if use_fuse_overlayfs(fs_type, Errno::from_i32(errno)) {
+                    return mount_fuse_overlayfs(origin_options, target.as_ref());
+                }
+            }
             code.into()
         }
         Ok(ForkResult::Child) => {
@@ -541,8 +633,10 @@ pub fn mount_rootfs(
     options: &[String],
     target: impl AsRef<Path>,
 ) -> Result<()> {
-    //TODO add helper to mount fuse
     //TODO compactLowerdirOption for overlay
+    if fs_type == Some("overlay") {
+        prepare_overlay(options)?;
+    }
     let mut flags: MsFlags = MsFlags::from_bits(0).unwrap();
     let mut data = Vec::new();
     options.iter().for_each(|x| {
@@ -611,11 +705,160 @@ pub fn mount_rootfs(
     Err(Error::Unimplemented("start".to_string()))
 }
 
+/// How many times an unmount is retried while the target is busy.
+#[cfg(target_os = "linux")]
+const UNMOUNT_RETRIES: u32 = 50;
+#[cfg(target_os = "linux")]
+const UNMOUNT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Unmount `target`, retrying for a while if it is still busy.
+///
+/// This sleeps between the retries, async callers run it on a blocking thread.
+#[cfg(target_os = "linux")]
+pub fn unmount(target: impl AsRef<Path>, flags: MntFlags) -> Result<()> {
+    unmount_with(target.as_ref(), UNMOUNT_RETRY_INTERVAL, |t| {
+        umount2(t, flags)
+    })
+}
+
+/// Unmount all the mounts stacked on `target`,
+/// nothing is done if `target` does not exist or is not a mount point.
+#[cfg(target_os = "linux")]
+pub fn unmount_all(target: impl AsRef<Path>, flags: MntFlags) -> Result<()> {
+    unmount_all_with(target.as_ref(), UNMOUNT_RETRY_INTERVAL, |t| {
+        umount2(t, flags)
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn unmount_with<F>(target: &Path, interval: Duration, mut umount: F) -> Result<()>
+where
+    F: FnMut(&Path) -> nix::Result<()>,
+{
+    let mut retries = 0;
+    loop {
+        match umount(target) {
+            Err(nix::errno::Errno::EBUSY) if retries < UNMOUNT_RETRIES => {
+                retries += 1;
+                thread::sleep(interval);
+            }
+            r => return r.map_err(mount_error!(e, "Unmount {}", target.display())),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unmount_all_with<F>(target: &Path, interval: Duration, mut umount: F) -> Result<()>
+where
+    F: FnMut(&Path) -> nix::Result<()>,
+{
+    loop {
+        match unmount_with(target, interval, &mut umount) {
+            Ok(_) => continue,
+            Err(Error::MountError {
+                err: nix::errno::Errno::EINVAL,
+                ..
+            })
+            | Err(Error::MountError {
+                err: nix::errno::Errno::ENOENT,
+                ..
+            }) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 #[cfg(test)]
 #[cfg(target_os = "linux")]
 mod tests {
     use super::*;
 
+    /// An unmount returning the `results` in turn, then EINVAL as the target is no mount point.
+    fn scripted_umount(results: Vec<nix::Result<()>>) -> impl FnMut(&Path) -> nix::Result<()> {
+        let mut results = results.into_iter();
+        move |_| results.next().unwrap_or(Err(Errno::EINVAL))
+    }
+
+    #[test]
+    fn test_unmount_retry() {
+        let target = Path::new("/mnt/test");
+        let calls = std::cell::Cell::new(0);
+        let mut umount = scripted_umount(vec![Err(Errno::EBUSY), Err(Errno::EBUSY), Ok(())]);
+        let res = unmount_with(target, Duration::ZERO, |t| {
+            calls.set(calls.get() + 1);
+            umount(t)
+        });
+        assert!(res.is_ok());
+        assert_eq!(calls.get(), 3);
+
+        // gives up once the retries are exhausted
+        calls.set(0);
+        let res = unmount_with(target, Duration::ZERO, |_| {
+            calls.set(calls.get() + 1);
+            Err(Errno::EBUSY)
+        });
+        assert!(matches!(
+            res,
+            Err(Error::MountError {
+                err: Errno::EBUSY,
+                ..
+            })
+        ));
+        assert_eq!(calls.get(), UNMOUNT_RETRIES + 1);
+
+        // other errors are not retried
+        calls.set(0);
+        let res = unmount_with(target, Duration::ZERO, |_| {
+            calls.set(calls.get() + 1);
+            Err(Errno::EPERM)
+        });
+        assert!(res.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_unmount_all() {
+        let target = Path::new("/mnt/test");
+        let calls = std::cell::Cell::new(0);
+        let mut umount = scripted_umount(vec![Ok(()), Err(Errno::EBUSY), Ok(())]);
+        let res = unmount_all_with(target, Duration::ZERO, |t| {
+            calls.set(calls.get() + 1);
+            umount(t)
+        });
+        assert!(res.is_ok());
+        // two stacked mounts, one busy retry and the final EINVAL
+        assert_eq!(calls.get(), 4);
+
+        let res = unmount_all_with(
+            target,
+            Duration::ZERO,
+            scripted_umount(vec![Err(Errno::ENOENT)]),
+        );
+        assert!(res.is_ok());
+
+        let res = unmount_all_with(
+            target,
+            Duration::ZERO,
+            scripted_umount(vec![Ok(()), Err(Errno::EPERM)]),
+        );
+        assert!(matches!(
+            res,
+            Err(Error::MountError {
+                err: Errno::EPERM,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_initial_uid_map() {
+        assert!(is_initial_uid_map("         0          0 4294967295\n"));
+        assert!(!is_initial_uid_map("         0       1000          1\n"));
+        assert!(!is_initial_uid_map("0 1000 1\n1 100000 65536\n"));
+        assert!(!is_initial_uid_map(""));
+    }
+
     #[test]
     fn test_trim_flawed_dir() {
         let mut tcases: Vec<(&str, String)> = Vec::new();
@@ -717,4 +960,28 @@ mod tests {
             assert_eq!(options, expected_options);
         }
     }
+
+    #[test]
+    fn test_prepare_overlay() {
+        let tmp = tempfile::tempdir().unwrap();
+        let upperdir = tmp.path().join("upper");
+        let workdir = tmp.path().join("work");
+
+        assert!(prepare_overlay(&["upperdir=/a".to_string()]).is_err());
+        assert!(prepare_overlay(&[
+            "lowerdir=/a".to_string(),
+            format!("upperdir={}", upperdir.display()),
+        ])
+        .is_err());
+        assert!(prepare_overlay(&["lowerdir=/a:/b".to_string()]).is_ok());
+
+        prepare_overlay(&[
+            "lowerdir=/a".to_string(),
+            format!("upperdir={}", upperdir.display()),
+            format!("workdir={}", workdir.display()),
+        ])
+        .unwrap();
+        assert!(upperdir.is_dir());
+        assert!(workdir.is_dir());
+    }
 }
@@ -0,0 +1,448 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers for the master side of the pseudo terminal of a process.
+//!
+//! The window size ioctls use the request numbers libc defines for each OS,
+//! so they work on macOS and FreeBSD as well as on Linux.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+#[cfg(feature = "async")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "async")]
+use log::warn;
+#[cfg(not(target_os = "linux"))]
+use nix::pty::ptsname;
+#[cfg(target_os = "linux")]
+use nix::pty::ptsname_r;
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    ioctl_read_bad,
+    pty::{grantpt, posix_openpt, unlockpt, PtyMaster},
+    sys::termios::{tcsetattr, SetArg, Termios},
+    unistd::ttyname,
+};
+#[cfg(feature = "async")]
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+
+use crate::{ioctl_set_winsz, Error, Result};
+
+ioctl_read_bad!(ioctl_get_winsz, libc::TIOCGWINSZ, libc::winsize);
+
+/// Return the window size of the terminal of `fd`.
+pub fn get_size(fd: RawFd) -> Result<libc::winsize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // Safe as the ioctl only writes the winsize
+    unsafe { ioctl_get_winsz(fd, &mut size) }?;
+    Ok(size)
+}
+
+/// Set the window size of the terminal of `fd`.
+pub fn resize(fd: RawFd, size: &libc::winsize) -> Result<()> {
+    // Safe as the ioctl only reads the winsize
+    unsafe { ioctl_set_winsz(fd, size) }?;
+    Ok(())
+}
+
+/// Settings of a new pty pair.
+///
+/// The window size and the termios are set on the slave as soon as it is opened,
+/// before it is handed out.
+#[derive(Clone, Default)]
+pub struct PtyOptions {
+    pub winsize: Option<libc::winsize>,
+    pub termios: Option<Termios>,
+    /// Open both fds with `O_CLOEXEC`.
+    pub cloexec: bool,
+    /// Set `O_NONBLOCK` on the master.
+    pub nonblocking: bool,
+}
+
+/// Slave side of a pty pair, with the path it can be opened again by.
+#[derive(Debug)]
+pub struct Slave {
+    file: File,
+    path: PathBuf,
+}
+
+impl Slave {
+    fn new(file: File) -> Result<Self> {
+        let path = ttyname(file.as_raw_fd())?;
+        Ok(Self { file, path })
+    }
+
+    /// Return the path of the slave, e.g. `/dev/pts/3`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the slave again, closed on exec and without making it the controlling
+    /// terminal of the shim.
+    pub fn open_cloexec(&self) -> Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+            .open(&self.path)
+            .map_err(io_error!(e, "open pty slave {}", self.path.display()))
+    }
+
+    /// Make the slave the controlling terminal and the stdio of the calling process,
+    /// as login_tty(3) does.
+    ///
+    /// # Safety
+    ///
+    /// Meant to run in a forked child before exec, e.g. in `CommandExt::pre_exec`,
+    /// it only makes async-signal-safe calls.
+    pub unsafe fn login_tty(&self) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        if libc::setsid() < 0 || libc::ioctl(fd, libc::TIOCSCTTY as _, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for target in 0..3 {
+            if libc::dup2(fd, target) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // the slave is only kept as the stdio of the process
+        if fd > 2 {
+            libc::close(fd);
+        }
+        Ok(())
+    }
+
+    /// Return the stdin, stdout and stderr of a command using the slave, for either
+    /// `std::process::Command` or `tokio::process::Command`.
+    pub fn into_stdio(self) -> Result<(Stdio, Stdio, Stdio)> {
+        let dup = |f: &File| f.try_clone().map_err(io_error!(e, "dup pty slave"));
+        let stdin = dup(&self.file)?;
+        let stdout = dup(&self.file)?;
+        Ok((stdin.into(), stdout.into(), self.file.into()))
+    }
+
+    pub fn into_file(self) -> File {
+        self.file
+    }
+}
+
+impl AsRawFd for Slave {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Open a pty pair with the default settings, returning its master and slave.
+pub fn new_pty_pair() -> Result<(File, Slave)> {
+    new_pty_pair_with(&PtyOptions::default())
+}
+
+/// Open a pty pair with `opts`, returning its master and slave.
+pub fn new_pty_pair_with(opts: &PtyOptions) -> Result<(File, Slave)> {
+    // with cloexec the fds are opened closed on exec, so no fork of another thread inherits them
+    let mut flags = OFlag::O_RDWR | OFlag::O_NOCTTY;
+    if opts.cloexec && cfg!(target_os = "linux") {
+        flags |= OFlag::O_CLOEXEC;
+    }
+    let pty = posix_openpt(flags)?;
+    grantpt(&pty)?;
+    unlockpt(&pty)?;
+    let slave_path = slave_name(&pty)?;
+    // Safe as posix_openpt just opened it for us only
+    let master = unsafe { File::from_raw_fd(pty.into_raw_fd()) };
+    if opts.cloexec && !cfg!(target_os = "linux") {
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+
+    let mut custom_flags = libc::O_NOCTTY;
+    if opts.cloexec {
+        custom_flags |= libc::O_CLOEXEC;
+    }
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(custom_flags)
+        .open(&slave_path)
+        .map_err(io_error!(e, "open pty slave {}", slave_path))?;
+    if let Some(termios) = opts.termios.as_ref() {
+        tcsetattr(slave.as_raw_fd(), SetArg::TCSANOW, termios)?;
+    }
+    if let Some(winsize) = opts.winsize.as_ref() {
+        resize(slave.as_raw_fd(), winsize)?;
+    }
+
+    if opts.nonblocking {
+        let flags = OFlag::from_bits_truncate(fcntl(master.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(
+            master.as_raw_fd(),
+            FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+        )?;
+    }
+    Ok((master, Slave::new(slave)?))
+}
+
+#[cfg(target_os = "linux")]
+fn slave_name(master: &PtyMaster) -> Result<String> {
+    Ok(ptsname_r(master)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn slave_name(master: &PtyMaster) -> Result<String> {
+    // Safe as the name is copied before another pty is opened by the shim
+    Ok(unsafe { ptsname(master) }?)
+}
+
+/// Copy the window size of the terminal `source` to the pty `master` now and
+/// on every SIGWINCH received by the process, until the returned task is aborted.
+///
+/// This is meant for interactive attach, with the terminal of the user as `source`.
+#[cfg(feature = "async")]
+pub fn resize_on_sigwinch<M, S>(master: M, source: S) -> Result<JoinHandle<()>>
+where
+    M: AsRawFd + Send + 'static,
+    S: AsRawFd + Send + 'static,
+{
+    let mut winch =
+        signal(SignalKind::window_change()).map_err(io_error!(e, "listen for SIGWINCH"))?;
+    Ok(tokio::spawn(async move {
+        loop {
+            let res =
+                get_size(source.as_raw_fd()).and_then(|size| resize(master.as_raw_fd(), &size));
+            if let Err(e) = res {
+                warn!("failed to forward the window size: {}", e);
+            }
+            if winch.recv().await.is_none() {
+                return;
+            }
+        }
+    }))
+}
+
+type HangupCallback = Box<dyn FnOnce() + Send + Sync>;
+
+/// Reader of a pty master which ends at the hangup of the terminal.
+///
+/// Once every fd of the slave side is closed, e.g. when the process exited, reads of the
+/// master fail with `EIO` instead of returning EOF. They are turned into EOF here, running the
+/// hangup callback first, so that copying a console behaves as copying a pipe.
+pub struct MasterReader<R> {
+    inner: R,
+    hung_up: bool,
+    on_hangup: Option<HangupCallback>,
+}
+
+impl<R> MasterReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hung_up: false,
+            on_hangup: None,
+        }
+    }
+
+    /// Run `f` once, when the terminal hangs up.
+    pub fn on_hangup<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce() + Send + Sync + 'static,
+    {
+        self.on_hangup = Some(Box::new(f));
+        self
+    }
+
+    /// Return whether the terminal hung up.
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Map the result of a read of the master, returning `None` for an EOF due to a hangup.
+    fn check<T>(&mut self, res: io::Result<T>) -> Option<io::Result<T>> {
+        match res {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => {
+                if !self.hung_up {
+                    self.hung_up = true;
+                    if let Some(f) = self.on_hangup.take() {
+                        f();
+                    }
+                }
+                None
+            }
+            res => Some(res),
+        }
+    }
+}
+
+impl<R: Read> Read for MasterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.hung_up {
+            return Ok(0);
+        }
+        let res = self.inner.read(buf);
+        self.check(res).unwrap_or(Ok(0))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> AsyncRead for MasterReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.hung_up {
+            return Poll::Ready(Ok(()));
+        }
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(res) => Poll::Ready(this.check(res).unwrap_or(Ok(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        os::unix::process::CommandExt,
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use nix::pty::openpty;
+
+    use super::*;
+
+    #[test]
+    fn test_resize() {
+        let pty = openpty(None, None).unwrap();
+        let size = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        resize(pty.master, &size).expect("resize failed");
+        let got = get_size(pty.slave).expect("get size failed");
+        assert_eq!((got.ws_row, got.ws_col), (24, 80));
+        nix::unistd::close(pty.master).unwrap();
+        nix::unistd::close(pty.slave).unwrap();
+    }
+
+    #[test]
+    fn test_new_pty_pair_with() {
+        let opts = PtyOptions {
+            winsize: Some(libc::winsize {
+                ws_row: 40,
+                ws_col: 120,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            }),
+            cloexec: true,
+            nonblocking: true,
+            ..Default::default()
+        };
+        let (master, slave) = new_pty_pair_with(&opts).expect("failed to open pty pair");
+        let size = get_size(slave.as_raw_fd()).unwrap();
+        assert_eq!((size.ws_row, size.ws_col), (40, 120));
+
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()].iter() {
+            let fd_flags = FdFlag::from_bits_truncate(fcntl(*fd, FcntlArg::F_GETFD).unwrap());
+            assert!(fd_flags.contains(FdFlag::FD_CLOEXEC));
+        }
+        let flags =
+            OFlag::from_bits_truncate(fcntl(master.as_raw_fd(), FcntlArg::F_GETFL).unwrap());
+        assert!(flags.contains(OFlag::O_NONBLOCK));
+    }
+
+    // EIO at hangup and /dev/pts paths are linux specific
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_slave() {
+        let (master, slave) = new_pty_pair().expect("failed to open pty pair");
+        assert!(slave.path().starts_with("/dev/pts"));
+        slave.open_cloexec().expect("failed to open slave again");
+
+        let path = slave.path().to_path_buf();
+        let slave = Arc::new(slave);
+        let mut cmd = Command::new("tty");
+        let login = slave.clone();
+        // Safe as login_tty is async-signal-safe
+        unsafe {
+            cmd.pre_exec(move || login.login_tty());
+        }
+        let mut child = cmd.spawn().expect("failed to spawn tty");
+        // the master hangs up once the child is the only one holding the slave
+        drop(cmd);
+        drop(slave);
+        let mut out = String::new();
+        MasterReader::new(master)
+            .read_to_string(&mut out)
+            .expect("failed to read master");
+        child.wait().unwrap();
+        assert_eq!(out.trim_end(), path.display().to_string());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_master_reader_hangup() {
+        let pty = openpty(None, None).unwrap();
+        let master = unsafe { File::from_raw_fd(pty.master) };
+        let mut slave = unsafe { File::from_raw_fd(pty.slave) };
+        slave.write_all(b"hello\n").unwrap();
+        drop(slave);
+
+        let hung_up = Arc::new(AtomicBool::new(false));
+        let flag = hung_up.clone();
+        let mut reader = MasterReader::new(master).on_hangup(move || {
+            flag.store(true, Ordering::SeqCst);
+        });
+        let mut out = String::new();
+        reader
+            .read_to_string(&mut out)
+            .expect("read of master failed");
+        assert_eq!(out.trim_end(), "hello");
+        assert!(reader.is_hung_up());
+        assert!(hung_up.load(Ordering::SeqCst));
+    }
+}
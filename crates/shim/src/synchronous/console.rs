@@ -16,14 +16,17 @@
 
 use std::{
     os::unix::net::{UnixListener, UnixStream},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 use log::warn;
+#[cfg(target_os = "linux")]
 use uuid::Uuid;
 
+#[cfg(target_os = "linux")]
+use crate::util::bind_abstract;
 use crate::{
-    util::{mkdir, xdg_runtime_dir},
+    util::{console_socket_path, mkdir},
     Error, Result,
 };
 
@@ -35,9 +38,10 @@ pub struct ConsoleSocket {
 
 impl ConsoleSocket {
     pub fn new() -> Result<ConsoleSocket> {
-        let dir = format!("{}/pty{}", xdg_runtime_dir(), Uuid::new_v4());
-        mkdir(&dir, 0o711)?;
-        let file_name = Path::new(&dir).join("pty.sock");
+        let file_name = console_socket_path()?;
+        if let Some(dir) = file_name.parent() {
+            mkdir(dir, 0o711)?;
+        }
         let listener = UnixListener::bind(&file_name).map_err(io_error!(
             e,
             "bind socket {}",
@@ -50,6 +54,19 @@ impl ConsoleSocket {
         })
     }
 
+    /// Listen on a socket in the Linux abstract namespace instead of the filesystem,
+    /// its path is the `@` prefixed name that runc understands.
+    #[cfg(target_os = "linux")]
+    pub fn new_abstract() -> Result<ConsoleSocket> {
+        let name = format!("pty{}", Uuid::new_v4());
+        let listener = bind_abstract(&name)?;
+        Ok(ConsoleSocket {
+            listener,
+            path: PathBuf::from(format!("@{}", name)),
+            rmdir: false,
+        })
+    }
+
     pub fn accept(&self) -> std::io::Result<UnixStream> {
         let (stream, _addr) = self.listener.accept()?;
         Ok(stream)
@@ -58,7 +58,7 @@ use util::{read_address, write_address};
 
 use crate::{
     api::DeleteResponse,
-    args, logger, parse_sockaddr,
+    args, listener_fd, logger, parse_sockaddr,
     protos::{
         protobuf::Message,
         shim::shim_ttrpc::{create_task, Task},
@@ -192,6 +192,7 @@ where
         "delete" => {
             std::thread::spawn(move || handle_signals(signals));
             let response = shim.delete_shim()?;
+            remove_stale_address();
             let stdout = std::io::stdout();
             let mut locked = stdout.lock();
             response.write_to_writer(&mut locked)?;
@@ -200,14 +201,14 @@ where
         }
         _ => {
             if !config.no_setup_logger {
-                logger::init(flags.debug)?;
+                logger::init(flags.debug || config.debug)?;
             }
 
             let publisher = publisher::RemotePublisher::new(&ttrpc_address)?;
             let task = shim.create_task_service(publisher);
             let task_service = create_task(Arc::new(Box::new(task)));
             let mut server = Server::new().register_service(task_service);
-            server = server.add_listener(SOCKET_FD)?;
+            server = server.add_listener(listener_fd(&flags.socket)?)?;
             server.start()?;
 
             info!("Shim successfully started, waiting for exit signal...");
@@ -221,6 +222,9 @@ where
             // socket might be leaking.
             let address = read_address()?;
             remove_socket_silently(&address);
+            if !flags.socket.is_empty() {
+                remove_socket_silently(&flags.socket);
+            }
             Ok(())
         }
     }
@@ -294,6 +298,21 @@ fn remove_socket_silently(address: &str) {
     remove_socket(address).unwrap_or_else(|e| warn!("failed to remove file {} {:?}", address, e))
 }
 
+/// Remove the shim socket recorded in the bundle's address file if nothing is
+/// listening on it anymore, together with the address file itself.
+fn remove_stale_address() {
+    if let Ok(address) = read_address() {
+        if Client::connect(&address).is_err() {
+            remove_socket_silently(&address);
+        }
+    }
+    fs::remove_file("address").unwrap_or_else(|e| {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("failed to remove address file {:?}", e)
+        }
+    });
+}
+
 fn remove_socket(address: &str) -> Result<()> {
     let path = parse_sockaddr(address);
     if let Ok(md) = Path::new(path).metadata() {
@@ -17,10 +17,13 @@
 use std::{
     env,
     os::unix::io::RawFd,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use oci_spec::runtime::Spec;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 
 #[cfg(feature = "async")]
@@ -29,7 +32,7 @@ pub use crate::asynchronous::util::*;
 pub use crate::synchronous::util::*;
 use crate::{
     api::Options,
-    error::Result,
+    error::{Error, Result},
     protos::protobuf::{
         well_known_types::{any::Any, timestamp::Timestamp},
         MessageDyn,
@@ -134,6 +137,30 @@ pub fn connect(address: impl AsRef<str>) -> Result<RawFd> {
     Ok(fd)
 }
 
+/// Bind a listening unix socket in the Linux abstract namespace, no file is created for it.
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_abstract(name: &str) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::io::FromRawFd;
+
+    use nix::{sys::socket::*, unistd::close};
+
+    let unix_addr = UnixAddr::new_abstract(name.as_bytes())?;
+    let fd = socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    bind(fd, &unix_addr)
+        .and_then(|_| listen(fd, 10))
+        .map_err(|e| {
+            let _ = close(fd);
+            e
+        })?;
+
+    Ok(unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) })
+}
+
 pub fn timestamp() -> Result<Timestamp> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
 
@@ -173,6 +200,63 @@ pub(crate) fn xdg_runtime_dir() -> String {
         .unwrap_or_else(|_| env::temp_dir().to_str().unwrap_or(".").to_string())
 }
 
+/// Longest path a unix socket can be bound to, `sun_path` keeps its last byte for the nul.
+pub const MAX_SOCKET_PATH_LEN: usize = 107;
+
+/// Returns the path of a new console socket under [xdg_runtime_dir], or under the temp dir
+/// when the former does not fit in [MAX_SOCKET_PATH_LEN].
+pub(crate) fn console_socket_path() -> Result<PathBuf> {
+    let name = format!("pty{}", uuid::Uuid::new_v4());
+    let roots = [
+        xdg_runtime_dir(),
+        env::temp_dir().to_str().unwrap_or(".").to_string(),
+    ];
+    for root in roots.iter() {
+        let path = Path::new(root).join(&name).join("pty.sock");
+        if path.as_os_str().len() <= MAX_SOCKET_PATH_LEN {
+            return Ok(path);
+        }
+    }
+    Err(Error::InvalidArgument(format!(
+        "console socket path under {} is longer than {} bytes",
+        roots[1], MAX_SOCKET_PATH_LEN
+    )))
+}
+
+/// Apply the changes from `spec` to `changed` on `raw`, the JSON `spec` was parsed from,
+/// keeping the fields of `raw` unknown to oci-spec, which a round trip through [Spec] drops.
+pub fn apply_spec_changes(raw: &mut Value, spec: &Spec, changed: &Spec) -> Result<()> {
+    let known = serde_json::to_value(spec)?;
+    let changed = serde_json::to_value(changed)?;
+    merge_known_fields(raw, &known, changed);
+    Ok(())
+}
+
+fn merge_known_fields(raw: &mut Value, known: &Value, changed: Value) {
+    match (raw, known, changed) {
+        (Value::Object(raw), Value::Object(known), Value::Object(changed)) => {
+            // the known fields missing from the change were removed by it
+            let removed: Vec<String> = known
+                .keys()
+                .filter(|k| !changed.contains_key(*k))
+                .cloned()
+                .collect();
+            for k in removed {
+                raw.remove(&k);
+            }
+            for (k, v) in changed {
+                match (raw.get_mut(&k), known.get(&k)) {
+                    (Some(r), Some(known)) => merge_known_fields(r, known, v),
+                    _ => {
+                        raw.insert(k, v);
+                    }
+                }
+            }
+        }
+        (raw, _, changed) => *raw = changed,
+    }
+}
+
 pub trait IntoOption
 where
     Self: Sized,
@@ -207,8 +291,48 @@ impl AsOption for str {
 
 #[cfg(test)]
 mod tests {
+    use oci_spec::runtime::ProcessBuilder;
+
     use super::*;
 
+    #[test]
+    fn test_apply_spec_changes() {
+        let mut raw = serde_json::to_value(&Spec::default()).unwrap();
+        raw["x-unknown"] = serde_json::json!({"a": 1});
+        raw["process"]["x-unknown"] = serde_json::json!(true);
+        let spec: Spec = serde_json::from_value(raw.clone()).unwrap();
+
+        let mut changed = spec.clone();
+        changed.set_hostname(None);
+        changed.set_annotations(Some(
+            [("policy".to_string(), "checked".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        ));
+        let process = ProcessBuilder::default()
+            .args(vec!["sleep".to_string(), "1".to_string()])
+            .build()
+            .unwrap();
+        changed.set_process(Some(process));
+        apply_spec_changes(&mut raw, &spec, &changed).unwrap();
+
+        assert_eq!(raw["x-unknown"]["a"], 1);
+        assert_eq!(raw["process"]["x-unknown"], true);
+        assert_eq!(raw["process"]["args"], serde_json::json!(["sleep", "1"]));
+        assert_eq!(raw["annotations"]["policy"], "checked");
+        assert!(raw.get("hostname").is_none());
+        let parsed: Spec = serde_json::from_value(raw).unwrap();
+        assert_eq!(parsed, changed);
+    }
+
+    #[test]
+    fn test_console_socket_path() {
+        let path = console_socket_path().unwrap();
+        assert!(path.as_os_str().len() <= MAX_SOCKET_PATH_LEN);
+        assert!(path.ends_with("pty.sock"));
+    }
+
     #[test]
     fn test_timestamp() {
         let ts = timestamp().unwrap();